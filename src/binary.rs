@@ -0,0 +1,634 @@
+use super::*;
+use std::fmt;
+use std::io::{self, Read};
+
+// Binary format
+//
+// All integers are LEB128 encoded. After the magic bytes and the version, the names of all port
+// types are listed once and referred to by index. Regions and nodes are written in the order of
+// their ids, including nodes which have been removed from their region, so that ids are the same
+// once loaded. The node and region lists of the `ListPool`s are rebuilt in the same order.
+//
+// The data of a node kind is either nothing, its payload, or the bytes from its `Encode` impl.
+
+const MAGIC: &[u8] = b"RVSDG\0";
+const VERSION: u32 = 1;
+
+const NO_DATA: u8 = 0;
+const PAYLOAD: u8 = 1;
+const ENCODED: u8 = 2;
+
+/// Errors from loading a binary translation unit, see [`TranslationUnitContext::load`]
+#[derive(Debug)]
+pub enum BinaryError {
+    Io(io::Error),
+    /// The input doesn't start with the magic bytes of the format
+    NotRvsdg,
+    /// The input was saved by an incompatible version of the format
+    UnsupportedVersion(u32),
+    /// The input ends early, refers to nodes, regions or ports which don't exist, or describes a
+    /// graph whose nodes, regions and edges don't fit together
+    Corrupt,
+    /// There's no factory or decoder registered for the kind
+    UnknownKind(String),
+    /// The factory or decoder of the kind didn't accept the data
+    InvalidPayload(String),
+    /// There's no type registered with the name
+    UnknownType(String),
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryError::Io(err) => write!(f, "{err}"),
+            BinaryError::NotRvsdg => write!(f, "not a binary rvsdg"),
+            BinaryError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "version {version} of the binary format isn't supported, expected {VERSION}"
+                )
+            }
+            BinaryError::Corrupt => write!(f, "the binary rvsdg is corrupt"),
+            BinaryError::UnknownKind(kind) => write!(f, "no node kind is registered as {kind}"),
+            BinaryError::InvalidPayload(kind) => write!(f, "invalid data for a {kind} node"),
+            BinaryError::UnknownType(name) => write!(f, "no type is registered as {name}"),
+        }
+    }
+}
+
+impl std::error::Error for BinaryError {}
+
+impl From<io::Error> for BinaryError {
+    fn from(err: io::Error) -> Self {
+        BinaryError::Io(err)
+    }
+}
+
+/// Append a LEB128 encoded integer, for use in [`Encode`](crate::nodes::Encode) impls
+pub fn write_u32(bytes: &mut Vec<u8>, mut n: u32) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_u32(bytes, data.len() as u32);
+    bytes.extend_from_slice(data);
+}
+
+/// Reads the data written by an [`Encode`](crate::nodes::Encode) impl
+pub struct Bytes<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Bytes { bytes }
+    }
+
+    /// Read an integer written by [`write_u32`]
+    pub fn u32(&mut self) -> Option<u32> {
+        let mut n = 0u32;
+        for shift in (0..35).step_by(7) {
+            let (&byte, rest) = self.bytes.split_first()?;
+            self.bytes = rest;
+            n |= u32::from(byte & 0x7f).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(n);
+            }
+        }
+        None
+    }
+
+    pub fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+        Some(byte)
+    }
+
+    fn slice(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let slice = self.bytes.get(..len)?;
+        self.bytes = &self.bytes[len..];
+        Some(slice)
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        std::str::from_utf8(self.slice()?).ok()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+// The lambdas of a recenv are ordered by their node for the same bytes each time
+impl Encode for RecEnv {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let mut lambdas: Vec<_> = self.lambdas.iter().collect();
+        lambdas.sort();
+
+        write_u32(bytes, lambdas.len() as u32);
+        for (lambda, (argument, output)) in lambdas {
+            write_u32(bytes, lambda.as_u32());
+            write_u32(bytes, argument.as_u32());
+            write_u32(bytes, output.as_u32());
+        }
+    }
+}
+
+impl Encode for TranslationUnit {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        write_u32(bytes, self.region.as_u32());
+    }
+}
+
+pub(crate) fn decode_recenv(bytes: &[u8]) -> Option<Box<dyn NodeKind + Send + Sync>> {
+    let mut bytes = Bytes::new(bytes);
+    let lambdas = (0..bytes.u32()?)
+        .map(|_| {
+            let lambda = id::AnyNode::from_u32(bytes.u32()?);
+            let argument = id::Argument::from_u32(bytes.u32()?);
+            let output = id::Output::from_u32(bytes.u32()?);
+            Some((lambda, (argument, output)))
+        })
+        .collect::<Option<_>>()?;
+    Some(Box::new(RecEnv { lambdas }))
+}
+
+pub(crate) fn decode_translation_unit(bytes: &[u8]) -> Option<Box<dyn NodeKind + Send + Sync>> {
+    let region = id::Region::from_u32(Bytes::new(bytes).u32()?);
+    Some(Box::new(TranslationUnit { region }))
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+    types: Vec<String>,
+    type_indices: HashMap<String, u32>,
+}
+
+impl Encoder {
+    fn u32(&mut self, n: u32) {
+        write_u32(&mut self.bytes, n);
+    }
+
+    fn string(&mut self, s: &str) {
+        write_bytes(&mut self.bytes, s.as_bytes());
+    }
+
+    // Either 0 for no type or the index of the type plus one
+    fn port_type(&mut self, ty: &Option<Type>) {
+        let n = match ty {
+            None => 0,
+            Some(ty) => {
+                let name = ty.to_string();
+                let next = self.types.len() as u32;
+                let index = *self.type_indices.entry(name.clone()).or_insert_with(|| {
+                    self.types.push(name);
+                    next
+                });
+                index + 1
+            }
+        };
+        self.u32(n);
+    }
+
+    fn port_types(&mut self, types: &[Option<Type>]) {
+        for ty in types {
+            self.port_type(ty);
+        }
+    }
+
+    fn origin(&mut self, origin: Origin) {
+        match origin {
+            Origin::Output(node, output) => {
+                self.bytes.push(0);
+                self.u32(node.as_u32());
+                self.u32(output.as_u32());
+            }
+            Origin::Argument(region, argument) => {
+                self.bytes.push(1);
+                self.u32(region.as_u32());
+                self.u32(argument.as_u32());
+            }
+        }
+    }
+
+    fn user(&mut self, user: User) {
+        match user {
+            User::Input(node, input) => {
+                self.bytes.push(0);
+                self.u32(node.as_u32());
+                self.u32(input.as_u32());
+            }
+            User::Result(region, result) => {
+                self.bytes.push(1);
+                self.u32(region.as_u32());
+                self.u32(result.as_u32());
+            }
+        }
+    }
+}
+
+struct Decoder<'a, 'k> {
+    bytes: Bytes<'a>,
+    kinds: &'k KindRegistry,
+    types: Vec<Type>,
+    // Ids are checked against these once everything is read
+    nodes: u32,
+    regions: u32,
+}
+
+impl<'a> Decoder<'a, '_> {
+    fn u32(&mut self) -> std::result::Result<u32, BinaryError> {
+        self.bytes.u32().ok_or(BinaryError::Corrupt)
+    }
+
+    fn byte(&mut self) -> std::result::Result<u8, BinaryError> {
+        self.bytes.byte().ok_or(BinaryError::Corrupt)
+    }
+
+    fn string(&mut self) -> std::result::Result<&'a str, BinaryError> {
+        self.bytes.string().ok_or(BinaryError::Corrupt)
+    }
+
+    fn node(&mut self) -> std::result::Result<id::AnyNode, BinaryError> {
+        let n = self.u32()?;
+        match n < self.nodes {
+            true => Ok(id::AnyNode::from_u32(n)),
+            false => Err(BinaryError::Corrupt),
+        }
+    }
+
+    fn region(&mut self) -> std::result::Result<id::Region, BinaryError> {
+        let n = self.u32()?;
+        match n < self.regions {
+            true => Ok(id::Region::from_u32(n)),
+            false => Err(BinaryError::Corrupt),
+        }
+    }
+
+    fn port_types(&mut self, count: u32) -> std::result::Result<Vec<Option<Type>>, BinaryError> {
+        (0..count)
+            .map(|_| match self.u32()? {
+                0 => Ok(None),
+                n => self
+                    .types
+                    .get(n as usize - 1)
+                    .cloned()
+                    .map(Some)
+                    .ok_or(BinaryError::Corrupt),
+            })
+            .collect()
+    }
+
+    fn origin(&mut self) -> std::result::Result<Origin, BinaryError> {
+        match self.byte()? {
+            0 => Ok(Origin::Output(
+                self.node()?,
+                id::Output::from_u32(self.u32()?),
+            )),
+            1 => Ok(Origin::Argument(
+                self.region()?,
+                id::Argument::from_u32(self.u32()?),
+            )),
+            _ => Err(BinaryError::Corrupt),
+        }
+    }
+
+    fn user(&mut self) -> std::result::Result<User, BinaryError> {
+        match self.byte()? {
+            0 => Ok(User::Input(self.node()?, id::Input::from_u32(self.u32()?))),
+            1 => Ok(User::Result(
+                self.region()?,
+                id::Result::from_u32(self.u32()?),
+            )),
+            _ => Err(BinaryError::Corrupt),
+        }
+    }
+
+    fn kind(&mut self) -> std::result::Result<Box<dyn NodeKind + Send + Sync>, BinaryError> {
+        let kind = self.string()?;
+        let created = match self.byte()? {
+            ENCODED => {
+                let data = self.bytes.slice().ok_or(BinaryError::Corrupt)?;
+                let decoder = self
+                    .kinds
+                    .decoders
+                    .get(kind)
+                    .ok_or_else(|| BinaryError::UnknownKind(kind.to_string()))?;
+                decoder(data)
+            }
+            data @ (NO_DATA | PAYLOAD) => {
+                let payload = match data {
+                    PAYLOAD => Some(self.string()?),
+                    _ => None,
+                };
+                let factory = self
+                    .kinds
+                    .kinds
+                    .get(kind)
+                    .ok_or_else(|| BinaryError::UnknownKind(kind.to_string()))?;
+                factory(payload)
+            }
+            _ => return Err(BinaryError::Corrupt),
+        };
+
+        created.ok_or_else(|| BinaryError::InvalidPayload(kind.to_string()))
+    }
+}
+
+impl TranslationUnitContext {
+    /// Write the translation unit in a compact binary format, read by [`Self::load`].
+    ///
+    /// Ids of nodes and regions are kept. Kinds which implement [`Encode`](crate::nodes::Encode)
+    /// are written through it, and other kinds through their payload.
+    pub fn save(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let mut body = Encoder {
+            bytes: vec![],
+            types: vec![],
+            type_indices: HashMap::new(),
+        };
+
+        body.u32(self.regions.len() as u32);
+        body.u32(self.nodes.len() as u32);
+
+        for region in self.regions.values() {
+            match region.container_node {
+                None => body.u32(0),
+                Some(node) => body.u32(node.as_u32() + 1),
+            }
+            body.u32(region.arguments);
+            body.u32(region.results);
+            body.port_types(&region.argument_types);
+            body.port_types(&region.result_types);
+
+            let nodes = region.nodes.as_slice(&self.node_id_pool);
+            body.u32(nodes.len() as u32);
+            for node in nodes {
+                body.u32(node.as_u32());
+            }
+
+            body.u32(region.edges.len() as u32);
+            for edge in &region.edges {
+                body.origin(edge.origin);
+                body.user(edge.user);
+            }
+        }
+
+        for (id, node) in self.nodes.iter() {
+            body.u32(node.region.as_u32());
+            body.u32(node.inputs);
+            body.u32(node.outputs);
+            body.port_types(&node.input_types);
+            body.port_types(&node.output_types);
+
+            let regions = node.regions.as_slice(&self.region_id_pool);
+            body.u32(regions.len() as u32);
+            for region in regions {
+                body.u32(region.as_u32());
+            }
+
+            body.string(node.kind.node_type());
+            if let Some(encode) = node.kind.as_encode() {
+                body.bytes.push(ENCODED);
+                let mut data = vec![];
+                encode.encode(&mut data);
+                write_bytes(&mut body.bytes, &data);
+            } else if let Some(payload) = node.kind.payload() {
+                body.bytes.push(PAYLOAD);
+                body.string(&payload);
+            } else {
+                body.bytes.push(NO_DATA);
+            }
+
+            body.string(self.symbols.get(id).map(String::as_str).unwrap_or(""));
+        }
+
+        body.u32(self.region.as_u32());
+
+        let mut header = MAGIC.to_vec();
+        write_u32(&mut header, VERSION);
+        write_u32(&mut header, body.types.len() as u32);
+        for name in &body.types {
+            write_bytes(&mut header, name.as_bytes());
+        }
+
+        writer.write_all(&header)?;
+        writer.write_all(&body.bytes)
+    }
+
+    /// Read a translation unit written by [`Self::save`].
+    ///
+    /// Only the built-in node kinds can be read, see [`Self::load_with`] for custom kinds.
+    pub fn load(reader: &mut impl Read) -> std::result::Result<Self, BinaryError> {
+        Self::load_with(reader, &KindRegistry::new())
+    }
+
+    /// Read a translation unit, creating the kinds of nodes and the types of ports through the
+    /// registry.
+    pub fn load_with(
+        reader: &mut impl Read,
+        kinds: &KindRegistry,
+    ) -> std::result::Result<Self, BinaryError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let bytes = bytes.strip_prefix(MAGIC).ok_or(BinaryError::NotRvsdg)?;
+        let mut d = Decoder {
+            bytes: Bytes::new(bytes),
+            kinds,
+            types: vec![],
+            nodes: 0,
+            regions: 0,
+        };
+
+        let version = d.u32()?;
+        if version != VERSION {
+            return Err(BinaryError::UnsupportedVersion(version));
+        }
+
+        for _ in 0..d.u32()? {
+            let name = d.string()?;
+            let ty = kinds
+                .types
+                .get(name)
+                .ok_or_else(|| BinaryError::UnknownType(name.to_string()))?;
+            d.types.push(ty.clone());
+        }
+
+        d.regions = d.u32()?;
+        d.nodes = d.u32()?;
+
+        let mut ctx = TranslationUnitContext {
+            nodes: PrimaryMap::new(),
+            regions: PrimaryMap::new(),
+            symbols: SecondaryMap::new(),
+            uses: uses::Uses::default(),
+            node_id_pool: ListPool::new(),
+            region_id_pool: ListPool::new(),
+            region: id::Region::from_u32(0),
+        };
+
+        let mut edges = vec![];
+        for _ in 0..d.regions {
+            let container_node = match d.u32()? {
+                0 => None,
+                n if n <= d.nodes => Some(id::AnyNode::from_u32(n - 1)),
+                _ => return Err(BinaryError::Corrupt),
+            };
+            let arguments = d.u32()?;
+            let results = d.u32()?;
+            let argument_types = d.port_types(arguments)?;
+            let result_types = d.port_types(results)?;
+
+            let mut nodes = EntityList::new();
+            for _ in 0..d.u32()? {
+                nodes.push(d.node()?, &mut ctx.node_id_pool);
+            }
+
+            let region = ctx.regions.push(Region {
+                container_node,
+                arguments,
+                results,
+                argument_types,
+                result_types,
+                edges: vec![],
+                nodes,
+            });
+
+            for _ in 0..d.u32()? {
+                let origin = d.origin()?;
+                let user = d.user()?;
+                edges.push((region, Edge { origin, user }));
+            }
+        }
+
+        for _ in 0..d.nodes {
+            let region = d.region()?;
+            let inputs = d.u32()?;
+            let outputs = d.u32()?;
+            let input_types = d.port_types(inputs)?;
+            let output_types = d.port_types(outputs)?;
+
+            let mut regions = EntityList::new();
+            for _ in 0..d.u32()? {
+                regions.push(d.region()?, &mut ctx.region_id_pool);
+            }
+
+            let kind = d.kind()?;
+            let symbol = d.string()?;
+
+            let id = ctx.nodes.next_key();
+            ctx.nodes.push(Node {
+                id,
+                region,
+                inputs,
+                outputs,
+                input_types,
+                output_types,
+                regions,
+                kind,
+            });
+            if !symbol.is_empty() {
+                ctx.add_symbol(id, symbol);
+            }
+        }
+
+        ctx.region = d.region()?;
+
+        if !d.bytes.is_empty() || ctx.nodes.is_empty() {
+            return Err(BinaryError::Corrupt);
+        }
+
+        // Ids of nodes and regions were checked while reading, but ports and kinds can only be
+        // checked once everything is read
+        if !ctx.is_kind::<TranslationUnit>(id::AnyNode::from_u32(0))
+            || !structure_is_consistent(&ctx)
+            || !ctx
+                .nodes
+                .keys()
+                .all(|node| kind_references_exist(&ctx, node))
+        {
+            return Err(BinaryError::Corrupt);
+        }
+
+        for (region, edge) in edges {
+            if ctx.check_origin(edge.origin).is_err()
+                || ctx.check_user(edge.user).is_err()
+                || !ctx.edge_is_in(region, edge)
+            {
+                return Err(BinaryError::Corrupt);
+            }
+            ctx.push_edge(region, edge);
+        }
+
+        Ok(ctx)
+    }
+}
+
+// Nodes and regions agree on which contains which, walking upwards from a region ends, and the
+// regions of each node have an argument for every input forwarded to them
+pub(crate) fn structure_is_consistent(ctx: &TranslationUnitContext) -> bool {
+    let nodes_agree = ctx.regions.iter().all(|(region, r)| {
+        r.nodes
+            .as_slice(&ctx.node_id_pool)
+            .iter()
+            .all(|&node| ctx.nodes[node].region == region)
+    });
+
+    // The region of omega has no container node
+    let regions_agree = ctx.nodes.iter().skip(1).all(|(node, n)| {
+        n.regions
+            .as_slice(&ctx.region_id_pool)
+            .iter()
+            .all(|&region| ctx.regions[region].container_node == Some(node))
+    });
+
+    let walks_end = ctx.regions.keys().all(|mut region| {
+        (0..=ctx.regions.len()).any(|_| match ctx.regions[region].container_node {
+            Some(node) => {
+                region = ctx.nodes[node].region;
+                false
+            }
+            None => true,
+        })
+    });
+
+    nodes_agree
+        && regions_agree
+        && walks_end
+        && ctx
+            .nodes
+            .keys()
+            .all(|node| ctx.has_forwarded_arguments(node))
+}
+
+// The nodes, regions and ports which built-in kinds refer to exist
+pub(crate) fn kind_references_exist(ctx: &TranslationUnitContext, node: id::AnyNode) -> bool {
+    let kind = ctx.nodes[node].kind.as_any();
+    if let Some(unit) = kind.downcast_ref::<TranslationUnit>() {
+        return ctx.regions.get(unit.region).is_some();
+    }
+
+    let Some(env) = kind.downcast_ref::<RecEnv>() else {
+        return true;
+    };
+    let Some(&region) = ctx.regions(node).first() else {
+        return env.lambdas.is_empty();
+    };
+
+    env.lambdas.iter().all(|(&lambda, &(argument, output))| {
+        ctx.nodes
+            .get(lambda)
+            .is_some_and(|n| n.region == region && n.kind.as_any().is::<Lambda>())
+            && ctx.check_origin(Origin::Argument(region, argument)).is_ok()
+            && ctx.check_origin(Origin::Output(node, output)).is_ok()
+    })
+}
@@ -0,0 +1,359 @@
+//! Lowering of lambdas to control-flow graphs in SSA form.
+//!
+//! The nodes of a region are scheduled in topological order. Gammas end the current block with a
+//! [`Terminator::Switch`] to a block for each branch, which all jump to a join block with a phi for
+//! each output. Thetas jump to a loop header with a phi for each loop variable, and the end of
+//! the loop body branches back to the header while the predicate is nonzero.
+
+use super::*;
+use cranelift_entity::entity_impl;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Block(u32);
+entity_impl!(Block, "block");
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(u32);
+entity_impl!(Value, "v");
+
+/// The control-flow graph of a lambda, see [`TranslationUnitContext::lower_to_cfg`]
+#[derive(Debug)]
+pub struct Cfg {
+    pub blocks: PrimaryMap<Block, BasicBlock>,
+    /// The values of the arguments of the lambda, including its context variables
+    pub params: Vec<Value>,
+    pub values: u32,
+}
+
+#[derive(Debug)]
+pub struct BasicBlock {
+    pub phis: Vec<Phi>,
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+/// A value taken from the value of the block control came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phi {
+    pub value: Value,
+    pub incoming: Vec<(Block, Value)>,
+}
+
+/// A node without regions, with a value for each of its inputs and outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inst {
+    pub node: id::AnyNode,
+    pub inputs: Vec<Value>,
+    pub outputs: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Jump(Block),
+    /// Continue at `nonzero` unless the condition is zero
+    Branch {
+        condition: Value,
+        nonzero: Block,
+        zero: Block,
+    },
+    /// Continue at the target selected by the predicate
+    Switch {
+        predicate: Value,
+        targets: Vec<Block>,
+    },
+    Return(Vec<Value>),
+}
+
+impl Cfg {
+    /// The block control starts in
+    pub fn entry(&self) -> Block {
+        Block::from_u32(0)
+    }
+
+    /// Render the blocks with the kinds of the nodes of their instructions
+    pub fn display<'a>(&'a self, ctx: &'a TranslationUnitContext) -> impl fmt::Display + 'a {
+        CfgDisplay { cfg: self, ctx }
+    }
+}
+
+struct CfgDisplay<'a> {
+    cfg: &'a Cfg,
+    ctx: &'a TranslationUnitContext,
+}
+
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for CfgDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (id, block) in self.cfg.blocks.iter() {
+            match id == self.cfg.entry() {
+                true => writeln!(f, "{id}({}):", list(&self.cfg.params))?,
+                false => writeln!(f, "{id}:")?,
+            }
+
+            for phi in &block.phis {
+                let incoming: Vec<_> = phi
+                    .incoming
+                    .iter()
+                    .map(|(block, value)| format!("{block}: {value}"))
+                    .collect();
+                writeln!(f, "  {} = phi [{}]", phi.value, incoming.join(", "))?;
+            }
+
+            for inst in &block.insts {
+                let kind = &self.ctx.nodes[inst.node].kind;
+                write!(f, "  ")?;
+                if !inst.outputs.is_empty() {
+                    write!(f, "{} = ", list(&inst.outputs))?;
+                }
+                write!(f, "{}", kind.node_type())?;
+                if let Some(payload) = kind.payload() {
+                    write!(f, " {payload}")?;
+                }
+                if !inst.inputs.is_empty() {
+                    write!(f, " {}", list(&inst.inputs))?;
+                }
+                writeln!(f)?;
+            }
+
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "  jump {target}")?,
+                Terminator::Branch {
+                    condition,
+                    nonzero,
+                    zero,
+                } => writeln!(f, "  branch {condition}, {nonzero}, {zero}")?,
+                Terminator::Switch { predicate, targets } => {
+                    writeln!(f, "  switch {predicate}, [{}]", list(targets))?
+                }
+                Terminator::Return(values) => writeln!(f, "  return {}", list(values))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Lowering<'a> {
+    ctx: &'a TranslationUnitContext,
+    cfg: Cfg,
+    values: HashMap<Origin, Value>,
+    current: Block,
+}
+
+impl Lowering<'_> {
+    fn new_value(&mut self) -> Value {
+        let value = Value::from_u32(self.cfg.values);
+        self.cfg.values += 1;
+        value
+    }
+
+    fn new_block(&mut self) -> Block {
+        self.cfg.blocks.push(BasicBlock {
+            phis: vec![],
+            insts: vec![],
+            terminator: Terminator::Return(vec![]),
+        })
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        self.cfg.blocks[self.current].terminator = terminator;
+    }
+
+    fn value_of(&self, user: User) -> std::result::Result<Value, Error> {
+        self.ctx
+            .origin_of(user)
+            .and_then(|origin| self.values.get(&origin).copied())
+            .ok_or(Error::Unconnected { user })
+    }
+
+    fn results(&self, region: id::Region) -> std::result::Result<Vec<Value>, Error> {
+        self.ctx
+            .results(region)
+            .map(|result| self.value_of(User::Result(region, result)))
+            .collect()
+    }
+
+    fn bind_arguments(&mut self, region: id::Region, values: &[Value]) {
+        for (argument, &value) in self.ctx.arguments(region).zip(values) {
+            self.values
+                .insert(Origin::Argument(region, argument), value);
+        }
+    }
+
+    fn bind_outputs(&mut self, node: id::AnyNode, values: &[Value]) {
+        for (output, &value) in self.ctx.outputs(node).zip(values) {
+            self.values.insert(Origin::Output(node, output), value);
+        }
+    }
+
+    fn lower_region(&mut self, region: id::Region) -> std::result::Result<(), Error> {
+        for node in self.ctx.try_topological_nodes(region)? {
+            self.lower_node(node)?;
+        }
+        Ok(())
+    }
+
+    fn lower_node(&mut self, node: id::AnyNode) -> std::result::Result<(), Error> {
+        let inputs = self
+            .ctx
+            .inputs(node)
+            .map(|input| self.value_of(User::Input(node, input)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        if self.ctx.is_kind::<Gamma>(node) {
+            return self.lower_gamma(node, &inputs);
+        }
+        if self.ctx.is_kind::<DoWhile>(node) {
+            return self.lower_theta(node, &inputs);
+        }
+        if !self.ctx.regions(node).is_empty() {
+            return Err(Error::NotLowerable { node });
+        }
+
+        let outputs: Vec<_> = self.ctx.outputs(node).map(|_| self.new_value()).collect();
+        self.bind_outputs(node, &outputs);
+
+        let block = &mut self.cfg.blocks[self.current];
+        block.insts.push(Inst {
+            node,
+            inputs,
+            outputs,
+        });
+
+        Ok(())
+    }
+
+    fn lower_gamma(
+        &mut self,
+        node: id::AnyNode,
+        inputs: &[Value],
+    ) -> std::result::Result<(), Error> {
+        let branches = self.ctx.regions(node);
+        let targets: Vec<_> = branches.iter().map(|_| self.new_block()).collect();
+        self.terminate(Terminator::Switch {
+            predicate: inputs[0],
+            targets: targets.clone(),
+        });
+
+        let join = self.new_block();
+        let mut incoming = vec![vec![]; self.ctx.outputs(node).count()];
+
+        for (&branch, target) in branches.iter().zip(targets) {
+            self.current = target;
+            self.bind_arguments(branch, inputs);
+            self.lower_region(branch)?;
+
+            for (incoming, value) in incoming.iter_mut().zip(self.results(branch)?) {
+                incoming.push((self.current, value));
+            }
+            self.terminate(Terminator::Jump(join));
+        }
+
+        self.current = join;
+        let mut outputs = vec![];
+        for incoming in incoming {
+            let value = self.new_value();
+            self.cfg.blocks[join].phis.push(Phi { value, incoming });
+            outputs.push(value);
+        }
+        self.bind_outputs(node, &outputs);
+
+        Ok(())
+    }
+
+    fn lower_theta(
+        &mut self,
+        node: id::AnyNode,
+        inputs: &[Value],
+    ) -> std::result::Result<(), Error> {
+        let body = self.ctx.region(node);
+
+        let entry = self.current;
+        let header = self.new_block();
+        self.terminate(Terminator::Jump(header));
+
+        let mut arguments = vec![];
+        for &input in inputs {
+            let value = self.new_value();
+            let phi = Phi {
+                value,
+                incoming: vec![(entry, input)],
+            };
+            self.cfg.blocks[header].phis.push(phi);
+            arguments.push(value);
+        }
+        self.bind_arguments(body, &arguments);
+
+        self.current = header;
+        self.lower_region(body)?;
+
+        // The predicate comes before the loop variables
+        let results = self.results(body)?;
+        let (&condition, variables) = results.split_first().ok_or(Error::NotLowerable { node })?;
+
+        let latch = self.current;
+        for (phi, &value) in self.cfg.blocks[header].phis.iter_mut().zip(variables) {
+            phi.incoming.push((latch, value));
+        }
+
+        let exit = self.new_block();
+        self.terminate(Terminator::Branch {
+            condition,
+            nonzero: header,
+            zero: exit,
+        });
+
+        self.current = exit;
+        self.bind_outputs(node, variables);
+
+        Ok(())
+    }
+}
+
+impl TranslationUnitContext {
+    /// Lower the region of a lambda to a control-flow graph.
+    ///
+    /// Panics if the region contains nodes with regions other than gammas and thetas, such as
+    /// nested lambdas, or if it isn't connected or is cyclic.
+    pub fn lower_to_cfg(&self, lambda: id::Node<Lambda>) -> Cfg {
+        self.try_lower_to_cfg(lambda)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_lower_to_cfg(&self, lambda: id::Node<Lambda>) -> std::result::Result<Cfg, Error> {
+        let region = self.region(lambda.id);
+
+        let mut lowering = Lowering {
+            ctx: self,
+            cfg: Cfg {
+                blocks: PrimaryMap::new(),
+                params: vec![],
+                values: 0,
+            },
+            values: HashMap::new(),
+            current: Block::from_u32(0),
+        };
+
+        lowering.current = lowering.new_block();
+        let params: Vec<_> = self
+            .arguments(region)
+            .map(|_| lowering.new_value())
+            .collect();
+        lowering.bind_arguments(region, &params);
+        lowering.cfg.params = params;
+
+        lowering.lower_region(region)?;
+        let results = lowering.results(region)?;
+        lowering.terminate(Terminator::Return(results));
+
+        Ok(lowering.cfg)
+    }
+}
@@ -0,0 +1,226 @@
+use super::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// Common node elimination
+//
+// Regions are visited from omega and inwards. Once the nodes of a region have been merged, inputs
+// of the same node which now share an origin are merged as well, so that the arguments they're
+// forwarded as are the same origin for the nodes of the inner regions.
+
+impl TranslationUnitContext {
+    /// Merge nodes which are structurally equal and use the same origins.
+    ///
+    /// Only nodes of kinds which opt into [`StructuralEq`](crate::nodes::StructuralEq) and don't
+    /// have a symbol, regions or state outputs are merged. Returns whether anything was merged.
+    pub fn eliminate_common_nodes(&mut self) -> bool {
+        let omega = self.get(self.omega()).region;
+        self.eliminate_common_nodes_in(omega)
+    }
+
+    fn eliminate_common_nodes_in(&mut self, region: id::Region) -> bool {
+        let mut changed = false;
+
+        // Merging nodes can make the nodes using them equal, so repeat until nothing changes
+        while self.merge_common_nodes(region) {
+            changed = true;
+        }
+
+        for node in self.nodes(region).collect::<Vec<_>>() {
+            changed |= self.merge_forwarded_inputs(node);
+
+            for inner in self.regions(node).to_vec() {
+                changed |= self.eliminate_common_nodes_in(inner);
+            }
+        }
+
+        changed
+    }
+
+    fn merge_common_nodes(&mut self, region: id::Region) -> bool {
+        let mut numbered: HashMap<u64, Vec<(id::AnyNode, Vec<Origin>)>> = HashMap::new();
+        let mut changed = false;
+
+        // In topological order the nodes used by a node have already been merged
+        let nodes: Vec<_> = match self.try_topological_nodes(region) {
+            Ok(nodes) => nodes.collect(),
+            Err(_) => self.nodes(region).collect(),
+        };
+
+        for node in nodes {
+            let Some((hash, origins)) = self.value_number(node) else {
+                continue;
+            };
+
+            let candidates = numbered.entry(hash).or_default();
+            let existing = candidates
+                .iter()
+                .find(|(other, other_origins)| {
+                    *other_origins == origins && self.structurally_equal(node, *other)
+                })
+                .map(|(other, _)| *other);
+
+            match existing {
+                Some(other) => {
+                    trace!(
+                        "merging {} into {}",
+                        self.debug_node(node),
+                        self.debug_node(other)
+                    );
+                    for output in self.outputs(node) {
+                        self.redirect_edges(
+                            region,
+                            Origin::Output(node, output),
+                            Origin::Output(other, output),
+                        );
+                    }
+                    self.remove_node(node);
+                    changed = true;
+                }
+                None => candidates.push((node, origins)),
+            }
+        }
+
+        changed
+    }
+
+    fn value_number(&self, node: id::AnyNode) -> Option<(u64, Vec<Origin>)> {
+        let kind = &self.nodes[node].kind;
+        let structural = kind.as_structural()?;
+
+        if !self.regions(node).is_empty() || !self.symbols[node].is_empty() {
+            return None;
+        }
+
+        // Each side effect has to happen, no matter if it's equal to another
+        if self
+            .outputs(node)
+            .any(|output| self.is_state_origin(Origin::Output(node, output)))
+        {
+            return None;
+        }
+
+        let origins = self
+            .inputs(node)
+            .map(|input| self.origin_of(User::Input(node, input)))
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut hasher = DefaultHasher::new();
+        kind.node_type().hash(&mut hasher);
+        structural.structural_hash(&mut hasher);
+        origins.hash(&mut hasher);
+        self.nodes[node].outputs.hash(&mut hasher);
+
+        Some((hasher.finish(), origins))
+    }
+
+    fn structurally_equal(&self, node: id::AnyNode, other: id::AnyNode) -> bool {
+        let (kind, other_kind) = (&self.nodes[node].kind, &self.nodes[other].kind);
+        let types = &self.nodes[node].output_types;
+        let other_types = &self.nodes[other].output_types;
+        let same_types = types.len() == other_types.len()
+            && types.iter().zip(other_types).all(|types| match types {
+                (Some(ty), Some(other)) => ty.type_eq(other.as_ref()),
+                (None, None) => true,
+                _ => false,
+            });
+
+        same_types
+            && kind.node_type() == other_kind.node_type()
+            && kind
+                .as_structural()
+                .is_some_and(|kind| kind.structural_eq(other_kind.as_ref()))
+    }
+
+    // Merge inputs of the node which have the same origin, along with the arguments they're
+    // forwarded as. For dowhile nodes only loop variables which are invariant are merged.
+    fn merge_forwarded_inputs(&mut self, node: id::AnyNode) -> bool {
+        // The inputs of simple nodes are operands, so `x + x` has to keep both
+        if self.regions(node).is_empty() {
+            return false;
+        }
+
+        let region = self.nodes[node].region;
+        let any = id::Node::<id::AnyNode>::new(node);
+        let theta = self.try_cast::<DoWhile>(node).ok();
+
+        let mut first_of: HashMap<Origin, id::Input> = HashMap::new();
+        let mut duplicates = vec![];
+
+        for input in self.inputs(node) {
+            let Some(origin) = self.origin_of(User::Input(node, input)) else {
+                continue;
+            };
+
+            if let Some(theta) = theta
+                && !self.is_invariant(theta, input)
+            {
+                continue;
+            }
+
+            match first_of.get(&origin) {
+                Some(&first) => duplicates.push((first, input)),
+                None => {
+                    first_of.insert(origin, input);
+                }
+            }
+        }
+
+        let changed = !duplicates.is_empty();
+
+        // Descending, so that the ids of the remaining duplicates stay intact
+        for &(first, duplicate) in duplicates.iter().rev() {
+            trace!(
+                "merging {duplicate} into {first} of {}",
+                self.debug_node(node)
+            );
+
+            for inner in self.regions(node).to_vec() {
+                let from = self.input_as_argument_in(
+                    Input {
+                        node: any,
+                        id: duplicate,
+                    },
+                    inner,
+                );
+                let to = self.input_as_argument_in(
+                    Input {
+                        node: any,
+                        id: first,
+                    },
+                    inner,
+                );
+                self.redirect_edges(inner, from.into(), to.into());
+            }
+
+            match theta {
+                Some(theta) => {
+                    let from = Origin::Output(node, id::Output::from_u32(duplicate.as_u32()));
+                    let to = Origin::Output(node, id::Output::from_u32(first.as_u32()));
+                    self.redirect_edges(region, from, to);
+                    self.remove_loop_var(theta, duplicate.as_u32());
+                }
+                None => self.remove_input_port(node, duplicate),
+            }
+        }
+
+        changed
+    }
+
+    pub(crate) fn is_invariant(&self, theta: id::Node<DoWhile>, input: id::Input) -> bool {
+        let region = self.region(theta.id);
+        let edge = Edge {
+            origin: Origin::Argument(region, id::Argument::from_u32(input.as_u32())),
+            user: User::Result(region, id::Result::from_u32(input.as_u32() + 1)),
+        };
+        self.regions[region].edges.contains(&edge)
+    }
+
+    pub(crate) fn redirect_edges(&mut self, region: id::Region, from: Origin, to: Origin) {
+        self.rewrite_edges(region, |edge| {
+            if edge.origin == from {
+                edge.origin = to;
+            }
+        });
+    }
+}
@@ -0,0 +1,168 @@
+use super::*;
+
+/// What was copied to where by [`TranslationUnitContext::copy_region_into`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mapping {
+    /// The copy of each node, including the nodes within the regions of copied nodes
+    pub nodes: HashMap<id::AnyNode, id::AnyNode>,
+    /// The copy of each region of the copied nodes
+    pub regions: HashMap<id::Region, id::Region>,
+    /// The origin of each result of the copied region, translated to the destination region
+    pub results: Vec<Option<Origin>>,
+}
+
+impl TranslationUnitContext {
+    /// Copy the nodes and edges of `src` into `dst`.
+    ///
+    /// Uses of the arguments of `src` are replaced by the origin at the same index of `arguments`,
+    /// which have to be reachable from `dst`. The results of `src` aren't connected to anything,
+    /// instead their origins are part of the returned [`Mapping`].
+    pub fn copy_region_into(
+        &mut self,
+        src: id::Region,
+        dst: id::Region,
+        arguments: &[Origin],
+    ) -> Mapping {
+        self.try_copy_region_into(src, dst, arguments)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_copy_region_into(
+        &mut self,
+        src: id::Region,
+        dst: id::Region,
+        arguments: &[Origin],
+    ) -> std::result::Result<Mapping, Error> {
+        let expected = self.regions[src].arguments as usize;
+        if arguments.len() != expected {
+            return Err(Error::ArgumentCountMismatch {
+                region: src,
+                expected,
+                found: arguments.len(),
+            });
+        }
+
+        let mut mapping = Mapping::default();
+        mapping.results = self.copy_region_nodes(src, dst, arguments, &mut mapping);
+        Ok(mapping)
+    }
+
+    /// Copy a node along with the nodes and edges of its regions into `into`.
+    ///
+    /// The inputs of the copy are left unconnected.
+    pub fn clone_node(&mut self, node: id::AnyNode, into: id::Region) -> id::AnyNode {
+        let mut mapping = Mapping::default();
+        self.copy_node(node, into, &mut mapping)
+    }
+
+    // Copying into the region itself adds to what's being copied, so the originals are taken first
+    fn copy_region_nodes(
+        &mut self,
+        src: id::Region,
+        dst: id::Region,
+        arguments: &[Origin],
+        mapping: &mut Mapping,
+    ) -> Vec<Option<Origin>> {
+        let nodes: Vec<_> = self.nodes(src).collect();
+        let edges = self.regions[src].edges.clone();
+
+        for node in nodes {
+            self.copy_node(node, dst, mapping);
+        }
+
+        let copy_of = |node| mapping.nodes.get(&node).copied().unwrap_or(node);
+
+        let mut results = vec![None; self.regions[src].results as usize];
+        for edge in edges {
+            let origin = match edge.origin {
+                Origin::Output(node, output) => Origin::Output(copy_of(node), output),
+                Origin::Argument(region, argument) if region == src => {
+                    arguments[argument.as_u32() as usize]
+                }
+                origin => origin,
+            };
+
+            match edge.user {
+                User::Input(node, input) => {
+                    let user = User::Input(copy_of(node), input);
+                    self.push_edge(dst, Edge { origin, user });
+                }
+                User::Result(_, result) => results[result.as_u32() as usize] = Some(origin),
+            }
+        }
+
+        results
+    }
+
+    fn copy_node(
+        &mut self,
+        node: id::AnyNode,
+        dst: id::Region,
+        mapping: &mut Mapping,
+    ) -> id::AnyNode {
+        let kind = self.nodes[node].kind.clone_box();
+
+        let regions: Vec<_> = self
+            .regions(node)
+            .to_vec()
+            .into_iter()
+            .map(|region| {
+                let copy =
+                    self.add_region(self.regions[region].arguments, self.regions[region].results);
+                self.regions[copy].argument_types = self.regions[region].argument_types.clone();
+                self.regions[copy].result_types = self.regions[region].result_types.clone();
+                (region, copy)
+            })
+            .collect();
+
+        let copy = self.in_region(dst, |ctx| {
+            ctx.push_node(kind, regions.iter().map(|(_, copy)| *copy))
+        });
+        mapping.nodes.insert(node, copy);
+
+        let original = &self.nodes[node];
+        let (inputs, outputs) = (original.inputs, original.outputs);
+        let input_types = original.input_types.clone();
+        let output_types = original.output_types.clone();
+
+        let copied = &mut self.nodes[copy];
+        copied.inputs = inputs;
+        copied.outputs = outputs;
+        copied.input_types = input_types;
+        copied.output_types = output_types;
+
+        let symbol = self.symbols[node].clone();
+        if !symbol.is_empty() {
+            self.symbols[copy] = symbol;
+        }
+
+        for (region, region_copy) in regions {
+            mapping.regions.insert(region, region_copy);
+
+            let arguments: Vec<_> = self
+                .arguments(region_copy)
+                .map(|argument| Origin::Argument(region_copy, argument))
+                .collect();
+
+            let results = self.copy_region_nodes(region, region_copy, &arguments, mapping);
+
+            for (result, origin) in self.results(region_copy).zip(results) {
+                if let Some(origin) = origin {
+                    let user = User::Result(region_copy, result);
+                    self.push_edge(region_copy, Edge { origin, user });
+                }
+            }
+        }
+
+        // Recenvs refer to their lambdas by id
+        if let Ok(env) = self.try_cast::<RecEnv>(copy) {
+            let lambdas = std::mem::take(&mut self.get_mut(env).lambdas);
+            self.get_mut(env).lambdas = lambdas
+                .into_iter()
+                .map(|(lambda, ports)| (mapping.nodes[&lambda], ports))
+                .collect();
+        }
+
+        copy
+    }
+}
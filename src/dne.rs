@@ -0,0 +1,221 @@
+use super::*;
+use std::collections::HashSet;
+
+// Dead node elimination
+//
+// Liveness is tracked per origin, starting from the exported symbols and spreading from users to
+// their origins. Structural nodes only keep what's needed to produce their live outputs, so a
+// gamma output being dead makes the results of every branch dead as well.
+//
+// Removing ports renumbers the ports after them, so the ports of a node are removed before its
+// regions are visited and always in descending order, keeping the ids still to be looked up intact.
+
+#[derive(Clone, Copy)]
+enum Work {
+    Node(id::AnyNode),
+    Origin(Origin),
+    User(User),
+}
+
+#[derive(Default)]
+struct Liveness {
+    nodes: HashSet<id::AnyNode>,
+    origins: HashSet<Origin>,
+}
+
+impl TranslationUnitContext {
+    /// Remove all nodes whose outputs are never used, along with unused inputs and the region
+    /// arguments they're forwarded as.
+    ///
+    /// Nodes with a symbol are treated as used. Returns whether anything was removed.
+    pub fn eliminate_dead_nodes(&mut self) -> bool {
+        let live = self.liveness();
+        let omega = self.get(self.omega()).region;
+        self.sweep_region(&live, omega)
+    }
+
+    fn liveness(&self) -> Liveness {
+        let mut live = Liveness::default();
+
+        let mut work: Vec<Work> = self
+            .symbols
+            .iter()
+            .filter(|(_, sym)| !sym.is_empty())
+            .flat_map(|(node, _)| {
+                let outputs = self.outputs(node).map(move |o| Origin::Output(node, o));
+                std::iter::once(Work::Node(node)).chain(outputs.map(Work::Origin))
+            })
+            .collect();
+
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Node(node) => {
+                    if live.nodes.insert(node) {
+                        self.node_liveness(node, &mut work);
+                    }
+                }
+                Work::Origin(origin) => {
+                    if live.origins.insert(origin) {
+                        self.origin_liveness(origin, &mut work);
+                    }
+                }
+                Work::User(user) => work.extend(self.origins_of(user).map(Work::Origin)),
+            }
+        }
+
+        live
+    }
+
+    // What a node needs regardless of which of its outputs are used
+    fn node_liveness(&self, node: id::AnyNode, work: &mut Vec<Work>) {
+        let regions = self.regions(node);
+
+        if self.is_kind::<Gamma>(node) {
+            work.push(Work::User(User::Input(node, id::Input::from_u32(0))));
+        } else if self.is_kind::<DoWhile>(node) {
+            work.push(Work::User(User::Result(
+                regions[0],
+                id::Result::from_u32(0),
+            )));
+        } else if self.is_kind::<RecEnv>(node) {
+            // Only the lambdas which are used
+        } else if regions.is_empty() {
+            let inputs = self.inputs(node).map(|i| Work::User(User::Input(node, i)));
+            work.extend(inputs);
+        } else {
+            for &region in regions {
+                let results = self.results(region).map(|r| User::Result(region, r));
+                work.extend(results.map(Work::User));
+            }
+        }
+    }
+
+    fn origin_liveness(&self, origin: Origin, work: &mut Vec<Work>) {
+        match origin {
+            Origin::Output(node, output) => {
+                work.push(Work::Node(node));
+
+                let regions = self.regions(node);
+                let result = id::Result::from_u32(output.as_u32());
+
+                if self.is_kind::<Gamma>(node) || self.is_kind::<RecEnv>(node) {
+                    let results = regions.iter().map(|&r| Work::User(User::Result(r, result)));
+                    work.extend(results);
+                } else if self.is_kind::<DoWhile>(node) {
+                    let result = id::Result::from_u32(output.as_u32() + 1);
+                    let input = id::Input::from_u32(output.as_u32());
+                    work.push(Work::User(User::Result(regions[0], result)));
+                    work.push(Work::User(User::Input(node, input)));
+                }
+            }
+            Origin::Argument(region, argument) => {
+                let Some(node) = self.regions[region].container_node else {
+                    return;
+                };
+
+                if let Some(input) = self.argument_as_input(region, argument) {
+                    work.push(Work::User(User::Input(node, input.id)));
+
+                    // The argument of a loop variable is also the value of its previous iteration
+                    if self.is_kind::<DoWhile>(node) {
+                        let result = id::Result::from_u32(input.id.as_u32() + 1);
+                        work.push(Work::User(User::Result(region, result)));
+                    }
+                } else if let Ok(env) = self.try_cast::<RecEnv>(node)
+                    && let Some(&(_, output)) =
+                        self.get(env).lambdas.values().find(|(a, _)| *a == argument)
+                {
+                    work.push(Work::Origin(Origin::Output(node, output)));
+                }
+            }
+        }
+    }
+
+    fn sweep_region(&mut self, live: &Liveness, region: id::Region) -> bool {
+        let nodes: Vec<_> = self.nodes(region).collect();
+        let (nodes, dead): (Vec<_>, Vec<_>) = nodes
+            .into_iter()
+            .partition(|node| live.nodes.contains(node));
+
+        let mut changed = !dead.is_empty();
+
+        // Lambdas of a recenv take their recursion argument and output with them
+        for node in dead {
+            self.remove_node(node);
+        }
+
+        for node in nodes {
+            changed |= self.sweep_ports(live, node);
+
+            for region in self.regions(node).to_vec() {
+                changed |= self.sweep_region(live, region);
+            }
+        }
+
+        changed
+    }
+
+    fn sweep_ports(&mut self, live: &Liveness, node: id::AnyNode) -> bool {
+        let mut changed = false;
+
+        let any = id::Node::<id::AnyNode>::new(node);
+        let regions = self.regions(node).to_vec();
+        let mut outputs: Vec<_> = self.outputs(node).collect();
+        let mut inputs: Vec<_> = self.inputs(node).collect();
+        outputs.reverse();
+        inputs.reverse();
+
+        let is_live_argument = |ctx: &Self, input: id::Input| {
+            regions.iter().any(|&region| {
+                let argument = ctx.input_as_argument_in(
+                    Input {
+                        node: any,
+                        id: input,
+                    },
+                    region,
+                );
+                live.origins
+                    .contains(&Origin::Argument(region, argument.id))
+            })
+        };
+
+        if let Ok(theta) = self.try_cast::<DoWhile>(node) {
+            for output in outputs {
+                let input = id::Input::from_u32(output.as_u32());
+                if !live.origins.contains(&Origin::Output(node, output))
+                    && !is_live_argument(self, input)
+                {
+                    self.remove_loop_var(theta, output.as_u32());
+                    changed = true;
+                }
+            }
+            return changed;
+        }
+
+        if self.is_kind::<Gamma>(node) {
+            for output in outputs {
+                if !live.origins.contains(&Origin::Output(node, output)) {
+                    self.remove_output(Output {
+                        node: any,
+                        id: output,
+                    });
+                    changed = true;
+                }
+            }
+        }
+
+        if regions.is_empty() {
+            return changed;
+        }
+
+        for input in inputs {
+            let is_predicate = self.is_kind::<Gamma>(node) && input.as_u32() == 0;
+            if !is_predicate && !is_live_argument(self, input) {
+                self.remove_input_port(node, input);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
@@ -0,0 +1,171 @@
+use super::*;
+use std::fmt::{self, Write};
+
+// Every node and region is a cluster. Ports are the fields of record nodes; one for each node, and
+// one each for the arguments and results of a region.
+
+struct DotCtx {
+    dot: String,
+}
+
+impl DotCtx {
+    fn line(&mut self, walker: &Walker, line: impl fmt::Display) {
+        let depth = walker.stack().len() + 1;
+        writeln!(self.dot, "{:indent$}{line}", "", indent = depth * 2).unwrap();
+    }
+
+    fn record(&mut self, walker: &Walker, name: &str, fields: &[String]) {
+        let label = fields.join("|");
+        self.line(
+            walker,
+            format!("{name} [shape=record, label=\"{{{label}}}\"];"),
+        );
+    }
+}
+
+fn ports<P: fmt::Display>(
+    ports: impl Iterator<Item = P>,
+    ty: impl Fn(&P) -> Option<String>,
+) -> Option<String> {
+    let fields: Vec<_> = ports
+        .map(|port| match ty(&port) {
+            Some(ty) => format!("<{port}> {port}: {}", escape(&ty)),
+            None => format!("<{port}> {port}"),
+        })
+        .collect();
+
+    (!fields.is_empty()).then(|| format!("{{{}}}", fields.join("|")))
+}
+
+// Characters with a meaning in record labels
+fn escape(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '{' | '}' | '|' | '<' | '>' | '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Characters with a meaning in quoted strings
+fn quote(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn node_name(node: id::AnyNode) -> String {
+    format!("n{}", node.as_u32())
+}
+
+fn origin_name(origin: Origin) -> String {
+    match origin {
+        Origin::Output(node, output) => format!("{}:{output}", node_name(node)),
+        Origin::Argument(region, argument) => {
+            format!("r{}_arguments:{argument}", region.as_u32())
+        }
+    }
+}
+
+fn user_name(user: User) -> String {
+    match user {
+        User::Input(node, input) => format!("{}:{input}", node_name(node)),
+        User::Result(region, result) => format!("r{}_results:{result}", region.as_u32()),
+    }
+}
+
+impl Visitor for DotCtx {
+    fn visit_node(&mut self, walker: &Walker, id: id::AnyNode) {
+        let ctx = walker.ctx;
+        let node = &ctx.nodes[id];
+
+        let mut label = node.kind.node_type().to_string();
+        if let Some(payload) = node.kind.payload() {
+            write!(label, " {payload}").unwrap();
+        }
+        if let Some(name) = ctx.symbols.get(id).filter(|name| !name.is_empty()) {
+            write!(label, "\n{name}").unwrap();
+        }
+
+        let inputs = ports(ctx.inputs(id), |&i| {
+            ctx.user_type(User::Input(id, i)).map(|ty| ty.to_string())
+        });
+        let outputs = ports(ctx.outputs(id), |&o| {
+            ctx.origin_type(Origin::Output(id, o))
+                .map(|ty| ty.to_string())
+        });
+        let fields: Vec<_> = [inputs, Some(escape(&label)), outputs]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if ctx.regions(id).is_empty() {
+            self.record(walker, &node_name(id), &fields);
+        } else {
+            self.line(walker, format!("subgraph cluster_{} {{", node_name(id)));
+            self.line(
+                walker,
+                format!("  label=\"{}\";", quote(&label.replace('\n', " "))),
+            );
+            self.record(walker, &format!("  {}", node_name(id)), &fields);
+        }
+    }
+
+    fn leave_node(&mut self, walker: &Walker, id: id::AnyNode) {
+        if !walker.ctx.regions(id).is_empty() {
+            self.line(walker, "}");
+        }
+    }
+
+    fn enter_region(&mut self, walker: &Walker, region: id::Region) {
+        let ctx = walker.ctx;
+        let name = format!("r{}", region.as_u32());
+
+        self.line(walker, format!("subgraph cluster_{name} {{"));
+        self.line(walker, format!("  label=\"{name}\";"));
+
+        let arguments = ports(ctx.arguments(region), |&a| {
+            ctx.origin_type(Origin::Argument(region, a))
+                .map(|ty| ty.to_string())
+        });
+        if let Some(arguments) = arguments {
+            self.record(walker, &format!("  {name}_arguments"), &[arguments]);
+        }
+
+        let results = ports(ctx.results(region), |&r| {
+            ctx.user_type(User::Result(region, r))
+                .map(|ty| ty.to_string())
+        });
+        if let Some(results) = results {
+            self.record(walker, &format!("  {name}_results"), &[results]);
+        }
+    }
+
+    fn leave_region(&mut self, walker: &Walker, region: id::Region) {
+        for edge in &walker.ctx.regions[region].edges {
+            let (from, to) = (origin_name(edge.origin), user_name(edge.user));
+            self.line(walker, format!("  {from} -> {to};"));
+        }
+
+        self.line(walker, "}");
+    }
+}
+
+impl TranslationUnitContext {
+    /// Render the graph in the Graphviz DOT format.
+    ///
+    /// Each node with regions and each region is drawn as a cluster, with the ports of nodes and
+    /// regions as the fields of record nodes.
+    pub fn to_dot(&self) -> String {
+        let mut ctx = DotCtx {
+            dot: String::from("digraph rvsdg {\n  compound=true;\n"),
+        };
+        self.walk(Order::PreOrder, &mut ctx);
+        ctx.dot.push_str("}\n");
+        ctx.dot
+    }
+}
@@ -1,18 +1,21 @@
 use super::id;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Edge {
     pub origin: Origin,
     pub user: User,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Copy)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum User {
     Input(id::AnyNode, id::Input),
     Result(id::Region, id::Result),
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Origin {
     Output(id::AnyNode, id::Output),
     Argument(id::Region, id::Argument),
@@ -30,13 +33,13 @@ pub struct Output<K> {
     pub id: id::Output,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Argument {
     pub region: id::Region,
     pub id: id::Argument,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Result {
     pub region: id::Region,
     pub id: id::Result,
@@ -62,18 +65,12 @@ impl<K> Input<K> {
 
 impl<K> Clone for Input<K> {
     fn clone(&self) -> Self {
-        Input {
-            node: self.node,
-            id: self.id,
-        }
+        *self
     }
 }
 impl<K> Clone for Output<K> {
     fn clone(&self) -> Self {
-        Output {
-            node: self.node,
-            id: self.id,
-        }
+        *self
     }
 }
 
@@ -0,0 +1,186 @@
+use super::{Diagnostic, Origin, User, id};
+use std::fmt;
+
+/// Errors from the fallible `try_*` methods of [`crate::TranslationUnitContext`]
+///
+/// Most methods check everything up front, leaving the graph unmodified when they fail. The
+/// exceptions are noted on the method or the error, e.g. [`Error::NodeAddedByInitializer`] and
+/// [`TranslationUnitContext::try_connect`](crate::TranslationUnitContext::try_connect).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// There's no path from the origin to the current region of the user
+    NoPath { origin: Origin, user: User },
+    /// There's no such node
+    NoSuchNode { node: id::AnyNode },
+    /// There's no such region
+    NoSuchRegion { region: id::Region },
+    /// The output or argument doesn't exist
+    NoSuchOrigin { origin: Origin },
+    /// The input or result doesn't exist
+    NoSuchUser { user: User },
+    /// The initializer passed to `add_node` created other nodes, which are left in the graph
+    NodeAddedByInitializer { node: id::AnyNode },
+    /// The node is not of the kind the operation expected
+    WrongNodeKind {
+        node: id::AnyNode,
+        expected: &'static str,
+        found: String,
+    },
+    /// The node is not in the region the operation expected it to be in
+    NodeNotInRegion {
+        node: id::AnyNode,
+        region: id::Region,
+    },
+    /// The node does not have the amount of regions the operation expected
+    RegionCountMismatch {
+        node: id::AnyNode,
+        expected: usize,
+        found: usize,
+    },
+    /// The region is not the region of a recenv
+    NotInRecEnv { region: id::Region },
+    /// The connection would form a cycle through a node which isn't a lambda
+    Cycle {
+        origin: Origin,
+        user: User,
+        through: id::AnyNode,
+    },
+    /// Gamma nodes need at least one branch
+    NoBranches,
+    /// There's no edge between the origin and the user
+    NoSuchEdge { origin: Origin, user: User },
+    /// The port is required by the kind of the node and can not be removed
+    RequiredPort { node: id::AnyNode },
+    /// The ports of dowhile nodes and their regions can only be added as loop variables
+    LoopVarPort { node: id::AnyNode },
+    /// The nodes of the region depend on each other, along with the nodes depending on those
+    CyclicRegion {
+        region: id::Region,
+        nodes: Vec<id::AnyNode>,
+    },
+    /// The graph is not well-formed after running the pass
+    VerifyFailed {
+        pass: String,
+        diagnostics: Vec<Diagnostic>,
+    },
+    /// The type of the origin is not the type of the user
+    TypeMismatch {
+        origin: Origin,
+        user: User,
+        expected: String,
+        found: String,
+    },
+    /// The callee of the apply node can't be traced back to a lambda
+    UnknownCallee { apply: id::AnyNode },
+    /// The apply node doesn't match the parameters or results of the called lambda
+    ArityMismatch {
+        apply: id::AnyNode,
+        expected: u32,
+        found: u32,
+    },
+    /// The region does not have the amount of arguments the operation expected
+    ArgumentCountMismatch {
+        region: id::Region,
+        expected: usize,
+        found: usize,
+    },
+    /// The operation needs the user to be connected
+    Unconnected { user: User },
+    /// The node has regions which can't be lowered to a control-flow graph, or a dowhile region
+    /// lacks the predicate
+    NotLowerable { node: id::AnyNode },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NoPath { origin, user } => {
+                write!(f, "no available path to connect {origin:?} → {user:?}")
+            }
+            Error::NoSuchNode { node } => write!(f, "there's no node {node}"),
+            Error::NoSuchRegion { region } => write!(f, "there's no region {region}"),
+            Error::NoSuchOrigin { origin } => write!(f, "there's no origin {origin:?}"),
+            Error::NoSuchUser { user } => write!(f, "there's no user {user:?}"),
+            Error::NodeAddedByInitializer { node } => {
+                write!(
+                    f,
+                    "the initializer of {node} is not allowed to create additional nodes"
+                )
+            }
+            Error::WrongNodeKind {
+                node,
+                expected,
+                found,
+            } => write!(f, "{node} is a {found} node, expected {expected}"),
+            Error::NodeNotInRegion { node, region } => write!(f, "{node} is not in {region}"),
+            Error::RegionCountMismatch {
+                node,
+                expected,
+                found,
+            } => write!(f, "{node} has {found} regions, expected {expected}"),
+            Error::NotInRecEnv { region } => write!(f, "{region} is not the region of a recenv"),
+            Error::Cycle {
+                origin,
+                user,
+                through,
+            } => write!(
+                f,
+                "{origin:?} → {user:?} forms a cycle through non-lambda {through}"
+            ),
+            Error::NoBranches => write!(f, "gamma nodes need at least one branch"),
+            Error::NoSuchEdge { origin, user } => {
+                write!(f, "there's no edge {origin:?} → {user:?}")
+            }
+            Error::RequiredPort { node } => {
+                write!(
+                    f,
+                    "port is required by the kind of {node} and can not be removed"
+                )
+            }
+            Error::LoopVarPort { node } => {
+                write!(f, "the ports of {node} can only be added as loop variables")
+            }
+            Error::CyclicRegion { region, nodes } => {
+                write!(f, "the nodes {nodes:?} of {region} form a cycle")
+            }
+            Error::VerifyFailed { pass, diagnostics } => {
+                write!(f, "the graph is not well-formed after {pass}:")?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {diagnostic}")?;
+                }
+                Ok(())
+            }
+            Error::TypeMismatch {
+                origin,
+                user,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{origin:?} → {user:?} connects a {found} to a port of type {expected}"
+            ),
+            Error::UnknownCallee { apply } => {
+                write!(f, "the callee of {apply} is not a known lambda")
+            }
+            Error::ArityMismatch {
+                apply,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{apply} has {found} ports where the called lambda expects {expected}"
+            ),
+            Error::ArgumentCountMismatch {
+                region,
+                expected,
+                found,
+            } => write!(f, "{region} has {expected} arguments, got {found}"),
+            Error::Unconnected { user } => write!(f, "{user:?} is not connected"),
+            Error::NotLowerable { node } => {
+                write!(f, "{node} can't be lowered to a control-flow graph")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,212 @@
+use super::*;
+
+// Constant folding
+//
+// Regions are visited from omega and inwards. Nodes are folded in topological order so that the
+// nodes they use have already been folded. Numbers used within a region are then recreated in the
+// region, leaving the inputs they were forwarded through for dead node elimination.
+
+impl TranslationUnitContext {
+    /// Replace nodes whose inputs are all numbers by the numbers they evaluate to.
+    ///
+    /// Only nodes of kinds which implement [`Fold`](crate::nodes::Fold) are evaluated. Numbers
+    /// forwarded into regions are recreated within them and gammas with a number as predicate are
+    /// replaced by the selected branch. Returns whether anything changed.
+    pub fn fold_constants(&mut self) -> bool {
+        let omega = self.get(self.omega()).region;
+        self.fold_constants_in(omega)
+    }
+
+    fn fold_constants_in(&mut self, region: id::Region) -> bool {
+        let mut changed = false;
+
+        // The nodes of a selected branch end up in this region, and might be foldable themselves
+        while self.fold_nodes(region) {
+            changed = true;
+        }
+
+        for node in self.nodes(region).collect::<Vec<_>>() {
+            changed |= self.propagate_constants(node);
+
+            for inner in self.regions(node).to_vec() {
+                changed |= self.fold_constants_in(inner);
+            }
+        }
+
+        changed
+    }
+
+    fn fold_nodes(&mut self, region: id::Region) -> bool {
+        let nodes: Vec<_> = match self.try_topological_nodes(region) {
+            Ok(nodes) => nodes.collect(),
+            Err(_) => self.nodes(region).collect(),
+        };
+
+        let mut changed = false;
+        for node in nodes {
+            changed |= match self.try_cast::<Gamma>(node) {
+                Ok(gamma) => self.fold_gamma(gamma),
+                Err(_) => self.fold_node(node),
+            };
+        }
+        changed
+    }
+
+    fn fold_node(&mut self, node: id::AnyNode) -> bool {
+        let Some(fold) = self.nodes[node].kind.as_fold() else {
+            return false;
+        };
+
+        let Some(inputs) = self
+            .inputs(node)
+            .map(|input| self.constant_of(User::Input(node, input)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return false;
+        };
+
+        let Some(values) = fold.fold(&inputs) else {
+            return false;
+        };
+
+        let outputs = self.nodes[node].outputs as usize;
+        if values.len() != outputs {
+            trace!(
+                "not folding {} as it gave {} values for {outputs} outputs",
+                self.debug_node(node),
+                values.len()
+            );
+            return false;
+        }
+
+        trace!("folding {} into {values:?}", self.debug_node(node));
+
+        let region = self.nodes[node].region;
+        for (output, value) in self.outputs(node).zip(values) {
+            let from = Origin::Output(node, output);
+            let number = self.in_region(region, |ctx| ctx.add_number_node(value));
+            if let Some(ty) = self.origin_type(from).cloned() {
+                self.set_origin_type(number, ty);
+            }
+            self.redirect_edges(region, from, number.into());
+        }
+
+        self.remove_node(node);
+
+        true
+    }
+
+    // Replace a gamma with a number as predicate by a copy of the selected branch
+    fn fold_gamma(&mut self, gamma: id::Node<Gamma>) -> bool {
+        let Some(predicate) = self.constant_of(User::Input(gamma.id, id::Input::from_u32(0)))
+        else {
+            return false;
+        };
+
+        let Some(&branch) = usize::try_from(predicate)
+            .ok()
+            .and_then(|i| self.regions(gamma.id).get(i))
+        else {
+            return false;
+        };
+
+        let Some(arguments) = self
+            .inputs(gamma.id)
+            .map(|input| self.origin_of(User::Input(gamma.id, input)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return false;
+        };
+
+        if self
+            .results(branch)
+            .any(|result| self.origin_of(User::Result(branch, result)).is_none())
+        {
+            return false;
+        }
+
+        trace!(
+            "folding {} into branch {predicate}",
+            self.debug_node(gamma.id)
+        );
+
+        let region = self.nodes[gamma.id].region;
+        let mapping = self.copy_region_into(branch, region, &arguments);
+
+        for (output, origin) in self.outputs(gamma.id).zip(mapping.results) {
+            self.redirect_edges(region, Origin::Output(gamma.id, output), origin.unwrap());
+        }
+
+        self.remove_node(gamma.id);
+
+        true
+    }
+
+    // Recreate the numbers a node forwards into its regions, so that folding within the regions
+    // can see them.
+    fn propagate_constants(&mut self, node: id::AnyNode) -> bool {
+        let theta = self.try_cast::<DoWhile>(node).ok();
+
+        let mut changed = false;
+        for input in self.inputs(node) {
+            let Some(value) = self.constant_of(User::Input(node, input)) else {
+                continue;
+            };
+
+            // Loop variables changed by the loop only start out as the number
+            if let Some(theta) = theta
+                && !self.is_invariant(theta, input)
+            {
+                continue;
+            }
+
+            let ty = self.user_type(User::Input(node, input)).cloned();
+
+            for region in self.regions(node).to_vec() {
+                let argument = self.input_as_argument_in(
+                    Input {
+                        node: id::Node::<id::AnyNode>::new(node),
+                        id: input,
+                    },
+                    region,
+                );
+                let from = Origin::from(argument);
+
+                // The result keeps the loop variable invariant
+                let users: Vec<_> = self
+                    .users(from)
+                    .filter(|user| theta.is_none() || matches!(user, User::Input(..)))
+                    .collect();
+                if users.is_empty() {
+                    continue;
+                }
+
+                let number = self.in_region(region, |ctx| ctx.add_number_node(value));
+                if let Some(ty) = ty.clone() {
+                    self.set_origin_type(number, ty);
+                }
+
+                let to = Origin::from(number);
+                self.rewrite_edges(region, |edge| {
+                    if edge.origin == from && users.contains(&edge.user) {
+                        edge.origin = to;
+                    }
+                });
+
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn constant_of(&self, user: User) -> Option<i128> {
+        match self.origin_of(user)? {
+            Origin::Output(node, _) => {
+                let kind = self.nodes[node].kind.as_any();
+                kind.downcast_ref::<Number>().map(|number| number.0)
+            }
+            Origin::Argument(..) => None,
+        }
+    }
+}
@@ -3,30 +3,53 @@ use std::marker::PhantomData;
 
 use cranelift_entity::entity_impl;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct AnyNode(u32);
 entity_impl!(AnyNode, "node");
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Region(u32);
 entity_impl!(Region, "region");
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Argument(u32);
 entity_impl!(Argument, "a");
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Result(u32);
 entity_impl!(Result, "r");
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Input(u32);
 entity_impl!(Input, "i");
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Output(u32);
 entity_impl!(Output, "o");
 
+// Ids are serialized as their number
+#[cfg(feature = "serde")]
+macro_rules! serde_impl {
+    ($($ty:ident),*) => {
+        $(
+            impl serde::Serialize for $ty {
+                fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+                    s.serialize_u32(self.0)
+                }
+            }
+
+            impl<'de> serde::Deserialize<'de> for $ty {
+                fn deserialize<D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+                    u32::deserialize(d).map($ty)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "serde")]
+serde_impl!(AnyNode, Region, Argument, Result, Input, Output);
+
 #[derive(Debug)]
 pub struct Node<K> {
     pub id: AnyNode,
@@ -35,7 +58,7 @@ pub struct Node<K> {
 
 impl<K> Clone for Node<K> {
     fn clone(&self) -> Self {
-        Self::new(self.id)
+        *self
     }
 }
 impl<K> Copy for Node<K> {}
@@ -55,6 +78,21 @@ impl From<AnyNode> for Node<AnyNode> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl<K> serde::Serialize for Node<K> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        self.id.serialize(s)
+    }
+}
+
+// Only untyped ids can be deserialized, since nothing checks the kind of the node
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Node<AnyNode> {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        AnyNode::deserialize(d).map(Node::new)
+    }
+}
+
 impl<K> fmt::Display for Node<K> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.id.fmt(f)
@@ -0,0 +1,178 @@
+use super::*;
+
+impl TranslationUnitContext {
+    /// Replace a call with a copy of the body of the called lambda.
+    ///
+    /// The callee has to be traceable to a lambda through forwarded arguments, unchanged loop
+    /// variables and recursion arguments. Context variables of the lambda are routed into the
+    /// region of the apply node.
+    pub fn inline(&mut self, apply: id::Node<Apply>) {
+        if let Err(err) = self.try_inline(apply) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_inline(&mut self, apply: id::Node<Apply>) -> std::result::Result<(), Error> {
+        let lambda = self.callee(apply)?;
+
+        let body = self.region(lambda.id);
+        let region = self.nodes[apply.id].region;
+
+        let context = self.nodes[lambda.id].inputs;
+        let params = self.regions[body].arguments - context;
+        let arguments = self.nodes[apply.id].inputs - 1;
+        if arguments != params {
+            return Err(Error::ArityMismatch {
+                apply: apply.id,
+                expected: params,
+                found: arguments,
+            });
+        }
+
+        let outputs = self.nodes[apply.id].outputs;
+        let results = self.regions[body].results;
+        if outputs > results {
+            return Err(Error::ArityMismatch {
+                apply: apply.id,
+                expected: results,
+                found: outputs,
+            });
+        }
+
+        let mut replacements = vec![];
+        for input in self.inputs(apply.id).skip(1) {
+            let user = User::Input(apply.id, input);
+            replacements.push(self.origin_of(user).ok_or(Error::Unconnected { user })?);
+        }
+
+        let mut context_origins = vec![];
+        for input in self.inputs(lambda.id) {
+            let user = User::Input(lambda.id, input);
+            let origin = self.origin_of(user).ok_or(Error::Unconnected { user })?;
+
+            // Recursion arguments are only available within the recenv, so refer to the sibling
+            // lambda instead which is reachable through the recenv output from outside of it.
+            let origin = self.in_region(region, |ctx| {
+                ctx.resolve_recenv_origin(ctx.recursion_lambda(origin))
+            });
+            let origin_region = match origin {
+                Origin::Output(node, _) => self.nodes[node].region,
+                Origin::Argument(region, _) => region,
+            };
+            if !self.region_is_within(region, origin_region) {
+                return Err(Error::NoPath { origin, user });
+            }
+
+            context_origins.push(origin);
+        }
+
+        for result in self.results(body).take(outputs as usize) {
+            let user = User::Result(body, result);
+            self.origin_of(user).ok_or(Error::Unconnected { user })?;
+        }
+
+        trace!("inlining {} into {region}", self.debug_node(lambda.id));
+
+        for origin in context_origins {
+            let routed = self
+                .in_region(region, |ctx| ctx.route_origin(origin))
+                .expect("checked to be reachable before inlining");
+            replacements.push(routed);
+        }
+
+        let results = self.copy_region_into(body, region, &replacements).results;
+
+        for (output, origin) in self.outputs(apply.id).zip(results) {
+            let from = Origin::Output(apply.id, output);
+            let to = origin.unwrap();
+            self.rewrite_edges(region, |edge| {
+                if edge.origin == from {
+                    edge.origin = to;
+                }
+            });
+        }
+
+        self.remove_node(apply.id);
+
+        Ok(())
+    }
+
+    /// The lambda called by an apply node
+    fn callee(&self, apply: id::Node<Apply>) -> std::result::Result<id::Node<Lambda>, Error> {
+        let unknown = Error::UnknownCallee { apply: apply.id };
+
+        let mut origin = self.origin_of(User::Input(apply.id, id::Input::from_u32(0)));
+        loop {
+            match origin {
+                Some(Origin::Output(node, _)) if self.is_kind::<Lambda>(node) => {
+                    return Ok(id::Node::new(node));
+                }
+                Some(Origin::Output(node, output)) if self.is_kind::<RecEnv>(node) => {
+                    let env = self.get::<RecEnv>(id::Node::new(node));
+                    return env
+                        .lambdas
+                        .iter()
+                        .find(|(_, (_, o))| *o == output)
+                        .map(|(&lambda, _)| id::Node::new(lambda))
+                        .ok_or(unknown);
+                }
+                Some(Origin::Argument(region, argument)) => {
+                    let Some(container) = self.regions[region].container_node else {
+                        return Err(unknown);
+                    };
+
+                    if let Some(input) = self.argument_as_input(region, argument) {
+                        if let Ok(theta) = self.try_cast::<DoWhile>(container)
+                            && !self.is_invariant(theta, input.id)
+                        {
+                            return Err(unknown);
+                        }
+                        origin = self.origin_of(User::Input(container, input.id));
+                    } else if let Ok(env) = self.try_cast::<RecEnv>(container) {
+                        return self
+                            .get(env)
+                            .lambdas
+                            .iter()
+                            .find(|(_, (a, _))| *a == argument)
+                            .map(|(&lambda, _)| id::Node::new(lambda))
+                            .ok_or(unknown);
+                    } else {
+                        return Err(unknown);
+                    }
+                }
+                _ => return Err(unknown),
+            }
+        }
+    }
+
+    // The output of the lambda a recursion argument stands for, or the origin itself if it isn't one
+    fn recursion_lambda(&self, origin: Origin) -> Origin {
+        let Origin::Argument(region, argument) = origin else {
+            return origin;
+        };
+
+        let lambda = self.regions[region]
+            .container_node
+            .and_then(|env| self.try_cast::<RecEnv>(env).ok())
+            .and_then(|env| {
+                self.get(env)
+                    .lambdas
+                    .iter()
+                    .find(|(_, (a, _))| *a == argument)
+                    .map(|(&lambda, _)| id::Node::new(lambda))
+            });
+
+        match lambda {
+            Some(lambda) => self.lambda_output_of(lambda).into(),
+            None => origin,
+        }
+    }
+
+    // Make an origin from an enclosing region available in the current region
+    fn route_origin(&mut self, origin: Origin) -> Option<Origin> {
+        match self.resolve_recenv_origin(origin) {
+            Origin::Output(node, output) => self.find_and_connect_output(node, output),
+            Origin::Argument(region, id) => self.find_and_connect_argument(Argument { region, id }),
+        }
+    }
+}
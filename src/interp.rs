@@ -0,0 +1,455 @@
+//! A reference interpreter evaluating the graph directly.
+//!
+//! Evaluation is demand-driven; a node is evaluated once the value of one of its outputs is needed,
+//! and only once for each time its region is entered. Lambdas capture the region they're evaluated
+//! in, and only evaluate their context variables once called.
+
+use crate::nodes::*;
+use crate::{Origin, TranslationUnitContext, User, id};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// A value computed by the interpreter
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(i128),
+    Function(Function),
+    /// The value of state edges, see [`crate::State`]
+    State,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Function(function) => write!(f, "λ{}", function.lambda),
+            Value::State => write!(f, "state"),
+        }
+    }
+}
+
+/// A lambda along with the region it was evaluated in
+#[derive(Clone)]
+pub struct Function {
+    lambda: id::AnyNode,
+    frame: Rc<Frame>,
+}
+
+impl Function {
+    pub fn lambda(&self) -> id::Node<Lambda> {
+        id::Node::new(self.lambda)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("lambda", &self.lambda)
+            .finish()
+    }
+}
+
+/// Functions are the same when they're of the same lambda
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.lambda == other.lambda
+    }
+}
+
+/// Evaluation of custom node kinds.
+///
+/// Given the values of the inputs, returns the values of the outputs. Kinds without this which
+/// implement [`Fold`] are evaluated by folding when all inputs are numbers. Opted into using
+/// `node_kind_impl!(.., evaluate)`.
+pub trait Evaluate {
+    fn evaluate(&self, inputs: &[Value]) -> std::result::Result<Vec<Value>, String>;
+}
+
+/// Errors from evaluating the graph
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// There's no lambda or global with the symbol in omega or a recenv within it
+    UnknownSymbol(String),
+    /// The node is called but isn't a function
+    NotAFunction { node: id::AnyNode, found: Value },
+    /// The function is called with the wrong amount of arguments
+    ArityMismatch {
+        lambda: id::AnyNode,
+        expected: usize,
+        found: usize,
+    },
+    /// The user needs to be evaluated but has no origin
+    Unconnected { user: User },
+    /// The argument has no value, such as an argument of omega
+    UnboundArgument {
+        region: id::Region,
+        argument: id::Argument,
+    },
+    /// The kind of the node implements neither [`Evaluate`] nor [`Fold`]
+    NotEvaluable { node: id::AnyNode, kind: String },
+    /// The predicate of a gamma or dowhile node doesn't select a branch or whether to iterate
+    InvalidPredicate { node: id::AnyNode, found: Value },
+    /// The node produced a different amount of values than it has outputs
+    OutputCountMismatch {
+        node: id::AnyNode,
+        expected: usize,
+        found: usize,
+    },
+    /// Evaluating a custom node kind failed
+    Failed { node: id::AnyNode, message: String },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UnknownSymbol(symbol) => write!(f, "there's no function named {symbol}"),
+            EvalError::NotAFunction { node, found } => {
+                write!(f, "{node} calls {found}, which is not a function")
+            }
+            EvalError::ArityMismatch {
+                lambda,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{lambda} takes {expected} arguments but was given {found}"
+            ),
+            EvalError::Unconnected { user } => write!(f, "{user:?} is not connected"),
+            EvalError::UnboundArgument { region, argument } => {
+                write!(f, "{argument} of {region} has no value")
+            }
+            EvalError::NotEvaluable { node, kind } => {
+                write!(f, "{node} is a {kind} node, which can not be evaluated")
+            }
+            EvalError::InvalidPredicate { node, found } => {
+                write!(f, "{found} is not a valid predicate for {node}")
+            }
+            EvalError::OutputCountMismatch {
+                node,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{node} has {expected} outputs but evaluated to {found} values"
+            ),
+            EvalError::Failed { node, message } => write!(f, "evaluating {node} failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+type Result<T> = std::result::Result<T, EvalError>;
+
+// An evaluation of a region
+struct Frame {
+    region: id::Region,
+    // The recursion arguments of recenv regions are left as `None`
+    arguments: Vec<Option<Value>>,
+    values: RefCell<HashMap<id::AnyNode, Rc<[Value]>>>,
+}
+
+impl Frame {
+    fn new(region: id::Region, arguments: Vec<Option<Value>>) -> Rc<Self> {
+        Rc::new(Frame {
+            region,
+            arguments,
+            values: RefCell::default(),
+        })
+    }
+}
+
+/// Evaluates the functions of a translation unit.
+///
+/// Globals are initialized once per interpreter, the first time they're used.
+pub struct Interpreter<'ctx> {
+    ctx: &'ctx TranslationUnitContext,
+    omega: Rc<Frame>,
+}
+
+impl<'ctx> Interpreter<'ctx> {
+    pub fn new(ctx: &'ctx TranslationUnitContext) -> Self {
+        let region = ctx.get(ctx.omega()).region;
+        let arguments = vec![None; ctx.arguments(region).count()];
+        Interpreter {
+            ctx,
+            omega: Frame::new(region, arguments),
+        }
+    }
+
+    /// Call the lambda with the symbol, returning the values of its results.
+    ///
+    /// The lambda has to be in omega or a recenv within omega.
+    pub fn call(&mut self, symbol: &str, arguments: Vec<Value>) -> Result<Vec<Value>> {
+        let (node, origin) = self.find_symbol(symbol)?;
+        match self.output(&self.omega.clone(), origin)? {
+            Value::Function(function) => self.apply(function, arguments),
+            found => Err(EvalError::NotAFunction { node, found }),
+        }
+    }
+
+    // The origin in omega of the node with the symbol
+    fn find_symbol(&self, symbol: &str) -> Result<(id::AnyNode, Origin)> {
+        let ctx = self.ctx;
+        let named = |node: &id::AnyNode| ctx.symbols[*node] == symbol;
+
+        if let Some(node) = ctx.nodes(self.omega.region).find(named) {
+            return Ok((node, Origin::Output(node, id::Output::from_u32(0))));
+        }
+
+        for env in ctx.nodes(self.omega.region) {
+            let Ok(env) = ctx.try_cast::<RecEnv>(env) else {
+                continue;
+            };
+            if let Some(node) = ctx.nodes(ctx.region(env.id)).find(named)
+                && let Some(&(_, output)) = ctx.get(env).lambdas.get(&node)
+            {
+                return Ok((node, Origin::Output(env.id, output)));
+            }
+        }
+
+        Err(EvalError::UnknownSymbol(symbol.to_string()))
+    }
+
+    fn apply(&mut self, function: Function, arguments: Vec<Value>) -> Result<Vec<Value>> {
+        let ctx = self.ctx;
+        let region = ctx.region(function.lambda);
+
+        let context = ctx.inputs(function.lambda).count();
+        let expected = ctx.arguments(region).count() - context;
+        if arguments.len() != expected {
+            return Err(EvalError::ArityMismatch {
+                lambda: function.lambda,
+                expected,
+                found: arguments.len(),
+            });
+        }
+
+        // Context variables are evaluated where the lambda was
+        let mut values: Vec<_> = arguments.into_iter().map(Some).collect();
+        for value in self.inputs(&function.frame, function.lambda)? {
+            values.push(Some(value));
+        }
+
+        let frame = Frame::new(region, values);
+        self.results(&frame)
+    }
+
+    fn results(&mut self, frame: &Rc<Frame>) -> Result<Vec<Value>> {
+        let ctx = self.ctx;
+        ctx.results(frame.region)
+            .map(|result| {
+                let user = User::Result(frame.region, result);
+                let origin = ctx.origin_of(user).ok_or(EvalError::Unconnected { user })?;
+                self.output(frame, origin)
+            })
+            .collect()
+    }
+
+    fn inputs(&mut self, frame: &Rc<Frame>, node: id::AnyNode) -> Result<Vec<Value>> {
+        let ctx = self.ctx;
+        ctx.inputs(node)
+            .map(|input| {
+                let user = User::Input(node, input);
+                let origin = ctx.origin_of(user).ok_or(EvalError::Unconnected { user })?;
+                self.output(frame, origin)
+            })
+            .collect()
+    }
+
+    fn output(&mut self, frame: &Rc<Frame>, origin: Origin) -> Result<Value> {
+        match origin {
+            Origin::Argument(region, argument) => {
+                debug_assert_eq!(region, frame.region);
+                match &frame.arguments[argument.as_u32() as usize] {
+                    Some(value) => Ok(value.clone()),
+                    None => self.recursion_argument(frame, argument),
+                }
+            }
+            Origin::Output(node, output) => {
+                let cached = frame.values.borrow().get(&node).cloned();
+                let values = match cached {
+                    Some(values) => values,
+                    None => {
+                        let values: Rc<[Value]> = self.node(frame, node)?.into();
+                        frame.values.borrow_mut().insert(node, values.clone());
+                        values
+                    }
+                };
+                Ok(values[output.as_u32() as usize].clone())
+            }
+        }
+    }
+
+    fn recursion_argument(&mut self, frame: &Rc<Frame>, argument: id::Argument) -> Result<Value> {
+        let ctx = self.ctx;
+        let unbound = EvalError::UnboundArgument {
+            region: frame.region,
+            argument,
+        };
+
+        let env = ctx.regions[frame.region]
+            .container_node
+            .ok_or(unbound.clone())?;
+        let env = ctx.try_cast::<RecEnv>(env).map_err(|_| unbound.clone())?;
+        let lambda = ctx
+            .get(env)
+            .lambdas
+            .iter()
+            .find(|(_, (a, _))| *a == argument)
+            .map(|(&lambda, _)| lambda)
+            .ok_or(unbound)?;
+
+        Ok(Value::Function(Function {
+            lambda,
+            frame: frame.clone(),
+        }))
+    }
+
+    fn recenv_frame(&mut self, frame: &Rc<Frame>, env: id::AnyNode) -> Result<Rc<Frame>> {
+        let ctx = self.ctx;
+        let region = ctx.region(env);
+        let recursion = ctx.arguments(region).count() - ctx.inputs(env).count();
+
+        let mut arguments = vec![None; recursion];
+        for value in self.inputs(frame, env)? {
+            arguments.push(Some(value));
+        }
+
+        Ok(Frame::new(region, arguments))
+    }
+
+    fn node(&mut self, frame: &Rc<Frame>, node: id::AnyNode) -> Result<Vec<Value>> {
+        let ctx = self.ctx;
+        let kind = &ctx.nodes[node].kind;
+
+        let values = if ctx.try_cast::<Lambda>(node).is_ok() {
+            vec![Value::Function(Function {
+                lambda: node,
+                frame: frame.clone(),
+            })]
+        } else if let Ok(env) = ctx.try_cast::<RecEnv>(node) {
+            let env_frame = self.recenv_frame(frame, node)?;
+            let mut lambdas: Vec<_> = ctx.get(env).lambdas.iter().collect();
+            lambdas.sort_by_key(|(_, (_, output))| *output);
+            lambdas
+                .into_iter()
+                .map(|(&lambda, _)| {
+                    Value::Function(Function {
+                        lambda,
+                        frame: env_frame.clone(),
+                    })
+                })
+                .collect()
+        } else if let Some(Number(n)) = kind.as_any().downcast_ref() {
+            vec![Value::Number(*n)]
+        } else {
+            let inputs = self.inputs(frame, node)?;
+            self.evaluate(node, kind.as_ref(), inputs)?
+        };
+
+        let expected = ctx.outputs(node).count();
+        if values.len() != expected {
+            return Err(EvalError::OutputCountMismatch {
+                node,
+                expected,
+                found: values.len(),
+            });
+        }
+
+        Ok(values)
+    }
+
+    fn evaluate(
+        &mut self,
+        node: id::AnyNode,
+        kind: &dyn NodeKind,
+        inputs: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        let ctx = self.ctx;
+        let arguments = || inputs.iter().cloned().map(Some).collect::<Vec<_>>();
+
+        if kind.as_any().is::<Apply>() {
+            let mut inputs = inputs.into_iter();
+            return match inputs.next() {
+                Some(Value::Function(function)) => self.apply(function, inputs.collect()),
+                Some(found) => Err(EvalError::NotAFunction { node, found }),
+                None => Err(EvalError::Unconnected {
+                    user: User::Input(node, id::Input::from_u32(0)),
+                }),
+            };
+        }
+
+        if kind.as_any().is::<GlobalV>() {
+            let frame = Frame::new(ctx.region(node), arguments());
+            return self.results(&frame);
+        }
+
+        if kind.as_any().is::<Gamma>() {
+            let branch = match inputs.first() {
+                Some(Value::Number(n)) => usize::try_from(*n)
+                    .ok()
+                    .and_then(|n| ctx.regions(node).get(n)),
+                _ => None,
+            };
+            let Some(&branch) = branch else {
+                let found = inputs[0].clone();
+                return Err(EvalError::InvalidPredicate { node, found });
+            };
+            let frame = Frame::new(branch, arguments());
+            return self.results(&frame);
+        }
+
+        if kind.as_any().is::<DoWhile>() {
+            let body = ctx.region(node);
+            let mut values = inputs;
+            loop {
+                let frame = Frame::new(body, values.into_iter().map(Some).collect());
+                let mut results = self.results(&frame)?.into_iter();
+                let predicate = results
+                    .next()
+                    .expect("dowhile regions start with the predicate");
+                values = results.collect();
+                match predicate {
+                    Value::Number(0) => return Ok(values),
+                    Value::Number(_) => {}
+                    found => return Err(EvalError::InvalidPredicate { node, found }),
+                }
+            }
+        }
+
+        if let Some(evaluate) = kind.as_evaluate() {
+            return evaluate
+                .evaluate(&inputs)
+                .map_err(|message| EvalError::Failed { node, message });
+        }
+
+        let numbers: Option<Vec<_>> = inputs
+            .iter()
+            .map(|value| match value {
+                Value::Number(n) => Some(*n),
+                _ => None,
+            })
+            .collect();
+
+        if let Some(fold) = kind.as_fold()
+            && let Some(values) = numbers.and_then(|numbers| fold.fold(&numbers))
+        {
+            return Ok(values.into_iter().map(Value::Number).collect());
+        }
+
+        Err(EvalError::NotEvaluable {
+            node,
+            kind: kind.node_type().to_string(),
+        })
+    }
+}
+
+impl TranslationUnitContext {
+    /// Call the lambda with the symbol using a new [`Interpreter`]
+    pub fn call(&self, symbol: &str, arguments: Vec<Value>) -> Result<Vec<Value>> {
+        Interpreter::new(self).call(symbol, arguments)
+    }
+}
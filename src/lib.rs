@@ -3,20 +3,90 @@ use std::collections::HashMap;
 use std::io::Write;
 use tracing::{info, trace};
 
+pub mod binary;
+pub mod cfg;
+mod cne;
+mod copy;
+pub use copy::Mapping;
+mod dne;
+mod dot;
 mod edge;
 pub use edge::{Argument, Edge, Input, Origin, Output, Result, User};
+mod error;
+pub use error::Error;
+mod fold;
 pub mod id;
+mod inline;
+pub mod interp;
 pub mod nodes;
 pub use nodes::NodeKind;
 use nodes::*;
+mod order;
+mod pass;
+pub use pass::{CommonNodeElimination, ConstantFolding, DeadNodeElimination, Pass, PassManager};
+mod registry;
+pub use registry::KindRegistry;
+mod remove;
+#[cfg(feature = "serde")]
+mod serialize;
+mod state;
+pub use state::{GammaState, State};
 #[cfg(test)]
 mod tests;
+mod text;
+pub use text::TextError;
+mod types;
+pub use types::{Type, ValueType};
+mod uses;
+mod verify;
+mod visit;
+pub use verify::Diagnostic;
+pub use visit::{Order, Scope, Visitor, Walker};
 mod xml;
-pub use xml::{new_xml, open_viewer};
-
+pub use xml::{XmlError, new_xml, open_viewer};
+
+/// Implement [`NodeKind`] for a type, which has to implement [`Clone`].
+///
+/// Optional capabilities can be listed after the name of the kind:
+///
+/// * `structural` allows nodes of the kind to be merged by common node elimination, requires
+///   [`PartialEq`] and [`Hash`](std::hash::Hash).
+/// * `fold` allows nodes of the kind to be evaluated by constant folding, requires
+///   [`Fold`](nodes::Fold).
+/// * `evaluate` allows nodes of the kind to be evaluated by the interpreter, requires
+///   [`Evaluate`](interp::Evaluate).
+/// * `payload` writes the kind to XML as its [`Display`](std::fmt::Display), so it can be
+///   recreated by a [`KindRegistry`] when read back.
+/// * `encode` saves the kind in binary through [`Encode`](nodes::Encode) rather than its payload,
+///   to be decoded by a [`KindRegistry`] when loaded.
 #[macro_export]
 macro_rules! node_kind_impl {
-    ($ty:ty, $kind:literal) => {
+    (@structural) => {
+        fn as_structural(&self) -> Option<&dyn $crate::nodes::StructuralEq> {
+            Some(self)
+        }
+    };
+    (@fold) => {
+        fn as_fold(&self) -> Option<&dyn $crate::nodes::Fold> {
+            Some(self)
+        }
+    };
+    (@payload) => {
+        fn payload(&self) -> Option<String> {
+            Some(self.to_string())
+        }
+    };
+    (@encode) => {
+        fn as_encode(&self) -> Option<&dyn $crate::nodes::Encode> {
+            Some(self)
+        }
+    };
+    (@evaluate) => {
+        fn as_evaluate(&self) -> Option<&dyn $crate::interp::Evaluate> {
+            Some(self)
+        }
+    };
+    ($ty:ty, $kind:literal $(, $cap:ident)*) => {
         impl NodeKind for $ty {
             fn as_any(&self) -> &dyn std::any::Any {
                 self
@@ -29,6 +99,12 @@ macro_rules! node_kind_impl {
             fn node_type(&self) -> &str {
                 $kind
             }
+
+            fn clone_box(&self) -> Box<dyn NodeKind + Send + Sync> {
+                Box::new(self.clone())
+            }
+
+            $($crate::node_kind_impl!(@$cap);)*
         }
     };
 }
@@ -41,6 +117,8 @@ pub struct TranslationUnitContext {
 
     symbols: SecondaryMap<id::AnyNode, String>,
 
+    uses: uses::Uses,
+
     node_id_pool: ListPool<id::AnyNode>,
     region_id_pool: ListPool<id::Region>,
 
@@ -50,11 +128,14 @@ pub struct TranslationUnitContext {
 #[derive(Debug)]
 pub struct Node {
     // Self-referential node id
+    #[allow(dead_code)]
     id: id::AnyNode,
     region: id::Region,
 
     inputs: u32,
     outputs: u32,
+    input_types: Vec<Option<Type>>,
+    output_types: Vec<Option<Type>>,
 
     regions: EntityList<id::Region>,
 
@@ -66,36 +147,87 @@ struct Region {
     container_node: Option<id::AnyNode>,
     arguments: u32,
     results: u32,
+    argument_types: Vec<Option<Type>>,
+    result_types: Vec<Option<Type>>,
 
     edges: Vec<Edge>,
 
     nodes: EntityList<id::AnyNode>,
 }
 
+impl Default for TranslationUnitContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TranslationUnitContext {
     pub fn new() -> Self {
         let mut omega = TranslationUnitContext {
             nodes: PrimaryMap::new(),
             regions: PrimaryMap::new(),
             symbols: SecondaryMap::new(),
+            uses: uses::Uses::default(),
             node_id_pool: ListPool::new(),
             region_id_pool: ListPool::new(),
             region: id::Region::from_u32(0),
         };
 
-        omega.add_region(0, 0);
+        // Omega's region has no container node, so that walking upwards through the regions ends
+        // there.
+        let region = omega.add_region(0, 0);
+        let regions = EntityList::from_slice(&[region], &mut omega.region_id_pool);
+        omega.nodes.push(Node {
+            id: id::AnyNode::from_u32(0),
+            region,
+            inputs: 0,
+            outputs: 0,
+            input_types: vec![],
+            output_types: vec![],
+            regions,
+            kind: Box::new(TranslationUnit { region }),
+        });
 
         omega
     }
 
+    /// The omega node, which contains the region of the whole translation unit
+    pub fn omega(&self) -> id::Node<TranslationUnit> {
+        id::Node::new(id::AnyNode::from_u32(0))
+    }
+
     pub fn get<K: NodeKind>(&self, id: id::Node<K>) -> &K {
+        self.try_get(id).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_get<K: NodeKind>(&self, id: id::Node<K>) -> std::result::Result<&K, Error> {
         get_kind(&self.nodes, id)
     }
 
     pub fn get_mut<K: NodeKind>(&mut self, id: id::Node<K>) -> &mut K {
+        self.try_get_mut(id).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_get_mut<K: NodeKind>(
+        &mut self,
+        id: id::Node<K>,
+    ) -> std::result::Result<&mut K, Error> {
         get_kind_mut(&mut self.nodes, id)
     }
 
+    /// Get a typed id for a node. Panics if the node is not of kind `K`
+    pub fn cast<K: NodeKind>(&self, node: id::AnyNode) -> id::Node<K> {
+        self.try_cast(node).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_cast<K: NodeKind>(
+        &self,
+        node: id::AnyNode,
+    ) -> std::result::Result<id::Node<K>, Error> {
+        let id = id::Node::new(node);
+        get_kind::<K>(&self.nodes, id).map(|_| id)
+    }
+
     pub fn in_region<T>(&mut self, region: id::Region, f: impl FnOnce(&mut Self) -> T) -> T {
         let previous = self.region;
         self.switch_region(region);
@@ -109,24 +241,16 @@ impl TranslationUnitContext {
     }
 
     pub fn inputs(&self, node: id::AnyNode) -> impl Iterator<Item = id::Input> + 'static {
-        (0..self.nodes[node].inputs)
-            .into_iter()
-            .map(|i| id::Input::from_u32(i))
+        (0..self.nodes[node].inputs).map(id::Input::from_u32)
     }
     pub fn outputs(&self, node: id::AnyNode) -> impl Iterator<Item = id::Output> + 'static {
-        (0..self.nodes[node].outputs)
-            .into_iter()
-            .map(|i| id::Output::from_u32(i))
+        (0..self.nodes[node].outputs).map(id::Output::from_u32)
     }
     pub fn arguments(&self, region: id::Region) -> impl Iterator<Item = id::Argument> + 'static {
-        (0..self.regions[region].arguments)
-            .into_iter()
-            .map(|i| id::Argument::from_u32(i))
+        (0..self.regions[region].arguments).map(id::Argument::from_u32)
     }
     pub fn results(&self, region: id::Region) -> impl Iterator<Item = id::Result> + 'static {
-        (0..self.regions[region].results)
-            .into_iter()
-            .map(|i| id::Result::from_u32(i))
+        (0..self.regions[region].results).map(id::Result::from_u32)
     }
     pub fn nodes(&self, region: id::Region) -> impl Iterator<Item = id::AnyNode> {
         self.regions[region]
@@ -137,46 +261,150 @@ impl TranslationUnitContext {
     }
 
     /// Create a new empty node of any kind and manually initialize it with `init`
-    pub fn add_node<const N: usize, F, K: NodeKind>(&mut self, init: F) -> id::Node<K>
+    pub fn add_node<F, R, K: NodeKind>(&mut self, init: F) -> id::Node<K>
     where
-        F: FnOnce(&mut Self, id::Node<K>) -> (K, [id::Region; N]),
+        F: FnOnce(&mut Self, id::Node<K>) -> (K, R),
+        R: IntoIterator<Item = id::Region>,
+    {
+        self.try_add_node(init)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_node<F, R, K: NodeKind>(
+        &mut self,
+        init: F,
+    ) -> std::result::Result<id::Node<K>, Error>
+    where
+        F: FnOnce(&mut Self, id::Node<K>) -> (K, R),
+        R: IntoIterator<Item = id::Region>,
     {
         let any_node_id = self.nodes.next_key();
         let node_id = id::Node::<K>::new(any_node_id);
 
         let (kind, regions) = init(self, node_id);
 
-        trace!("adding {node_id} in {}", self.region);
+        if self.nodes.next_key() != any_node_id {
+            return Err(Error::NodeAddedByInitializer { node: any_node_id });
+        }
+
+        self.push_node(Box::new(kind), regions);
+
+        Ok(node_id)
+    }
+
+    fn push_node(
+        &mut self,
+        kind: Box<dyn NodeKind + Send + Sync>,
+        regions: impl IntoIterator<Item = id::Region>,
+    ) -> id::AnyNode {
+        let any_node_id = self.nodes.next_key();
+
+        trace!("adding {any_node_id} in {}", self.region);
 
         let mut node = Node {
-            kind: Box::new(kind),
+            kind,
             region: self.region,
             inputs: 0,
             outputs: 0,
+            input_types: vec![],
+            output_types: vec![],
             regions: EntityList::new(),
             id: any_node_id,
         };
 
         for region in regions {
             self.regions[region].container_node = Some(any_node_id);
+            node.regions.push(region, &mut self.region_id_pool);
         }
-        node.regions.extend(regions, &mut self.region_id_pool);
 
-        assert_eq!(
-            self.nodes.push(node),
-            any_node_id,
-            "A node initializer is not allowed to create additional nodes"
-        );
+        self.nodes.push(node);
 
         self.regions[self.region]
             .nodes
-            .push(node_id.id, &mut self.node_id_pool);
+            .push(any_node_id, &mut self.node_id_pool);
 
-        node_id
+        any_node_id
     }
 
     pub fn add_symbol(&mut self, node: id::AnyNode, sym: impl Into<String>) {
+        self.try_add_symbol(node, sym)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_symbol(
+        &mut self,
+        node: id::AnyNode,
+        sym: impl Into<String>,
+    ) -> std::result::Result<(), Error> {
+        self.check_node(node)?;
         self.symbols[node] = sym.into();
+        Ok(())
+    }
+
+    pub(crate) fn check_node(&self, node: id::AnyNode) -> std::result::Result<(), Error> {
+        match self.nodes.get(node) {
+            Some(_) => Ok(()),
+            None => Err(Error::NoSuchNode { node }),
+        }
+    }
+
+    /// Check that the output or argument exists
+    pub(crate) fn check_origin(&self, origin: Origin) -> std::result::Result<(), Error> {
+        let exists = match origin {
+            Origin::Output(node, output) => self
+                .nodes
+                .get(node)
+                .is_some_and(|node| output.as_u32() < node.outputs),
+            Origin::Argument(region, argument) => self
+                .regions
+                .get(region)
+                .is_some_and(|region| argument.as_u32() < region.arguments),
+        };
+
+        match exists {
+            true => Ok(()),
+            false => Err(Error::NoSuchOrigin { origin }),
+        }
+    }
+
+    /// Check that the input or result exists
+    pub(crate) fn check_user(&self, user: User) -> std::result::Result<(), Error> {
+        let exists = match user {
+            User::Input(node, input) => self
+                .nodes
+                .get(node)
+                .is_some_and(|node| input.as_u32() < node.inputs),
+            User::Result(region, result) => self
+                .regions
+                .get(region)
+                .is_some_and(|region| result.as_u32() < region.results),
+        };
+
+        match exists {
+            true => Ok(()),
+            false => Err(Error::NoSuchUser { user }),
+        }
+    }
+
+    /// Check that each region of the node has an argument for every input forwarded to it
+    pub(crate) fn has_forwarded_arguments(&self, node: id::AnyNode) -> bool {
+        let inputs = self.nodes[node].inputs;
+        self.regions(node)
+            .iter()
+            .all(|&region| self.regions[region].arguments >= inputs)
+    }
+
+    /// Check that both ends of an edge are in the region, assuming the ports exist
+    pub(crate) fn edge_is_in(&self, region: id::Region, edge: Edge) -> bool {
+        let origin_region = match edge.origin {
+            Origin::Output(node, _) => self.nodes[node].region,
+            Origin::Argument(region, _) => region,
+        };
+        let user_region = match edge.user {
+            User::Input(node, _) => self.nodes[node].region,
+            User::Result(region, _) => region,
+        };
+        origin_region == region && user_region == region
     }
 
     fn add_region(&mut self, arguments: u32, results: u32) -> id::Region {
@@ -184,6 +412,8 @@ impl TranslationUnitContext {
             container_node: None,
             arguments,
             results,
+            argument_types: vec![None; arguments as usize],
+            result_types: vec![None; results as usize],
             edges: vec![],
             nodes: EntityList::new(),
         })
@@ -198,12 +428,18 @@ impl TranslationUnitContext {
 
     /// Get the only singular region. Panics if there's not exactly one region
     pub fn region(&self, node: id::AnyNode) -> id::Region {
+        self.try_region(node).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_region(&self, node: id::AnyNode) -> std::result::Result<id::Region, Error> {
+        self.check_node(node)?;
         match self.nodes[node].regions.as_slice(&self.region_id_pool) {
-            [only] => *only,
-            regions => panic!(
-                "`region` can not be called for node with {} regions",
-                regions.len()
-            ),
+            [only] => Ok(*only),
+            regions => Err(Error::RegionCountMismatch {
+                node,
+                expected: 1,
+                found: regions.len(),
+            }),
         }
     }
 
@@ -244,18 +480,133 @@ impl TranslationUnitContext {
         (id::Result::from_u32(0), output)
     }
 
+    /// Create a gamma (conditional) node.
+    ///
+    /// Gamma nodes have one region for each branch.
+    /// Gamma nodes take the predicate selecting which branch to evaluate as first input. Like any
+    /// other input, the predicate is forwarded as an argument to every branch region.
+    pub fn add_gamma_node(&mut self, branches: usize) -> (Input<Gamma>, Vec<id::Region>) {
+        self.try_add_gamma_node(branches)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_gamma_node(
+        &mut self,
+        branches: usize,
+    ) -> std::result::Result<(Input<Gamma>, Vec<id::Region>), Error> {
+        if branches == 0 {
+            return Err(Error::NoBranches);
+        }
+
+        let node_id = self.add_node(|ctx, _| {
+            let regions: Vec<_> = (0..branches).map(|_| ctx.add_region(0, 0)).collect();
+            (Gamma {}, regions)
+        });
+
+        let predicate = self.add_input(node_id);
+        Ok((predicate, self.regions(node_id.id).to_vec()))
+    }
+
+    /// Add an output to a gamma node, along with its matching result in every branch region.
+    pub fn add_gamma_output(&mut self, node: id::Node<Gamma>) -> (Vec<Result>, Output<Gamma>) {
+        self.try_add_gamma_output(node)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_gamma_output(
+        &mut self,
+        node: id::Node<Gamma>,
+    ) -> std::result::Result<(Vec<Result>, Output<Gamma>), Error> {
+        self.try_get(node)?;
+
+        let results = self
+            .regions(node.id)
+            .to_vec()
+            .into_iter()
+            .map(|branch| self.in_region(branch, |ctx| ctx.add_result()))
+            .collect();
+
+        let output = self.push_output(node);
+
+        Ok((results, output))
+    }
+
+    /// Create a dowhile (theta) node.
+    ///
+    /// DoWhile nodes have a singular region representing the loop body.
+    /// DoWhile regions start with a singular result, the predicate deciding whether to iterate again.
+    /// Every input of a DoWhile node is loop-carried, see [`Self::add_loop_var`].
+    pub fn add_dowhile_node(&mut self) -> (Result, id::Node<DoWhile>) {
+        let node_id = self.add_node(|ctx, _| {
+            let body = ctx.add_region(0, 1);
+            (DoWhile {}, [body])
+        });
+
+        let predicate = Result {
+            region: self.region(node_id.id),
+            id: id::Result::from_u32(0),
+        };
+
+        (predicate, node_id)
+    }
+
+    /// Add a loop-carried variable to a dowhile node.
+    ///
+    /// Creates an input, region argument, region result and output together so that input `n`,
+    /// argument `n`, result `n + 1` and output `n` always describe the same variable.
+    pub fn add_loop_var(&mut self, node: id::Node<DoWhile>) -> LoopVar {
+        self.try_add_loop_var(node)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_loop_var(
+        &mut self,
+        node: id::Node<DoWhile>,
+    ) -> std::result::Result<LoopVar, Error> {
+        self.try_get(node)?;
+
+        let input = self.push_input(node);
+        let argument = self.input_as_argument(input);
+        let result = self.in_region(argument.region, |ctx| ctx.push_result());
+        let output = self.push_output(node);
+
+        Ok(LoopVar {
+            input,
+            argument,
+            result,
+            output,
+        })
+    }
+
+    pub fn loop_vars(&self, node: id::Node<DoWhile>) -> impl Iterator<Item = LoopVar> + 'static {
+        let region = self.region(node.id);
+        self.inputs(node.id).map(move |input| LoopVar {
+            input: Input { node, id: input },
+            argument: Argument {
+                region,
+                id: id::Argument::from_u32(input.as_u32()),
+            },
+            result: Result {
+                region,
+                id: id::Result::from_u32(input.as_u32() + 1),
+            },
+            output: Output {
+                node,
+                id: id::Output::from_u32(input.as_u32()),
+            },
+        })
+    }
+
     // Create a RecEnv (phi) node.
     //
     // RecEnv nodes have a singular region, containing lambdas that can be mutually recursive.
     // RecEnv nodes have an output for each contained lambda.
     pub fn add_recenv_node(&mut self) -> id::Node<RecEnv> {
-        let node_id = self.add_node(|ctx, _| {
+        self.add_node(|ctx, _| {
             let region = ctx.add_region(0, 0);
             let lambdas = HashMap::new();
             (RecEnv { lambdas }, [region])
-        });
-
-        node_id
+        })
     }
 
     // Create a number node.
@@ -287,26 +638,45 @@ impl TranslationUnitContext {
 
     fn debug_node(&self, node: id::AnyNode) -> String {
         let sym = &self.symbols[node];
-        if sym == "" {
+        if sym.is_empty() {
             format!("{node}")
         } else {
             format!("{node}·{sym}")
         }
     }
 
+    /// Add an input to a node, forwarded as an argument to each of its regions.
+    ///
+    /// The inputs of dowhile nodes are added as loop variables with [`Self::add_loop_var`].
     pub fn add_input<K>(&mut self, node: id::Node<K>) -> Input<K> {
+        self.try_add_input(node)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_input<K>(&mut self, node: id::Node<K>) -> std::result::Result<Input<K>, Error> {
+        self.check_node(node.id)?;
+        if self.is_kind::<DoWhile>(node.id) {
+            return Err(Error::LoopVarPort { node: node.id });
+        }
+
+        Ok(self.push_input(node))
+    }
+
+    fn push_input<K>(&mut self, node: id::Node<K>) -> Input<K> {
         let inputs = &mut self.nodes[node.id].inputs;
         let input = id::Input::from_u32(*inputs);
         *inputs += 1;
+        self.nodes[node.id].input_types.push(None);
 
         trace!("added input {input} for {}", self.debug_node(node.id));
 
         // Forward this input as an argument to each contained region.
         for region in self.nodes[node.id].regions.as_slice(&self.region_id_pool) {
-            let arguments = &mut self.regions[*region].arguments;
-            *arguments += 1;
+            let region = &mut self.regions[*region];
+            region.arguments += 1;
+            region.argument_types.push(None);
             assert!(
-                dbg!(*arguments) >= dbg!(input.as_u32() + 1),
+                region.arguments > input.as_u32(),
                 "region has fewer arguments than node has inputs"
             );
         }
@@ -315,7 +685,25 @@ impl TranslationUnitContext {
     }
 
     pub fn input_as_argument<K>(&self, input: Input<K>) -> Argument {
-        let region = self.region(input.node.id);
+        self.try_input_as_argument(input)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_input_as_argument<K>(
+        &self,
+        input: Input<K>,
+    ) -> std::result::Result<Argument, Error> {
+        self.check_user(User::Input(input.node.id, input.id))?;
+        let region = self.try_region(input.node.id)?;
+        Ok(self.input_as_argument_in(input, region))
+    }
+
+    /// Get the argument an input is forwarded as in one of the node's regions.
+    ///
+    /// Unlike [`Self::input_as_argument`] this also works for nodes with several regions.
+    pub fn input_as_argument_in<K>(&self, input: Input<K>, region: id::Region) -> Argument {
+        debug_assert!(self.regions(input.node.id).contains(&region));
+
         let args = self.regions[region].arguments;
         let inputs = self.nodes[input.node.id].inputs;
 
@@ -332,7 +720,7 @@ impl TranslationUnitContext {
         argument: id::Argument,
     ) -> Option<Input<id::AnyNode>> {
         let args = self.regions[region].arguments;
-        let node = self.regions[region].container_node.unwrap();
+        let node = self.regions[region].container_node?;
         let inputs = self.nodes[node].inputs;
 
         let node_custom_args = args - inputs;
@@ -351,30 +739,105 @@ impl TranslationUnitContext {
         }
     }
 
+    /// Add an output to a node.
+    ///
+    /// The outputs of dowhile nodes are added as loop variables with [`Self::add_loop_var`].
     pub fn add_output<K>(&mut self, node: id::Node<K>) -> Output<K> {
+        self.try_add_output(node)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_output<K>(
+        &mut self,
+        node: id::Node<K>,
+    ) -> std::result::Result<Output<K>, Error> {
+        self.check_node(node.id)?;
+        if self.is_kind::<DoWhile>(node.id) {
+            return Err(Error::LoopVarPort { node: node.id });
+        }
+
+        Ok(self.push_output(node))
+    }
+
+    fn push_output<K>(&mut self, node: id::Node<K>) -> Output<K> {
         let outputs = &mut self.nodes[node.id].outputs;
         let output = id::Output::from_u32(*outputs);
         *outputs += 1;
+        self.nodes[node.id].output_types.push(None);
         Output { id: output, node }
     }
 
+    /// Add an argument to the current region.
+    ///
+    /// The argument is placed before the arguments forwarded from the inputs of the containing
+    /// node, shifting those if necessary. The arguments of dowhile regions are added as loop
+    /// variables with [`Self::add_loop_var`].
+    ///
+    /// **Forwarded arguments are renumbered.** Edges are updated, but an [`Argument`] for a
+    /// forwarded input obtained earlier, e.g. from [`Self::input_as_argument`], refers to the
+    /// wrong port afterwards and has to be looked up again. Add the arguments of a region before
+    /// forwarding anything into it to avoid this.
     pub fn add_argument(&mut self) -> Argument {
-        let arguments = &mut self.regions[self.region].arguments;
-        let arg = id::Argument::from_u32(*arguments);
-        *arguments += 1;
+        self.try_add_argument()
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
 
-        trace!("added argument {arg} for {}", self.region);
+    pub fn try_add_argument(&mut self) -> std::result::Result<Argument, Error> {
+        let region = self.region;
+        self.check_not_loop_body(region)?;
 
-        Argument {
-            id: arg,
-            region: self.region,
+        let forwarded = match self.regions[region].container_node {
+            Some(node) => self.nodes[node].inputs,
+            None => 0,
+        };
+
+        let arguments = &mut self.regions[region].arguments;
+        let arg = id::Argument::from_u32(*arguments - forwarded);
+        *arguments += 1;
+        self.regions[region]
+            .argument_types
+            .insert(arg.as_u32() as usize, None);
+
+        if forwarded != 0 {
+            self.rewrite_edges(region, |edge| {
+                if let Origin::Argument(r, a) = &mut edge.origin
+                    && *r == region
+                    && a.as_u32() >= arg.as_u32()
+                {
+                    *a = id::Argument::from_u32(a.as_u32() + 1);
+                }
+            });
         }
+
+        trace!("added argument {arg} for {}", region);
+
+        Ok(Argument { id: arg, region })
     }
 
+    /// Add a result to the current region.
+    ///
+    /// The results of dowhile regions are added as loop variables with [`Self::add_loop_var`].
     pub fn add_result(&mut self) -> Result {
+        self.try_add_result().unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_add_result(&mut self) -> std::result::Result<Result, Error> {
+        self.check_not_loop_body(self.region)?;
+        Ok(self.push_result())
+    }
+
+    fn check_not_loop_body(&self, region: id::Region) -> std::result::Result<(), Error> {
+        match self.regions[region].container_node {
+            Some(node) if self.is_kind::<DoWhile>(node) => Err(Error::LoopVarPort { node }),
+            _ => Ok(()),
+        }
+    }
+
+    fn push_result(&mut self) -> Result {
         let results = &mut self.regions[self.region].results;
         let result = id::Result::from_u32(*results);
         *results += 1;
+        self.regions[self.region].result_types.push(None);
 
         trace!("added result {result} for {}", self.region);
 
@@ -385,49 +848,105 @@ impl TranslationUnitContext {
     }
 
     pub fn connect(&mut self, origin: impl Into<Origin>, user: impl Into<User>) {
-        let origin = origin.into();
-        let user = user.into();
-        let ok = self.try_connect(origin, user);
-        if !ok {
-            panic!("no available path to connect {origin:?} → {user:?}");
+        if let Err(err) = self.try_connect(origin, user) {
+            panic!("{err}");
         }
     }
 
-    /// Try to find a path to make the connection. Returns false if unable.
-    pub fn try_connect(&mut self, origin: impl Into<Origin>, user: impl Into<User>) -> bool {
-        const CONNECTED: bool = true;
-
-        // TODO: We could resolve recenv lambda nodes here and edge-case them to 'fix' connections?
-        // That way we could effectively 'hide' recenv's from user API.
+    /// Try to find a path to make the connection.
+    ///
+    /// The connection is checked before anything is changed. Errors while wrapping lambdas which
+    /// became mutually recursive in a recenv are returned once the connection has been made
+    /// though, with the graph left partially rewired.
+    pub fn try_connect(
+        &mut self,
+        origin: impl Into<Origin>,
+        user: impl Into<User>,
+    ) -> std::result::Result<(), Error> {
+        let (origin, user) = (origin.into(), user.into());
+        self.check_origin(origin)?;
+        self.check_user(user)?;
 
-        let origin = origin.into();
-        let user = user.into();
+        // Lambdas which have been moved into a recenv are transparently used through the recenv.
+        let origin = self.resolve_recenv_origin(origin);
 
         trace!("trying to connect {origin:?} -> {user:?}");
 
         if self.connection_exists(origin, user) {
-            return CONNECTED;
+            return Ok(());
         }
 
-        // TODO: we need to be able to detect cycles and reformat things into recenvs.
+        // Check everything up front so that a failed connection doesn't leave forwarded inputs behind
+        self.check_connection(origin, user)?;
 
-        match origin {
-            Origin::Output(node_id, output) => {
-                let Some(origin) = self.find_and_connect_output(node_id, output) else {
-                    return !CONNECTED;
-                };
+        let edges_before = self.edge_counts_to_omega();
 
-                self.raw_connect_asserted(origin, user);
-                return CONNECTED;
-            }
+        let path = match origin {
+            Origin::Output(node_id, output) => self.find_and_connect_output(node_id, output),
             Origin::Argument(region, arg) => {
                 let arg = Argument { region, id: arg };
-                let Some(origin) = self.find_and_connect_argument(arg) else {
-                    return !CONNECTED;
-                };
+                self.find_and_connect_argument(arg)
+            }
+        };
 
-                self.raw_connect_asserted(origin, user);
-                return CONNECTED;
+        let path = path.ok_or(Error::NoPath { origin, user })?;
+        self.raw_connect_asserted(path, user);
+
+        // The new edges may have made lambdas mutually recursive, in which case they need to be
+        // wrapped in a recenv.
+        self.wrap_recursive_lambdas(edges_before)
+    }
+
+    fn check_connection(&self, origin: Origin, user: User) -> std::result::Result<(), Error> {
+        match user {
+            User::Input(node, _) if self.nodes[node].region != self.region => {
+                return Err(Error::NodeNotInRegion {
+                    node,
+                    region: self.region,
+                });
+            }
+            User::Result(region, _) if region != self.region => {
+                return Err(Error::NoPath { origin, user });
+            }
+            _ => {}
+        }
+
+        let origin_region = match origin {
+            Origin::Output(node, _) => self.nodes[node].region,
+            Origin::Argument(region, _) => region,
+        };
+
+        if !self.region_is_within(self.region, origin_region) {
+            return Err(Error::NoPath { origin, user });
+        }
+
+        self.check_types(origin, user)?;
+        self.check_recursion(origin, user)
+    }
+
+    // The amount of edges in the current region and each of its parent regions
+    fn edge_counts_to_omega(&self) -> Vec<(id::Region, usize)> {
+        let mut counts = vec![];
+        let mut region = self.region;
+        loop {
+            counts.push((region, self.regions[region].edges.len()));
+            match self.regions[region].container_node {
+                Some(node) => region = self.nodes[node].region,
+                None => break counts,
+            }
+        }
+    }
+
+    /// Whether `inner` is `outer` or a region nested within it
+    fn region_is_within(&self, inner: id::Region, outer: id::Region) -> bool {
+        let mut region = inner;
+        loop {
+            if region == outer {
+                break true;
+            }
+            match self.regions[region].container_node {
+                Some(node) => region = self.nodes[node].region,
+                None => break false,
             }
         }
     }
@@ -460,13 +979,8 @@ impl TranslationUnitContext {
             // current region.
             self.in_region(parent_region, |this| {
                 this.find_and_connect_output(output_node, output)
-                    .map(|origin| {
-                        let input = this.add_input::<id::AnyNode>(id::Node::new(in_node));
-                        let arg = this.input_as_argument(input);
-                        this.raw_connect_asserted(origin, input);
-                        Origin::Argument(arg.region, arg.id)
-                    })
             })
+            .map(|origin| self.forward_origin_as_argument(origin).into())
         }
     }
 
@@ -484,47 +998,124 @@ impl TranslationUnitContext {
             trace!("not in current region, checking parent {parent_node:?}");
 
             let parent_region = self.nodes[parent_node].region;
-            self.in_region(parent_region, |this| {
-                this.find_and_connect_argument(arg).map(|origin| {
-                    dbg!(&origin);
-                    let input = this.add_input::<id::AnyNode>(id::Node::new(parent_node));
-                    let arg = this.input_as_argument(input);
-                    this.raw_connect_asserted(origin, input);
-                    Origin::Argument(arg.region, arg.id)
-                })
-            })
+            self.in_region(parent_region, |this| this.find_and_connect_argument(arg))
+                .map(|origin| self.forward_origin_as_argument(origin).into())
         }
     }
 
-    // Raw-connect an origin to a node and return the created argument for the contained region
+    // Raw-connect an origin from the parent region to a new input of the node containing the
+    // current region and return the created argument for the current region
     fn forward_origin_as_argument(&mut self, origin: Origin) -> Argument {
-        todo!();
+        let in_node = self.regions[self.region]
+            .container_node
+            .expect("omega has no parent region to forward from");
+
+        let region = self.region;
+        let parent_region = self.nodes[in_node].region;
+
+        // Reuse an input which already forwards the origin, unless it's a loop variable that's
+        // changed by the loop.
+        let existing = self.regions[parent_region]
+            .edges
+            .iter()
+            .filter(|edge| edge.origin == origin)
+            .filter_map(|edge| match edge.user {
+                User::Input(node, input) if node == in_node => Some(Input {
+                    node: id::Node::<id::AnyNode>::new(node),
+                    id: input,
+                }),
+                _ => None,
+            })
+            .map(|input| self.input_as_argument_in(input, region))
+            .find(|argument| {
+                !self.is_kind::<DoWhile>(in_node) || {
+                    let result = id::Result::from_u32(argument.id.as_u32() + 1);
+                    self.regions[region].edges.contains(&Edge {
+                        origin: Origin::Argument(region, argument.id),
+                        user: User::Result(region, result),
+                    })
+                }
+            });
+
+        if let Some(argument) = existing {
+            return argument;
+        }
+
+        // Values used inside a loop body have to be loop-carried, so forward them as loop
+        // variables which pass the argument through unchanged.
+        let ty = self.origin_type(origin).cloned();
+
+        if self.is_kind::<DoWhile>(in_node) {
+            let var = self.in_region(parent_region, |this| {
+                let var = this.add_loop_var(id::Node::new(in_node));
+                if let Some(ty) = ty {
+                    this.set_user_type(var.input, ty);
+                }
+                this.raw_connect_asserted(origin, var.input);
+                var
+            });
+            self.raw_connect_asserted(var.argument, var.result);
+            return var.argument;
+        }
+
+        self.in_region(parent_region, |this| {
+            let input = this.add_input::<id::AnyNode>(id::Node::new(in_node));
+            if let Some(ty) = ty {
+                this.set_user_type(input, ty);
+            }
+            this.raw_connect_asserted(origin, input);
+            this.input_as_argument_in(input, region)
+        })
+    }
+
+    fn is_kind<K: NodeKind>(&self, node: id::AnyNode) -> bool {
+        self.nodes[node].kind.as_any().is::<K>()
     }
 
     /// Connect without any implicit automatic connections but still assert against incorrect connections
     pub fn raw_connect_asserted(&mut self, origin: impl Into<Origin>, user: impl Into<User>) {
+        if let Err(err) = self.try_raw_connect_asserted(origin, user) {
+            panic!("{err}");
+        }
+    }
+
+    /// Connect without any implicit automatic connections, returning an error for incorrect connections
+    pub fn try_raw_connect_asserted(
+        &mut self,
+        origin: impl Into<Origin>,
+        user: impl Into<User>,
+    ) -> std::result::Result<(), Error> {
         let origin = origin.into();
         let user = user.into();
 
         info!("connecting {origin:?} → {user:?}");
 
-        if let Origin::Output(node_id, _) = origin {
-            assert!(
-                self.current_nodes().contains(&node_id),
-                "origin {origin:?} is from node not in current {}",
-                self.region
-            );
+        self.check_origin(origin)?;
+        self.check_user(user)?;
+
+        if let Origin::Output(node, _) = origin
+            && !self.current_nodes().contains(&node)
+        {
+            return Err(Error::NodeNotInRegion {
+                node,
+                region: self.region,
+            });
         }
 
-        if let User::Input(node_id, _) = user {
-            assert!(
-                self.current_nodes().contains(&node_id),
-                "user {user:?} is for node not in current {}",
-                self.region
-            );
+        if let User::Input(node, _) = user
+            && !self.current_nodes().contains(&node)
+        {
+            return Err(Error::NodeNotInRegion {
+                node,
+                region: self.region,
+            });
         }
 
-        unsafe { self.raw_connect(origin, user) }
+        self.check_types(origin, user)?;
+
+        unsafe { self.raw_connect(origin, user) };
+
+        Ok(())
     }
 
     /// NOTE: While this function is memory safe, it's marked as unsafe since it allows you to
@@ -532,7 +1123,7 @@ impl TranslationUnitContext {
     unsafe fn raw_connect(&mut self, origin: impl Into<Origin>, user: impl Into<User>) {
         let origin = origin.into();
         let user = user.into();
-        self.regions[self.region].edges.push(Edge { origin, user });
+        self.push_edge(self.region, Edge { origin, user });
     }
 
     fn current_nodes(&self) -> &[id::AnyNode] {
@@ -540,19 +1131,16 @@ impl TranslationUnitContext {
     }
 
     fn connection_exists(&self, origin: Origin, user: User) -> bool {
-        self.regions[self.region]
-            .edges
-            .iter()
-            .find(|edge| (edge.user == user) && self.edge_leads_to_origin(&edge, origin))
-            .is_some()
+        self.origins_of(user)
+            .any(|from| self.origin_leads_to(from, origin))
     }
 
-    fn edge_leads_to_origin(&self, edge: &Edge, origin: Origin) -> bool {
-        edge.origin == origin
-            || match edge.origin {
+    fn origin_leads_to(&self, from: Origin, origin: Origin) -> bool {
+        from == origin
+            || match from {
                 Origin::Output(..) => false,
-                Origin::Argument(_, argument) => {
-                    let Some(input) = self.argument_as_input(self.region, argument) else {
+                Origin::Argument(region, argument) => {
+                    let Some(input) = self.argument_as_input(region, argument) else {
                         return false;
                     };
 
@@ -561,15 +1149,35 @@ impl TranslationUnitContext {
             }
     }
 
+    /// Move a node from the region it's in to another region.
+    ///
+    /// Edges connected to the node are left untouched.
     pub fn move_node(&mut self, node: id::AnyNode, to: id::Region) {
-        let rnodes = &mut self.regions[self.region].nodes;
+        if let Err(err) = self.try_move_node(node, to) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_move_node(
+        &mut self,
+        node: id::AnyNode,
+        to: id::Region,
+    ) -> std::result::Result<(), Error> {
+        self.check_node(node)?;
+        if self.regions.get(to).is_none() {
+            return Err(Error::NoSuchRegion { region: to });
+        }
+        let from = self.nodes[node].region;
+        let rnodes = &mut self.regions[from].nodes;
         let i = rnodes
             .as_slice(&self.node_id_pool)
             .iter()
             .position(|n| *n == node)
-            .expect("node is not in current region");
+            .ok_or(Error::NodeNotInRegion { node, region: from })?;
         rnodes.remove(i, &mut self.node_id_pool);
         self.regions[to].nodes.push(node, &mut self.node_id_pool);
+        self.nodes[node].region = to;
+        Ok(())
     }
 
     pub fn open_rvsdg_viewer(&mut self) {
@@ -578,18 +1186,37 @@ impl TranslationUnitContext {
     }
 }
 
-fn get_kind<K: NodeKind>(nodes: &PrimaryMap<id::AnyNode, Node>, id: id::Node<K>) -> &K {
-    nodes[id.id]
-        .kind
-        .as_any()
+fn get_kind<K: NodeKind>(
+    nodes: &PrimaryMap<id::AnyNode, Node>,
+    id: id::Node<K>,
+) -> std::result::Result<&K, Error> {
+    let kind = &nodes
+        .get(id.id)
+        .ok_or(Error::NoSuchNode { node: id.id })?
+        .kind;
+    kind.as_any()
         .downcast_ref()
-        .expect("node is not of expected kind")
+        .ok_or_else(|| wrong_node_kind::<K>(id.id, kind.node_type()))
 }
 
-fn get_kind_mut<K: NodeKind>(nodes: &mut PrimaryMap<id::AnyNode, Node>, id: id::Node<K>) -> &mut K {
-    nodes[id.id]
-        .kind
-        .as_any_mut()
-        .downcast_mut()
-        .expect("node is not of expected kind")
+fn get_kind_mut<K: NodeKind>(
+    nodes: &mut PrimaryMap<id::AnyNode, Node>,
+    id: id::Node<K>,
+) -> std::result::Result<&mut K, Error> {
+    let kind = &mut nodes
+        .get_mut(id.id)
+        .ok_or(Error::NoSuchNode { node: id.id })?
+        .kind;
+    if !kind.as_any().is::<K>() {
+        return Err(wrong_node_kind::<K>(id.id, kind.node_type()));
+    }
+    Ok(kind.as_any_mut().downcast_mut().unwrap())
+}
+
+fn wrong_node_kind<K: NodeKind>(node: id::AnyNode, found: &str) -> Error {
+    Error::WrongNodeKind {
+        node,
+        expected: std::any::type_name::<K>(),
+        found: found.to_string(),
+    }
 }
@@ -1,11 +1,85 @@
-use crate::{Input, Origin, Output, TranslationUnitContext, User, get_kind, id, node_kind_impl};
+use crate::interp::Evaluate;
+use crate::{
+    Argument, Edge, Error, Input, Origin, Output, Result, TranslationUnitContext, User, id,
+    node_kind_impl,
+};
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use tracing::trace;
 
 pub trait NodeKind: std::any::Any + std::fmt::Debug + Send + Sync {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
     fn node_type(&self) -> &str;
+
+    /// Clone the kind for a copy of the node, see [`TranslationUnitContext::clone_node`]
+    fn clone_box(&self) -> Box<dyn NodeKind + Send + Sync>;
+
+    /// Opt into common node elimination, see [`StructuralEq`]
+    fn as_structural(&self) -> Option<&dyn StructuralEq> {
+        None
+    }
+
+    /// Opt into constant folding, see [`Fold`]
+    fn as_fold(&self) -> Option<&dyn Fold> {
+        None
+    }
+
+    /// Opt into being evaluated by the interpreter, see [`Evaluate`]
+    fn as_evaluate(&self) -> Option<&dyn Evaluate> {
+        None
+    }
+
+    /// The data of the node beyond its kind, written to and read from XML
+    fn payload(&self) -> Option<String> {
+        None
+    }
+
+    /// Opt into a binary encoding of the data of the node, see [`Encode`]
+    fn as_encode(&self) -> Option<&dyn Encode> {
+        None
+    }
+}
+
+/// Binary encoding of the data of a node kind.
+///
+/// Written by [`TranslationUnitContext::save`] in place of the [`NodeKind::payload`], and given
+/// back to the decoder registered with [`KindRegistry::register_decoder`](crate::KindRegistry::register_decoder).
+/// Opted into using `node_kind_impl!(.., encode)`.
+pub trait Encode {
+    fn encode(&self, bytes: &mut Vec<u8>);
+}
+
+/// Evaluation of nodes whose inputs are all constants.
+///
+/// Given the values of the inputs, returns the values of the outputs or `None` if the node can't
+/// be evaluated. A node is left as it is if the amount of values doesn't match its outputs.
+/// Nodes of a kind which implements this are replaced by [`Number`] nodes by
+/// [`TranslationUnitContext::fold_constants`], opted into using `node_kind_impl!(.., fold)`.
+pub trait Fold {
+    fn fold(&self, inputs: &[i128]) -> Option<Vec<i128>>;
+}
+
+/// Structural equality and hashing of node kinds.
+///
+/// Nodes of a kind which implements this are considered the same value when they're of the same
+/// kind, structurally equal and use the same origins, allowing them to be merged. Implemented for
+/// all node kinds with [`PartialEq`] and [`Hash`], opted into using `node_kind_impl!(.., structural)`.
+pub trait StructuralEq {
+    fn structural_eq(&self, other: &dyn NodeKind) -> bool;
+    fn structural_hash(&self, state: &mut dyn Hasher);
+}
+
+impl<K: NodeKind + PartialEq + Hash> StructuralEq for K {
+    fn structural_eq(&self, other: &dyn NodeKind) -> bool {
+        other.as_any().downcast_ref::<K>() == Some(self)
+    }
+
+    fn structural_hash(&self, mut state: &mut dyn Hasher) {
+        self.hash(&mut state)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -16,6 +90,22 @@ node_kind_impl!(Apply, "apply");
 pub struct DoWhile {}
 node_kind_impl!(DoWhile, "theta");
 
+/// A loop-carried variable of a [`DoWhile`] node.
+///
+/// The input provides the initial value, the argument is the value for the current iteration and
+/// the result is the value for the next iteration. Once the loop exits the output holds the final value.
+#[derive(Debug, Clone, Copy)]
+pub struct LoopVar {
+    pub input: Input<DoWhile>,
+    pub argument: Argument,
+    pub result: Result,
+    pub output: Output<DoWhile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Gamma {}
+node_kind_impl!(Gamma, "gamma");
+
 #[derive(Debug, Clone)]
 pub struct GlobalV {}
 node_kind_impl!(GlobalV, "delta");
@@ -24,40 +114,493 @@ node_kind_impl!(GlobalV, "delta");
 pub struct Lambda {}
 node_kind_impl!(Lambda, "lambda");
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Number(pub i128);
-node_kind_impl!(Number, "number");
+node_kind_impl!(Number, "number", structural, payload);
 
-#[derive(Debug, Clone)]
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Placeholder(pub &'static str);
-node_kind_impl!(Placeholder, "placeholder");
+node_kind_impl!(Placeholder, "placeholder", structural, payload);
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RecEnv {
     // pub lambdas: PrimaryMap<id::Output, id::Node<Lambda>>,
     pub lambdas: HashMap<id::AnyNode, (id::Argument, id::Output)>,
 }
-node_kind_impl!(RecEnv, "phi");
+node_kind_impl!(RecEnv, "phi", encode);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TranslationUnit {
     pub region: id::Region,
 }
-node_kind_impl!(TranslationUnit, "omega");
+node_kind_impl!(TranslationUnit, "omega", encode);
 
 impl TranslationUnitContext {
+    /// Move a lambda into the RecEnv whose region is the current region.
+    ///
+    /// Allocates the recursion argument in the RecEnv region along with the RecEnv output (and
+    /// its result) that exposes the lambda outside of the RecEnv. Edges between the lambda and
+    /// its surroundings are rerouted through the RecEnv, with references to sibling lambdas
+    /// being replaced by their recursion arguments.
     pub fn move_lambda_to_recenv(
         &mut self,
         lambda: id::Node<Lambda>,
-    ) -> (id::Argument, Output<Lambda>) {
-        let to = self.region;
+    ) -> (Argument, Output<RecEnv>) {
+        self.try_move_lambda_to_recenv(lambda)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_move_lambda_to_recenv(
+        &mut self,
+        lambda: id::Node<Lambda>,
+    ) -> std::result::Result<(Argument, Output<RecEnv>), Error> {
+        let env = self.regions[self.region]
+            .container_node
+            .filter(|&node| self.is_kind::<RecEnv>(node))
+            .ok_or(Error::NotInRecEnv {
+                region: self.region,
+            })?;
+
+        Ok(self.move_lambdas_to_recenv(id::Node::new(env), &[lambda])?[0])
+    }
+
+    pub(crate) fn move_lambdas_to_recenv(
+        &mut self,
+        env: id::Node<RecEnv>,
+        lambdas: &[id::Node<Lambda>],
+    ) -> std::result::Result<Vec<(Argument, Output<RecEnv>)>, Error> {
+        let env_region = self.region(env.id);
+
+        // Detach the lambdas from wherever they are, remembering the edges to restore.
+        let mut detached = vec![];
+        for lambda in lambdas {
+            let from = self.nodes[lambda.id].region;
+            if from == env_region {
+                continue;
+            }
+
+            let touching = self.regions[from]
+                .edges
+                .iter()
+                .filter(|edge| edge_touches_node(edge, lambda.id))
+                .map(|&edge| (from, edge));
+            detached.extend(touching);
+            self.retain_edges(from, |edge| !edge_touches_node(edge, lambda.id));
+
+            self.move_node(lambda.id, env_region);
+        }
+
+        let ports = self.in_region(env_region, |ctx| {
+            lambdas
+                .iter()
+                .map(|&lambda| {
+                    let argument = ctx.add_argument();
+                    let output = ctx.add_output(env);
+                    let result = ctx.add_result();
+                    ctx.raw_connect_asserted(ctx.lambda_output_of(lambda), result);
+
+                    // All of them are the lambda
+                    if let Some(ty) = ctx.origin_type(ctx.lambda_output_of(lambda)).cloned() {
+                        ctx.set_origin_type(argument, ty.clone());
+                        ctx.set_origin_type(output, ty.clone());
+                        ctx.set_user_type(result, ty);
+                    }
+
+                    ctx.get_mut(env)
+                        .lambdas
+                        .insert(lambda.id, (argument.id, output.id));
+
+                    (argument, output)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // References to lambdas inside of the RecEnv need to go through the recursion arguments.
+        let recursive = self.get(env).lambdas.clone();
+        self.rewrite_edges(env_region, |edge| {
+            if let (Origin::Output(node, _), User::Input(..)) = (edge.origin, edge.user)
+                && let Some((argument, _)) = recursive.get(&node)
+            {
+                edge.origin = Origin::Argument(env_region, *argument);
+            }
+        });
+
+        let mut unused_inputs = vec![];
+        for (from, edge) in detached {
+            unused_inputs.extend(self.reattach_recenv_edge(env, from, edge)?);
+        }
+
+        // Removing inputs renumbers the ones after it, so remove from the back
+        unused_inputs.sort_unstable_by_key(|input| std::cmp::Reverse(input.as_u32()));
+        for input in unused_inputs {
+            self.remove_input_port(env.id, input);
+        }
 
-        // TODO: it's difficult for me to imagine how this would look.
-        //
-        // So; let's try just using this in a real lower.
+        Ok(ports)
+    }
+
+    // Restore an edge of a lambda moved into the recenv. Returns the input of the recenv which
+    // became unused if the edge was forwarding the lambda into the recenv.
+    fn reattach_recenv_edge(
+        &mut self,
+        env: id::Node<RecEnv>,
+        from: id::Region,
+        edge: Edge,
+    ) -> std::result::Result<Option<id::Input>, Error> {
+        let env_region = self.region(env.id);
+        let recursive = &self.get(env).lambdas;
+
+        let moved_origin = match edge.origin {
+            Origin::Output(node, _) => recursive.get(&node).copied(),
+            Origin::Argument(..) => None,
+        };
+
+        let moved_user = match edge.user {
+            User::Input(node, _) => recursive.contains_key(&node),
+            User::Result(..) => false,
+        };
+
+        match (moved_origin, moved_user) {
+            // Both sides now live in the RecEnv
+            (Some((argument, _)), true) => self.in_region(env_region, |ctx| {
+                ctx.raw_connect_asserted(Origin::Argument(env_region, argument), edge.user)
+            }),
+            (Some((argument, output)), false) => match edge.user {
+                // The RecEnv used to forward this lambda into its region; use the recursion
+                // argument for those uses instead.
+                User::Input(node, input) if node == env.id => {
+                    let forwarded = self.input_as_argument(Input {
+                        node: env,
+                        id: input,
+                    });
+
+                    self.rewrite_edges(env_region, |edge| {
+                        if edge.origin == Origin::from(forwarded) {
+                            edge.origin = Origin::Argument(env_region, argument);
+                        }
+                    });
 
-        self.move_node(lambda.id, to);
-        todo!();
+                    return Ok(Some(input));
+                }
+                // The lambda is used outside of the RecEnv
+                user => self.in_region(from, |ctx| {
+                    ctx.try_connect(Origin::Output(env.id, output), user)
+                })?,
+            },
+            // The lambda uses a value from outside of the RecEnv
+            (None, _) => {
+                let origin = match edge.origin {
+                    Origin::Output(node, output) if node == env.id => {
+                        let sibling = recursive.values().find(|(_, o)| *o == output).copied();
+                        match sibling {
+                            Some((argument, _)) => Origin::Argument(env_region, argument),
+                            None => edge.origin,
+                        }
+                    }
+                    _ => edge.origin,
+                };
+
+                self.in_region(env_region, |ctx| ctx.try_connect(origin, edge.user))?
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Rebuild the lambdas of a recenv read from a format which doesn't include them.
+    ///
+    /// Lambda `n` of a recenv has its recursion argument, result and output at `n`.
+    pub(crate) fn restore_recenv_lambdas(&mut self, env: id::Node<RecEnv>) {
+        let region = self.region(env.id);
+        let lambdas = self.regions[region]
+            .edges
+            .iter()
+            .filter_map(|edge| match (edge.origin, edge.user) {
+                (Origin::Output(lambda, _), User::Result(_, result))
+                    if self.is_kind::<Lambda>(lambda) =>
+                {
+                    let n = result.as_u32();
+                    Some((lambda, (id::Argument::from_u32(n), id::Output::from_u32(n))))
+                }
+                _ => None,
+            })
+            .collect();
+        self.get_mut(env).lambdas = lambdas;
+    }
+
+    /// Resolve an origin from a lambda inside of a recenv to how it's reachable from the current
+    /// region; the recursion argument from within the recenv and the recenv output from outside.
+    pub(crate) fn resolve_recenv_origin(&self, origin: Origin) -> Origin {
+        let Origin::Output(node, _) = origin else {
+            return origin;
+        };
+
+        let region = self.nodes[node].region;
+        let Some(env) = self.regions[region]
+            .container_node
+            .filter(|&env| self.is_kind::<RecEnv>(env))
+        else {
+            return origin;
+        };
+
+        match self.get::<RecEnv>(id::Node::new(env)).lambdas.get(&node) {
+            Some(&(argument, _)) if self.region_is_within(self.region, region) => {
+                Origin::Argument(region, argument)
+            }
+            Some(&(_, output)) => self.resolve_recenv_origin(Origin::Output(env, output)),
+            None => origin,
+        }
+    }
+
+    /// Check that connecting `origin` to `user` from the current region doesn't form a cycle
+    /// which can't be resolved by wrapping lambdas in a recenv.
+    ///
+    /// Cycles through several recenvs are resolved by merging those recenvs.
+    pub(crate) fn check_recursion(
+        &self,
+        origin: Origin,
+        user: User,
+    ) -> std::result::Result<(), Error> {
+        let Origin::Output(node, _) = origin else {
+            return Ok(());
+        };
+
+        let region = self.nodes[node].region;
+
+        // The node which will end up using the origin from within its region
+        let using = if region == self.region {
+            match user {
+                User::Input(using, _) => using,
+                User::Result(..) => return Ok(()),
+            }
+        } else {
+            let mut inner = self.region;
+            loop {
+                let container = self.regions[inner]
+                    .container_node
+                    .expect("origin region is a parent of the current region");
+                inner = self.nodes[container].region;
+                if inner == region {
+                    break container;
+                }
+            }
+        };
+
+        for through in self.dependency_cycle(region, node, using) {
+            if !self.is_kind::<Lambda>(through) && !self.is_kind::<RecEnv>(through) {
+                return Err(Error::Cycle {
+                    origin,
+                    user,
+                    through,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Look through the edges added since `edges_before` for dependency cycles, wrapping the
+    /// lambdas of each cycle in a recenv.
+    pub(crate) fn wrap_recursive_lambdas(
+        &mut self,
+        edges_before: Vec<(id::Region, usize)>,
+    ) -> std::result::Result<(), Error> {
+        let mut added = vec![];
+        for (region, before) in edges_before {
+            let edges = &self.regions[region].edges;
+            added.extend(
+                edges[before.min(edges.len())..]
+                    .iter()
+                    .map(|&edge| (region, edge)),
+            );
+        }
+
+        for (region, edge) in added {
+            let (Origin::Output(origin, _), User::Input(user, _)) = (edge.origin, edge.user) else {
+                continue;
+            };
+
+            // An earlier cycle might've already been resolved by this
+            if !self.regions[region].edges.contains(&edge) {
+                continue;
+            }
+
+            let cycle = self.dependency_cycle(region, origin, user);
+            if cycle.is_empty() {
+                continue;
+            }
+
+            let mut envs = vec![];
+            let mut lambdas = vec![];
+            for node in cycle {
+                if self.is_kind::<RecEnv>(node) {
+                    envs.push(id::Node::<RecEnv>::new(node));
+                } else if self.is_kind::<Lambda>(node) {
+                    lambdas.push(id::Node::<Lambda>::new(node));
+                } else {
+                    unreachable!("cycles through non-lambdas are rejected by `check_recursion`");
+                }
+            }
+
+            let env = match envs[..] {
+                [] => self.in_region(region, |ctx| ctx.add_recenv_node()),
+                [env, ref merged @ ..] => {
+                    for &other in merged {
+                        trace!("merging {other} into {env}");
+                        lambdas.extend(self.unwrap_recenv(other));
+                    }
+                    env
+                }
+            };
+
+            trace!("wrapping {} lambdas in recursive {env}", lambdas.len());
+
+            self.move_lambdas_to_recenv(env, &lambdas)?;
+        }
+
+        Ok(())
+    }
+
+    // Move the lambdas of a recenv out into the region of the recenv and remove it, referring to
+    // the lambdas directly instead of through the recursion arguments and outputs.
+    fn unwrap_recenv(&mut self, env: id::Node<RecEnv>) -> Vec<id::Node<Lambda>> {
+        let region = self.nodes[env.id].region;
+        let env_region = self.region(env.id);
+
+        let mut lambdas: Vec<_> = self
+            .get(env)
+            .lambdas
+            .iter()
+            .map(|(&l, &p)| (l, p))
+            .collect();
+        lambdas.sort_by_key(|(lambda, _)| *lambda);
+
+        let lambda_origin = |ctx: &Self, lambda: id::AnyNode| {
+            Origin::Output(lambda, ctx.lambda_output(id::Node::new(lambda)))
+        };
+
+        // Where each argument of the recenv region comes from once the lambdas are outside of it
+        let outer_origin = |ctx: &Self, argument: id::Argument| match lambdas
+            .iter()
+            .find(|(_, (a, _))| *a == argument)
+        {
+            Some(&(lambda, _)) => Some(lambda_origin(ctx, lambda)),
+            None => ctx
+                .argument_as_input(env_region, argument)
+                .and_then(|input| ctx.origin_of(User::Input(env.id, input.id))),
+        };
+
+        let edges = self.regions[env_region].edges.clone();
+        self.retain_edges(env_region, |_| false);
+        for edge in edges {
+            if let User::Result(..) = edge.user {
+                continue;
+            }
+
+            let origin = match edge.origin {
+                Origin::Argument(r, argument) if r == env_region => {
+                    match outer_origin(self, argument) {
+                        Some(origin) => origin,
+                        None => continue,
+                    }
+                }
+                origin => origin,
+            };
+
+            self.push_edge(
+                region,
+                Edge {
+                    origin,
+                    user: edge.user,
+                },
+            );
+        }
+
+        let outputs: HashMap<_, _> = lambdas
+            .iter()
+            .map(|&(lambda, (_, output))| (output, lambda_origin(self, lambda)))
+            .collect();
+        self.rewrite_edges(region, |edge| {
+            if let Origin::Output(node, output) = edge.origin
+                && node == env.id
+            {
+                edge.origin = outputs[&output];
+            }
+        });
+
+        for &(lambda, _) in &lambdas {
+            self.move_node(lambda, region);
+        }
+        self.get_mut(env).lambdas.clear();
+        self.remove_node(env.id);
+
+        lambdas
+            .into_iter()
+            .map(|(lambda, _)| id::Node::new(lambda))
+            .collect()
+    }
+
+    // Nodes in `region` which are part of a cycle caused by `origin` being used by `user`.
+    //
+    // Returns an empty set if there's no such cycle.
+    fn dependency_cycle(
+        &self,
+        region: id::Region,
+        origin: id::AnyNode,
+        user: id::AnyNode,
+    ) -> Vec<id::AnyNode> {
+        let mut users: HashMap<id::AnyNode, Vec<id::AnyNode>> = HashMap::new();
+        let mut origins: HashMap<id::AnyNode, Vec<id::AnyNode>> = HashMap::new();
+        for edge in &self.regions[region].edges {
+            if let (Origin::Output(from, _), User::Input(to, _)) = (edge.origin, edge.user) {
+                users.entry(from).or_default().push(to);
+                origins.entry(to).or_default().push(from);
+            }
+        }
+
+        let reachable = |start: id::AnyNode, next: &HashMap<_, Vec<_>>| {
+            let mut seen = HashSet::from([start]);
+            let mut stack = vec![start];
+            while let Some(node) = stack.pop() {
+                for &n in next.get(&node).into_iter().flatten() {
+                    if seen.insert(n) {
+                        stack.push(n);
+                    }
+                }
+            }
+            seen
+        };
+
+        // With `origin` used by `user`, everything downstream of `user` which is also upstream of
+        // `origin` forms a cycle.
+        let downstream = reachable(user, &users);
+        if !downstream.contains(&origin) {
+            return vec![];
+        }
+
+        let upstream = reachable(origin, &origins);
+        let mut cycle: Vec<_> = downstream.intersection(&upstream).copied().collect();
+        cycle.sort_by_key(|node| node.as_u32());
+        cycle
+    }
+
+    pub(crate) fn lambda_output_of(&self, lambda: id::Node<Lambda>) -> Output<Lambda> {
+        Output {
+            node: lambda,
+            id: self.lambda_output(lambda),
+        }
     }
 
     // Get another lambda from the recenv, if its been connected.
@@ -97,3 +640,8 @@ impl TranslationUnitContext {
     //     Err(env_lambda_argument)
     // }
 }
+
+fn edge_touches_node(edge: &Edge, node: id::AnyNode) -> bool {
+    matches!(edge.origin, Origin::Output(n, _) if n == node)
+        || matches!(edge.user, User::Input(n, _) if n == node)
+}
@@ -0,0 +1,81 @@
+use super::*;
+use std::collections::VecDeque;
+
+impl TranslationUnitContext {
+    /// The nodes of a region ordered so that each node comes after the nodes it uses.
+    ///
+    /// The order is deterministic. Nodes without dependencies within the region come first, in
+    /// the order they were added in, and every other node follows as soon as the last of its
+    /// dependencies has been placed.
+    pub fn topological_nodes(&self, region: id::Region) -> impl Iterator<Item = id::AnyNode> {
+        self.try_topological_nodes(region)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_topological_nodes(
+        &self,
+        region: id::Region,
+    ) -> std::result::Result<impl Iterator<Item = id::AnyNode>, Error> {
+        self.topological_order(region).map(Vec::into_iter)
+    }
+
+    /// The nodes of a region ordered so that each node comes before the nodes it uses.
+    pub fn reverse_topological_nodes(
+        &self,
+        region: id::Region,
+    ) -> impl Iterator<Item = id::AnyNode> {
+        self.try_reverse_topological_nodes(region)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_reverse_topological_nodes(
+        &self,
+        region: id::Region,
+    ) -> std::result::Result<impl Iterator<Item = id::AnyNode>, Error> {
+        self.topological_order(region)
+            .map(|order| order.into_iter().rev())
+    }
+
+    fn topological_order(
+        &self,
+        region: id::Region,
+    ) -> std::result::Result<Vec<id::AnyNode>, Error> {
+        let nodes: Vec<_> = self.nodes(region).collect();
+
+        // The amount of edges from nodes not yet in the order
+        let mut pending: HashMap<id::AnyNode, usize> = nodes.iter().map(|&n| (n, 0)).collect();
+        let mut users: HashMap<id::AnyNode, Vec<id::AnyNode>> = HashMap::new();
+
+        for edge in &self.regions[region].edges {
+            if let (Origin::Output(from, _), User::Input(to, _)) = (edge.origin, edge.user)
+                && pending.contains_key(&from)
+                && let Some(count) = pending.get_mut(&to)
+            {
+                *count += 1;
+                users.entry(from).or_default().push(to);
+            }
+        }
+
+        let mut ready: VecDeque<_> = nodes.iter().copied().filter(|n| pending[n] == 0).collect();
+        let mut order = Vec::with_capacity(nodes.len());
+
+        while let Some(node) = ready.pop_front() {
+            order.push(node);
+
+            for user in users.get(&node).into_iter().flatten() {
+                let count = pending.get_mut(user).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push_back(*user);
+                }
+            }
+        }
+
+        if order.len() != nodes.len() {
+            let nodes = nodes.into_iter().filter(|n| pending[n] != 0).collect();
+            return Err(Error::CyclicRegion { region, nodes });
+        }
+
+        Ok(order)
+    }
+}
@@ -0,0 +1,141 @@
+use super::*;
+use std::time::Instant;
+use tracing::info_span;
+
+/// A transformation of the whole translation unit
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    /// Run the pass, returning whether anything changed.
+    fn run(&mut self, ctx: &mut TranslationUnitContext) -> bool;
+}
+
+/// See [`TranslationUnitContext::eliminate_dead_nodes`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeadNodeElimination;
+
+impl Pass for DeadNodeElimination {
+    fn name(&self) -> &str {
+        "dne"
+    }
+
+    fn run(&mut self, ctx: &mut TranslationUnitContext) -> bool {
+        ctx.eliminate_dead_nodes()
+    }
+}
+
+/// See [`TranslationUnitContext::eliminate_common_nodes`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommonNodeElimination;
+
+impl Pass for CommonNodeElimination {
+    fn name(&self) -> &str {
+        "cne"
+    }
+
+    fn run(&mut self, ctx: &mut TranslationUnitContext) -> bool {
+        ctx.eliminate_common_nodes()
+    }
+}
+
+/// See [`TranslationUnitContext::fold_constants`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &str {
+        "fold"
+    }
+
+    fn run(&mut self, ctx: &mut TranslationUnitContext) -> bool {
+        ctx.fold_constants()
+    }
+}
+
+/// Runs a pipeline of passes in the order they were added
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    fixpoint: bool,
+    verify: bool,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_pass(&mut self, pass: impl Pass + 'static) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Repeat the pipeline until none of the passes change anything.
+    ///
+    /// The passes need to eventually stop reporting changes, or this never finishes.
+    pub fn run_to_fixpoint(&mut self, fixpoint: bool) -> &mut Self {
+        self.fixpoint = fixpoint;
+        self
+    }
+
+    /// Verify the graph after each pass which changed it
+    pub fn verify_between(&mut self, verify: bool) -> &mut Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Run the pipeline, returning whether anything changed.
+    pub fn run(&mut self, ctx: &mut TranslationUnitContext) -> bool {
+        self.try_run(ctx).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_run(
+        &mut self,
+        ctx: &mut TranslationUnitContext,
+    ) -> std::result::Result<bool, Error> {
+        let mut changed = false;
+
+        for iteration in 0.. {
+            let _span = info_span!("pipeline", iteration).entered();
+
+            let mut iteration_changed = false;
+            for pass in &mut self.passes {
+                iteration_changed |= run_pass(pass.as_mut(), ctx, self.verify)?;
+            }
+
+            changed |= iteration_changed;
+            if !(self.fixpoint && iteration_changed) {
+                break;
+            }
+        }
+
+        Ok(changed)
+    }
+}
+
+fn run_pass(
+    pass: &mut dyn Pass,
+    ctx: &mut TranslationUnitContext,
+    verify: bool,
+) -> std::result::Result<bool, Error> {
+    let name = pass.name().to_string();
+    let _span = info_span!("pass", name).entered();
+
+    let start = Instant::now();
+    let changed = pass.run(ctx);
+    let elapsed = start.elapsed();
+
+    info!(?elapsed, changed, "ran {name}");
+
+    if verify && changed {
+        let diagnostics = ctx.verify();
+        if !diagnostics.is_empty() {
+            return Err(Error::VerifyFailed {
+                pass: name,
+                diagnostics,
+            });
+        }
+    }
+
+    Ok(changed)
+}
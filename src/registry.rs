@@ -0,0 +1,105 @@
+use super::*;
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex};
+
+pub(crate) type Decoder = Box<dyn Fn(&[u8]) -> Option<Box<dyn NodeKind + Send + Sync>>>;
+pub(crate) type Factory = Box<dyn Fn(Option<&str>) -> Option<Box<dyn NodeKind + Send + Sync>>>;
+
+/// Creates the kinds of nodes when reading a graph, see [`TranslationUnitContext::from_xml_with`],
+/// [`TranslationUnitContext::from_text_with`] and [`TranslationUnitContext::load_with`].
+///
+/// The built-in kinds are registered from the start. Other kinds are created by the factory
+/// registered for their name, which is given the payload of the node, or by the decoder
+/// registered for them when loading the binary format.
+pub struct KindRegistry {
+    pub(crate) kinds: HashMap<String, Factory>,
+    pub(crate) types: HashMap<String, Type>,
+    pub(crate) decoders: HashMap<String, Decoder>,
+}
+
+impl Default for KindRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KindRegistry {
+    pub fn new() -> Self {
+        let mut registry = KindRegistry {
+            kinds: HashMap::new(),
+            types: HashMap::new(),
+            decoders: HashMap::new(),
+        };
+
+        registry
+            .register("apply", |_| Some(Box::new(Apply {})))
+            .register("theta", |_| Some(Box::new(DoWhile {})))
+            .register("gamma", |_| Some(Box::new(Gamma {})))
+            .register("delta", |_| Some(Box::new(GlobalV {})))
+            .register("lambda", |_| Some(Box::new(Lambda {})))
+            .register("phi", |_| {
+                let lambdas = HashMap::new();
+                Some(Box::new(RecEnv { lambdas }))
+            })
+            .register("number", |payload| {
+                let n = payload?.parse().ok()?;
+                Some(Box::new(Number(n)))
+            })
+            .register("placeholder", |payload| {
+                Some(Box::new(Placeholder(intern(payload?))))
+            })
+            .register_type("state", state::STATE.clone())
+            .register_decoder("phi", binary::decode_recenv)
+            .register_decoder("omega", binary::decode_translation_unit);
+
+        registry
+    }
+
+    /// Create nodes with the `type` attribute using the factory.
+    ///
+    /// The factory returns `None` if the payload is missing or invalid.
+    pub fn register(
+        &mut self,
+        kind: impl Into<String>,
+        factory: impl Fn(Option<&str>) -> Option<Box<dyn NodeKind + Send + Sync>> + 'static,
+    ) -> &mut Self {
+        self.kinds.insert(kind.into(), Box::new(factory));
+        self
+    }
+
+    /// Create nodes saved in binary through [`Encode`](crate::nodes::Encode) using the decoder.
+    ///
+    /// The decoder returns `None` if the bytes are invalid.
+    pub fn register_decoder(
+        &mut self,
+        kind: impl Into<String>,
+        decoder: impl Fn(&[u8]) -> Option<Box<dyn NodeKind + Send + Sync>> + 'static,
+    ) -> &mut Self {
+        self.decoders.insert(kind.into(), Box::new(decoder));
+        self
+    }
+
+    /// Give ports with the `type` attribute the type.
+    ///
+    /// Ports in XML with types which aren't registered are left without a type, while the textual
+    /// IR rejects them.
+    pub fn register_type(&mut self, name: impl Into<String>, ty: Type) -> &mut Self {
+        self.types.insert(name.into(), ty);
+        self
+    }
+}
+
+// Placeholders are named by static strings
+fn intern(name: &str) -> &'static str {
+    static NAMES: LazyLock<Mutex<HashSet<&'static str>>> = LazyLock::new(Mutex::default);
+
+    let mut names = NAMES.lock().unwrap();
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name = Box::leak(name.to_string().into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use super::*;
+
+// Removing a port renumbers all ports after it, so edges referring to those are rewritten.
+//
+// Ports which only make sense together (such as the input, argument, result and output of a loop
+// variable) are removed together.
+
+impl TranslationUnitContext {
+    /// Remove a node along with all edges connected to it.
+    ///
+    /// Users of the node's outputs are left without an origin.
+    pub fn remove_node(&mut self, node: id::AnyNode) {
+        if let Err(err) = self.try_remove_node(node) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_remove_node(&mut self, node: id::AnyNode) -> std::result::Result<(), Error> {
+        self.check_node(node)?;
+        let region = self.nodes[node].region;
+
+        let rnodes = &mut self.regions[region].nodes;
+        let i = rnodes
+            .as_slice(&self.node_id_pool)
+            .iter()
+            .position(|n| *n == node)
+            .ok_or(Error::NodeNotInRegion { node, region })?;
+        rnodes.remove(i, &mut self.node_id_pool);
+
+        trace!("removing {}", self.debug_node(node));
+
+        self.retain_edges(region, |edge| {
+            !matches!(edge.origin, Origin::Output(n, _) if n == node)
+                && !matches!(edge.user, User::Input(n, _) if n == node)
+        });
+
+        if self.symbols.get(node).is_some() {
+            self.symbols[node] = String::new();
+        }
+
+        // A lambda of a recenv takes its recursion argument and output with it
+        if let Some(env) = self.regions[region].container_node
+            && let Ok(env) = self.try_cast::<RecEnv>(env)
+            && let Some(&(_, output)) = self.get(env).lambdas.get(&node)
+        {
+            self.remove_recenv_lambda(env, output);
+        }
+
+        Ok(())
+    }
+
+    /// Remove an edge
+    pub fn remove_edge(&mut self, origin: impl Into<Origin>, user: impl Into<User>) {
+        if let Err(err) = self.try_remove_edge(origin, user) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_remove_edge(
+        &mut self,
+        origin: impl Into<Origin>,
+        user: impl Into<User>,
+    ) -> std::result::Result<(), Error> {
+        let origin = origin.into();
+        let user = user.into();
+        self.check_user(user)?;
+
+        let region = match user {
+            User::Input(node, _) => self.nodes[node].region,
+            User::Result(region, _) => region,
+        };
+
+        let edge = Edge { origin, user };
+        if !self.regions[region].edges.contains(&edge) {
+            return Err(Error::NoSuchEdge { origin, user });
+        }
+        self.retain_edges(region, |e| *e != edge);
+
+        Ok(())
+    }
+
+    /// Remove an input from a node along with the argument it's forwarded as in each region.
+    ///
+    /// For dowhile nodes this removes the entire loop variable.
+    pub fn remove_input<K>(&mut self, input: Input<K>) {
+        if let Err(err) = self.try_remove_input(input) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_remove_input<K>(&mut self, input: Input<K>) -> std::result::Result<(), Error> {
+        let node = input.node.id;
+        self.check_user(User::Input(node, input.id))?;
+
+        if self.is_kind::<Gamma>(node) && input.id.as_u32() == 0 {
+            return Err(Error::RequiredPort { node });
+        }
+
+        if let Ok(node) = self.try_cast::<DoWhile>(node) {
+            self.remove_loop_var(node, input.id.as_u32());
+        } else {
+            self.remove_input_port(node, input.id);
+        }
+
+        Ok(())
+    }
+
+    /// Remove an output from a node.
+    ///
+    /// For gamma nodes this also removes the matching result of every branch, for dowhile nodes
+    /// the entire loop variable and for recenv nodes the lambda's recursion argument.
+    pub fn remove_output<K>(&mut self, output: Output<K>) {
+        if let Err(err) = self.try_remove_output(output) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_remove_output<K>(&mut self, output: Output<K>) -> std::result::Result<(), Error> {
+        let node = output.node.id;
+        self.check_origin(Origin::Output(node, output.id))?;
+
+        if self.is_kind::<Lambda>(node) || self.is_kind::<GlobalV>(node) {
+            return Err(Error::RequiredPort { node });
+        }
+
+        if let Ok(node) = self.try_cast::<DoWhile>(node) {
+            self.remove_loop_var(node, output.id.as_u32());
+        } else if let Ok(env) = self.try_cast::<RecEnv>(node) {
+            self.remove_recenv_lambda(env, output.id);
+        } else {
+            if self.is_kind::<Gamma>(node) {
+                let result = id::Result::from_u32(output.id.as_u32());
+                for region in self.regions(node).to_vec() {
+                    self.remove_result_port(region, result);
+                }
+            }
+            self.remove_output_port(node, output.id);
+        }
+
+        Ok(())
+    }
+
+    /// Remove an argument from a region.
+    ///
+    /// Arguments forwarded from an input are removed along with the input.
+    pub fn remove_argument(&mut self, argument: Argument) {
+        if let Err(err) = self.try_remove_argument(argument) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_remove_argument(&mut self, argument: Argument) -> std::result::Result<(), Error> {
+        self.check_origin(argument.into())?;
+
+        let Some(node) = self.regions[argument.region].container_node else {
+            self.remove_argument_port(argument.region, argument.id);
+            return Ok(());
+        };
+
+        if let Some(input) = self.argument_as_input(argument.region, argument.id) {
+            return self.try_remove_input(input);
+        }
+
+        if let Ok(env) = self.try_cast::<RecEnv>(node) {
+            let recursive = self.get(env).lambdas.values();
+            let Some(&(_, output)) = recursive.clone().find(|(a, _)| *a == argument.id) else {
+                unreachable!("all custom arguments of a recenv are recursion arguments");
+            };
+            self.remove_recenv_lambda(env, output);
+        } else {
+            self.remove_argument_port(argument.region, argument.id);
+        }
+
+        Ok(())
+    }
+
+    /// Remove a result from a region.
+    ///
+    /// Results matching an output are removed along with the output.
+    pub fn remove_result(&mut self, result: Result) {
+        if let Err(err) = self.try_remove_result(result) {
+            panic!("{err}");
+        }
+    }
+
+    pub fn try_remove_result(&mut self, result: Result) -> std::result::Result<(), Error> {
+        self.check_user(result.into())?;
+
+        let Some(node) = self.regions[result.region].container_node else {
+            self.remove_result_port(result.region, result.id);
+            return Ok(());
+        };
+
+        let output = id::Output::from_u32(result.id.as_u32());
+
+        if let Ok(node) = self.try_cast::<DoWhile>(node) {
+            match result.id.as_u32() {
+                0 => return Err(Error::RequiredPort { node: node.id }),
+                n => self.remove_loop_var(node, n - 1),
+            }
+        } else if self.is_kind::<Gamma>(node) || self.is_kind::<RecEnv>(node) {
+            let node = id::Node::<id::AnyNode>::new(node);
+            return self.try_remove_output(Output { node, id: output });
+        } else if self.is_kind::<GlobalV>(node) {
+            return Err(Error::RequiredPort { node });
+        } else {
+            self.remove_result_port(result.region, result.id);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn remove_loop_var(&mut self, node: id::Node<DoWhile>, n: u32) {
+        let region = self.region(node.id);
+        self.remove_result_port(region, id::Result::from_u32(n + 1));
+        self.remove_output_port(node.id, id::Output::from_u32(n));
+        self.remove_input_port(node.id, id::Input::from_u32(n));
+    }
+
+    fn remove_recenv_lambda(&mut self, env: id::Node<RecEnv>, output: id::Output) {
+        let region = self.region(env.id);
+        let lambdas = &mut self.get_mut(env).lambdas;
+
+        let Some(&lambda) = lambdas
+            .iter()
+            .find(|(_, (_, o))| *o == output)
+            .map(|(l, _)| l)
+        else {
+            return;
+        };
+        let (argument, _) = lambdas.remove(&lambda).unwrap();
+
+        for (a, o) in lambdas.values_mut() {
+            if *a > argument {
+                *a = id::Argument::from_u32(a.as_u32() - 1);
+            }
+            if *o > output {
+                *o = id::Output::from_u32(o.as_u32() - 1);
+            }
+        }
+
+        self.remove_argument_port(region, argument);
+        self.remove_result_port(region, id::Result::from_u32(output.as_u32()));
+        self.remove_output_port(env.id, output);
+    }
+
+    // Remove an input and the arguments it's forwarded as without regard for the kind of node
+    pub(crate) fn remove_input_port(&mut self, node: id::AnyNode, input: id::Input) {
+        let forwarded: Vec<_> = self
+            .regions(node)
+            .iter()
+            .map(|&region| {
+                let argument = self.input_as_argument_in(
+                    Input {
+                        node: id::Node::<id::AnyNode>::new(node),
+                        id: input,
+                    },
+                    region,
+                );
+                (region, argument.id)
+            })
+            .collect();
+
+        let region = self.nodes[node].region;
+        self.retain_edges(region, |edge| edge.user != User::Input(node, input));
+        self.rewrite_edges(region, |edge| {
+            if let User::Input(n, i) = &mut edge.user
+                && *n == node
+                && *i > input
+            {
+                *i = id::Input::from_u32(i.as_u32() - 1);
+            }
+        });
+        self.nodes[node].inputs -= 1;
+        self.nodes[node].input_types.remove(input.as_u32() as usize);
+
+        for (region, argument) in forwarded {
+            self.remove_argument_port(region, argument);
+        }
+    }
+
+    fn remove_output_port(&mut self, node: id::AnyNode, output: id::Output) {
+        let region = self.nodes[node].region;
+        self.retain_edges(region, |edge| edge.origin != Origin::Output(node, output));
+        self.rewrite_edges(region, |edge| {
+            if let Origin::Output(n, o) = &mut edge.origin
+                && *n == node
+                && *o > output
+            {
+                *o = id::Output::from_u32(o.as_u32() - 1);
+            }
+        });
+        self.nodes[node].outputs -= 1;
+        self.nodes[node]
+            .output_types
+            .remove(output.as_u32() as usize);
+    }
+
+    fn remove_argument_port(&mut self, region: id::Region, argument: id::Argument) {
+        self.retain_edges(region, |edge| {
+            edge.origin != Origin::Argument(region, argument)
+        });
+        self.rewrite_edges(region, |edge| {
+            if let Origin::Argument(r, a) = &mut edge.origin
+                && *r == region
+                && *a > argument
+            {
+                *a = id::Argument::from_u32(a.as_u32() - 1);
+            }
+        });
+        self.regions[region].arguments -= 1;
+        self.regions[region]
+            .argument_types
+            .remove(argument.as_u32() as usize);
+    }
+
+    fn remove_result_port(&mut self, region: id::Region, result: id::Result) {
+        self.retain_edges(region, |edge| edge.user != User::Result(region, result));
+        self.rewrite_edges(region, |edge| {
+            if let User::Result(r, res) = &mut edge.user
+                && *r == region
+                && *res > result
+            {
+                *res = id::Result::from_u32(res.as_u32() - 1);
+            }
+        });
+        self.regions[region].results -= 1;
+        self.regions[region]
+            .result_types
+            .remove(result.as_u32() as usize);
+    }
+}
@@ -0,0 +1,243 @@
+use super::*;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Regions and nodes are listed in the order of their ids, like in the binary format. Port types
+// are written by name, and node kinds by their type along with their payload or the bytes from
+// their `Encode` impl, to be recreated through a `KindRegistry`.
+
+#[derive(Serialize, Deserialize)]
+struct Unit {
+    regions: Vec<RegionData>,
+    nodes: Vec<NodeData>,
+    region: id::Region,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegionData {
+    container_node: Option<id::AnyNode>,
+    argument_types: Vec<Option<String>>,
+    result_types: Vec<Option<String>>,
+    nodes: Vec<id::AnyNode>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NodeData {
+    region: id::Region,
+    input_types: Vec<Option<String>>,
+    output_types: Vec<Option<String>>,
+    regions: Vec<id::Region>,
+    kind: KindData,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    symbol: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KindData {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<u8>>,
+}
+
+fn type_names(types: &[Option<Type>]) -> Vec<Option<String>> {
+    types
+        .iter()
+        .map(|ty| ty.as_ref().map(|ty| ty.to_string()))
+        .collect()
+}
+
+impl Serialize for TranslationUnitContext {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        let regions = self
+            .regions
+            .values()
+            .map(|region| RegionData {
+                container_node: region.container_node,
+                argument_types: type_names(&region.argument_types),
+                result_types: type_names(&region.result_types),
+                nodes: region.nodes.as_slice(&self.node_id_pool).to_vec(),
+                edges: region.edges.clone(),
+            })
+            .collect();
+
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(id, node)| {
+                let data = node.kind.as_encode().map(|encode| {
+                    let mut bytes = vec![];
+                    encode.encode(&mut bytes);
+                    bytes
+                });
+                NodeData {
+                    region: node.region,
+                    input_types: type_names(&node.input_types),
+                    output_types: type_names(&node.output_types),
+                    regions: self.regions(id).to_vec(),
+                    kind: KindData {
+                        kind: node.kind.node_type().to_string(),
+                        payload: data.is_none().then(|| node.kind.payload()).flatten(),
+                        data,
+                    },
+                    symbol: self.symbols.get(id).cloned().unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        Unit {
+            regions,
+            nodes,
+            region: self.region,
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for TranslationUnitContext {
+    /// Only the built-in node kinds can be deserialized, see [`Self::deserialize_with`] for
+    /// custom kinds.
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        Self::deserialize_with(d, &KindRegistry::new())
+    }
+}
+
+impl TranslationUnitContext {
+    /// Deserialize a translation unit, creating the kinds of nodes and the types of ports through
+    /// the registry.
+    pub fn deserialize_with<'de, D: Deserializer<'de>>(
+        d: D,
+        kinds: &KindRegistry,
+    ) -> std::result::Result<Self, D::Error> {
+        let unit = Unit::deserialize(d)?;
+
+        let node_count = unit.nodes.len() as u32;
+        let region_count = unit.regions.len() as u32;
+        let node = |node: id::AnyNode| match node.as_u32() < node_count {
+            true => Ok(node),
+            false => Err(D::Error::custom(format!("there's no node {node}"))),
+        };
+        let region = |region: id::Region| match region.as_u32() < region_count {
+            true => Ok(region),
+            false => Err(D::Error::custom(format!("there's no region {region}"))),
+        };
+        let types = |names: Vec<Option<String>>| {
+            names
+                .into_iter()
+                .map(|name| match name {
+                    None => Ok(None),
+                    Some(name) => kinds.types.get(&name).cloned().map(Some).ok_or_else(|| {
+                        D::Error::custom(format!("no type is registered as {name}"))
+                    }),
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()
+        };
+
+        let mut ctx = TranslationUnitContext {
+            nodes: PrimaryMap::new(),
+            regions: PrimaryMap::new(),
+            symbols: SecondaryMap::new(),
+            uses: uses::Uses::default(),
+            node_id_pool: ListPool::new(),
+            region_id_pool: ListPool::new(),
+            region: region(unit.region)?,
+        };
+
+        let mut edges = vec![];
+        for data in unit.regions {
+            let mut nodes = EntityList::new();
+            for &n in &data.nodes {
+                nodes.push(node(n)?, &mut ctx.node_id_pool);
+            }
+
+            let argument_types = types(data.argument_types)?;
+            let result_types = types(data.result_types)?;
+            let id = ctx.regions.push(Region {
+                container_node: data.container_node.map(node).transpose()?,
+                arguments: argument_types.len() as u32,
+                results: result_types.len() as u32,
+                argument_types,
+                result_types,
+                edges: vec![],
+                nodes,
+            });
+
+            edges.extend(data.edges.into_iter().map(|edge| (id, edge)));
+        }
+
+        for data in unit.nodes {
+            let KindData {
+                kind,
+                payload,
+                data: bytes,
+            } = data.kind;
+            let created = match bytes {
+                Some(bytes) => kinds.decoders.get(&kind).map(|decoder| decoder(&bytes)),
+                None => kinds
+                    .kinds
+                    .get(&kind)
+                    .map(|factory| factory(payload.as_deref())),
+            }
+            .ok_or_else(|| D::Error::custom(format!("no node kind is registered as {kind}")))?
+            .ok_or_else(|| D::Error::custom(format!("invalid data for a {kind} node")))?;
+
+            let mut regions = EntityList::new();
+            for &r in &data.regions {
+                regions.push(region(r)?, &mut ctx.region_id_pool);
+            }
+
+            let input_types = types(data.input_types)?;
+            let output_types = types(data.output_types)?;
+            let id = ctx.nodes.next_key();
+            ctx.nodes.push(Node {
+                id,
+                region: region(data.region)?,
+                inputs: input_types.len() as u32,
+                outputs: output_types.len() as u32,
+                input_types,
+                output_types,
+                regions,
+                kind: created,
+            });
+            if !data.symbol.is_empty() {
+                ctx.add_symbol(id, data.symbol);
+            }
+        }
+
+        let omega = id::AnyNode::from_u32(0);
+        if ctx.nodes.is_empty() || !ctx.is_kind::<TranslationUnit>(omega) {
+            return Err(D::Error::custom("the omega node is missing"));
+        }
+
+        if !binary::structure_is_consistent(&ctx) {
+            return Err(D::Error::custom("the nodes and regions don't fit together"));
+        }
+
+        if let Some(node) = ctx
+            .nodes
+            .keys()
+            .find(|&node| !binary::kind_references_exist(&ctx, node))
+        {
+            return Err(D::Error::custom(format!(
+                "{node} refers to nodes or ports which don't exist"
+            )));
+        }
+
+        for (region, edge) in edges {
+            ctx.check_origin(edge.origin)
+                .and_then(|_| ctx.check_user(edge.user))
+                .map_err(D::Error::custom)?;
+            if !ctx.edge_is_in(region, edge) {
+                return Err(D::Error::custom(format!(
+                    "an edge of {region} connects ports outside of it"
+                )));
+            }
+            ctx.push_edge(region, edge);
+        }
+
+        Ok(ctx)
+    }
+}
@@ -0,0 +1,108 @@
+use super::*;
+use std::fmt;
+use std::sync::{Arc, LazyLock};
+
+/// The type of state edges.
+///
+/// State edges order side effects such as memory accesses and I/O. A state is produced by one
+/// node and consumed by the next, so nodes threading the same state can not be reordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct State;
+value_type_impl!(State);
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "state")
+    }
+}
+
+pub(crate) static STATE: LazyLock<Type> = LazyLock::new(|| Arc::new(State));
+
+/// The ports threading a state through a gamma node
+#[derive(Debug, Clone)]
+pub struct GammaState {
+    pub input: Input<Gamma>,
+    pub arguments: Vec<Argument>,
+    pub results: Vec<Result>,
+    pub output: Output<Gamma>,
+}
+
+impl TranslationUnitContext {
+    pub fn add_state_input<K>(&mut self, node: id::Node<K>) -> Input<K> {
+        let input = self.add_input(node);
+        self.set_user_type(input, STATE.clone());
+        input
+    }
+
+    pub fn add_state_output<K>(&mut self, node: id::Node<K>) -> Output<K> {
+        let output = self.add_output(node);
+        self.set_origin_type(output, STATE.clone());
+        output
+    }
+
+    /// Add a state argument to the current region, such as the state a lambda is called with.
+    pub fn add_state_argument(&mut self) -> Argument {
+        let argument = self.add_argument();
+        self.set_origin_type(argument, STATE.clone());
+        argument
+    }
+
+    /// Add a state result to the current region, such as the state a lambda returns.
+    pub fn add_state_result(&mut self) -> Result {
+        let result = self.add_result();
+        self.set_user_type(result, STATE.clone());
+        result
+    }
+
+    /// Thread a state through a gamma node.
+    ///
+    /// The state is forwarded to every branch, which each have to connect the state they end
+    /// with to their result.
+    pub fn add_gamma_state(&mut self, node: id::Node<Gamma>) -> GammaState {
+        let input = self.add_state_input(node);
+        let arguments = self
+            .regions(node.id)
+            .iter()
+            .map(|&region| self.input_as_argument_in(input, region))
+            .collect();
+
+        let (results, output) = self.add_gamma_output(node);
+        self.set_origin_type(output, STATE.clone());
+        for &result in &results {
+            self.set_user_type(result, STATE.clone());
+        }
+
+        GammaState {
+            input,
+            arguments,
+            results,
+            output,
+        }
+    }
+
+    /// Thread a state through a dowhile node as a loop variable
+    pub fn add_state_loop_var(&mut self, node: id::Node<DoWhile>) -> LoopVar {
+        let var = self.add_loop_var(node);
+        self.set_user_type(var.input, STATE.clone());
+        var
+    }
+
+    /// Whether the origin is a state port
+    pub fn is_state_origin(&self, origin: impl Into<Origin>) -> bool {
+        self.origin_type(origin).is_some_and(is_state)
+    }
+
+    /// Whether the user is a state port
+    pub fn is_state_user(&self, user: impl Into<User>) -> bool {
+        self.user_type(user).is_some_and(is_state)
+    }
+
+    /// Whether the edge orders side effects rather than carrying a value
+    pub fn is_state_edge(&self, edge: &Edge) -> bool {
+        self.is_state_origin(edge.origin) || self.is_state_user(edge.user)
+    }
+}
+
+fn is_state(ty: &Type) -> bool {
+    ty.as_any().is::<State>()
+}
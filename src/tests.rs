@@ -1,5 +1,4 @@
 use crate::*;
-use std::io::Write;
 
 // fn fa x = fb (x + 1)
 // fn fb y = fa (y - 1)
@@ -9,7 +8,7 @@ fn phi() {
 
     let fa = ctx.add_lambda_node();
     let fa_region = ctx.region(fa.node.id);
-    ctx.in_region(fa_region, |ctx| {
+    let fb_input = ctx.in_region(fa_region, |ctx| {
         let x = ctx.add_argument();
         let fb_input = ctx.add_input(fa.node);
         let fb = ctx.input_as_argument(fb_input);
@@ -28,11 +27,13 @@ fn phi() {
 
         let result = ctx.add_result();
         ctx.connect(apply_output, result);
+
+        fb_input
     });
 
     let fb = ctx.add_lambda_node();
     let fb_region = ctx.region(fb.node.id);
-    ctx.in_region(fb_region, |ctx| {
+    let fa_input = ctx.in_region(fb_region, |ctx| {
         let x = ctx.add_argument();
         let fa_input = ctx.add_input(fb.node);
         let fa = ctx.input_as_argument(fa_input);
@@ -51,6 +52,8 @@ fn phi() {
 
         let result = ctx.add_result();
         ctx.connect(apply_output, result);
+
+        fa_input
     });
 
     let recenv = ctx.add_recenv_node();
@@ -60,28 +63,2758 @@ fn phi() {
     ctx.move_node(fb.node.id, recenv_region);
 
     let [fa_out, _fb_out] = ctx.in_region(recenv_region, |ctx| {
-        let (_fa_arg, fa_out) = ctx.move_lambda_to_recenv(fa.node);
-        let (_fb_arg, fb_out) = ctx.move_lambda_to_recenv(fb.node);
+        let (fa_arg, fa_out) = ctx.move_lambda_to_recenv(fa.node);
+        let (fb_arg, fb_out) = ctx.move_lambda_to_recenv(fb.node);
+
+        ctx.connect(fb_arg, fb_input);
+        ctx.connect(fa_arg, fa_input);
 
         [fa_out, fb_out]
     });
 
+    assert_eq!(ctx.arguments(recenv_region).count(), 2);
+    assert_eq!(ctx.results(recenv_region).count(), 2);
+    assert_eq!(ctx.get(recenv).lambdas.len(), 2);
+
     let main = ctx.add_lambda_node();
     let main_region = ctx.region(main.node.id);
-    ctx.in_region(main_region, |ctx| {
+    let main_fa_input = ctx.in_region(main_region, |ctx| {
         let main_fa_input = ctx.add_input(main.node);
         let main_fa_arg = ctx.input_as_argument(main_fa_input);
-        ctx.connect(fa_out, main_fa_input);
 
         let init = ctx.add_number_node(10);
         let apply = ctx.add_apply_node();
+        let apply_x = ctx.add_input(apply.node);
         ctx.connect(main_fa_arg, apply);
-        ctx.connect(init, apply);
+        ctx.connect(init, apply_x);
 
         let apply_output = ctx.add_output(apply.node);
         let result = ctx.add_result();
         ctx.connect(apply_output, result);
+
+        main_fa_input
+    });
+    ctx.connect(fa_out, main_fa_input);
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    assert_eq!(root, [recenv.id, main.node.id]);
+    assert_eq!(ctx.nodes(recenv_region).count(), 2);
+
+    assert_eq!(ctx.verify(), []);
+
+    ctx.to_xml();
+}
+
+// fn abs x = if x < 0 then 0 - x else x
+#[test]
+fn gamma() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let abs = ctx.add_lambda_node();
+    let abs_region = ctx.region(abs.node.id);
+    ctx.in_region(abs_region, |ctx| {
+        let x = ctx.add_argument();
+        let zero = ctx.add_number_node(0);
+
+        let less = ctx.add_placeholder_node("<");
+        let less_x = ctx.add_input(less.node);
+        let less_y = ctx.add_input(less.node);
+        ctx.connect(x, less_x);
+        ctx.connect(zero, less_y);
+
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(less, predicate);
+
+        let x_input = ctx.add_input(predicate.node);
+        ctx.connect(x, x_input);
+
+        let (results, output) = ctx.add_gamma_output(predicate.node);
+        assert_eq!(results.len(), 2);
+
+        let [negative, positive] = branches[..] else {
+            panic!("expected two branches");
+        };
+
+        ctx.in_region(negative, |ctx| {
+            let x = ctx.input_as_argument_in(x_input, negative);
+            let zero = ctx.add_number_node(0);
+            let minus = ctx.add_placeholder_node("-");
+            let minus_x = ctx.add_input(minus.node);
+            let minus_y = ctx.add_input(minus.node);
+            ctx.connect(zero, minus_x);
+            ctx.connect(x, minus_y);
+            ctx.connect(minus, results[0]);
+        });
+
+        ctx.in_region(positive, |ctx| {
+            let x = ctx.input_as_argument_in(x_input, positive);
+            ctx.connect(x, results[1]);
+        });
+
+        for branch in branches {
+            assert_eq!(ctx.arguments(branch).count(), 2);
+            assert_eq!(ctx.results(branch).count(), 1);
+        }
+
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+    });
+
+    assert_eq!(ctx.verify(), []);
+}
+
+// fn countdown n = do { n = n - step } while n > 0
+#[test]
+fn dowhile() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let countdown = ctx.add_lambda_node();
+    let countdown_region = ctx.region(countdown.node.id);
+    ctx.in_region(countdown_region, |ctx| {
+        let n = ctx.add_argument();
+        let step = ctx.add_number_node(1);
+
+        let (predicate, theta) = ctx.add_dowhile_node();
+        let n_var = ctx.add_loop_var(theta);
+        ctx.connect(n, n_var.input);
+
+        let body = ctx.region(theta.id);
+        ctx.in_region(body, |ctx| {
+            let minus = ctx.add_placeholder_node("-");
+            let minus_x = ctx.add_input(minus.node);
+            let minus_y = ctx.add_input(minus.node);
+            ctx.connect(n_var.argument, minus_x);
+            // Implicitly forwarded into the loop as an invariant loop variable
+            ctx.connect(step, minus_y);
+            ctx.connect(minus, n_var.result);
+
+            let zero = ctx.add_number_node(0);
+            let greater = ctx.add_placeholder_node(">");
+            let greater_x = ctx.add_input(greater.node);
+            let greater_y = ctx.add_input(greater.node);
+            ctx.connect(minus, greater_x);
+            ctx.connect(zero, greater_y);
+            ctx.connect(greater, predicate);
+        });
+
+        let vars: Vec<_> = ctx.loop_vars(theta).collect();
+        assert_eq!(vars.len(), 2);
+        assert_eq!(ctx.arguments(body).count(), 2);
+        assert_eq!(ctx.results(body).count(), 3);
+        assert_eq!(ctx.outputs(theta.id).count(), 2);
+
+        let step_var = vars[1];
+        assert!(ctx.in_region(body, |ctx| {
+            ctx.connection_exists(step_var.argument.into(), step_var.result.into())
+        }));
+
+        // Loop variables can't be added one port at a time
+        let looping = Err(Error::LoopVarPort { node: theta.id });
+        assert_eq!(ctx.try_add_input(theta).map(|_| ()), looping);
+        assert_eq!(ctx.try_add_output(theta).map(|_| ()), looping);
+        assert_eq!(
+            ctx.in_region(body, |ctx| ctx.try_add_argument().map(|_| ())),
+            looping
+        );
+        assert_eq!(
+            ctx.in_region(body, |ctx| ctx.try_add_result().map(|_| ())),
+            looping
+        );
+        assert_eq!(ctx.loop_vars(theta).count(), 2);
+
+        let result = ctx.add_result();
+        ctx.connect(n_var.output, result);
+    });
+
+    assert_eq!(ctx.verify(), []);
+}
+
+#[test]
+fn recenv_reroutes_edges() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let ten = ctx.add_number_node(10);
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    ctx.in_region(f_region, |ctx| {
+        let result = ctx.add_result();
+        ctx.connect(ten, result);
+    });
+
+    let main = ctx.add_lambda_node();
+    let main_region = ctx.region(main.node.id);
+    ctx.in_region(main_region, |ctx| {
+        let apply = ctx.add_apply_node();
+        ctx.connect(f, apply);
+    });
+
+    let recenv = ctx.add_recenv_node();
+    let recenv_region = ctx.region(recenv.id);
+    let (_, f_out) = ctx.in_region(recenv_region, |ctx| ctx.move_lambda_to_recenv(f.node));
+
+    let f_input = Input {
+        node: f.node,
+        id: id::Input::from_u32(0),
+    };
+    let main_input = Input {
+        node: main.node,
+        id: id::Input::from_u32(0),
+    };
+
+    // `f`'s use of the constant is now forwarded through the recenv
+    assert_eq!(ctx.inputs(recenv.id).count(), 1);
+    assert!(ctx.in_region(recenv_region, |ctx| {
+        ctx.connection_exists(ten.into(), f_input.into())
+    }));
+
+    // `main` now uses `f` through the recenv's output
+    assert!(ctx.connection_exists(f_out.into(), main_input.into()));
+    assert!(!ctx.connection_exists(f.into(), main_input.into()));
+}
+
+#[test]
+fn recenv_drops_forwarded_lambda() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+
+    let recenv = ctx.add_recenv_node();
+    let recenv_region = ctx.region(recenv.id);
+    let (g, f_arg) = ctx.in_region(recenv_region, |ctx| {
+        let g = ctx.add_lambda_node();
+        let g_region = ctx.region(g.node.id);
+        ctx.in_region(g_region, |ctx| {
+            let apply = ctx.add_apply_node();
+            ctx.connect(f, apply);
+        });
+
+        ctx.move_lambda_to_recenv(g.node);
+        let (f_arg, _) = ctx.move_lambda_to_recenv(f.node);
+        (g, f_arg)
+    });
+
+    // `f` was forwarded into the recenv, which is replaced by its recursion argument
+    assert_eq!(ctx.inputs(recenv.id).count(), 0);
+    assert_eq!(ctx.arguments(recenv_region).count(), 2);
+
+    let g_input = Input {
+        node: g.node,
+        id: id::Input::from_u32(0),
+    };
+    assert!(ctx.in_region(recenv_region, |ctx| {
+        ctx.connection_exists(f_arg.into(), g_input.into())
+    }));
+}
+
+// fn even n = odd n
+// fn odd n = even n
+// fn main = even 10
+#[test]
+fn mutual_recursion_forms_recenv() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let even = ctx.add_lambda_node();
+    let odd = ctx.add_lambda_node();
+
+    for (f, calls) in [(even, odd), (odd, even)] {
+        let region = ctx.region(f.node.id);
+        ctx.in_region(region, |ctx| {
+            let n = ctx.add_argument();
+            let apply = ctx.add_apply_node();
+            let apply_n = ctx.add_input(apply.node);
+            ctx.connect(calls, apply);
+            ctx.connect(n, apply_n);
+
+            let output = ctx.add_output(apply.node);
+            let result = ctx.add_result();
+            ctx.connect(output, result);
+        });
+    }
+
+    let main = ctx.add_lambda_node();
+    let main_region = ctx.region(main.node.id);
+    ctx.in_region(main_region, |ctx| {
+        let ten = ctx.add_number_node(10);
+        let apply = ctx.add_apply_node();
+        let apply_n = ctx.add_input(apply.node);
+        ctx.connect(even, apply);
+        ctx.connect(ten, apply_n);
+    });
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    let [recenv, m] = root[..] else {
+        panic!("expected a recenv and main in omega, got {root:?}");
+    };
+    assert_eq!(m, main.node.id);
+
+    let recenv = id::Node::<RecEnv>::new(recenv);
+    let recenv_region = ctx.region(recenv.id);
+    let lambdas = &ctx.get(recenv).lambdas;
+    assert_eq!(lambdas.len(), 2);
+    let (odd_arg, _) = lambdas[&odd.node.id];
+    let (_, even_out) = lambdas[&even.node.id];
+
+    // `even` refers to `odd` through the recursion argument
+    let even_input = Input {
+        node: even.node,
+        id: id::Input::from_u32(0),
+    };
+    let odd_arg = Argument {
+        region: recenv_region,
+        id: odd_arg,
+    };
+    assert!(ctx.in_region(recenv_region, |ctx| {
+        ctx.connection_exists(odd_arg.into(), even_input.into())
+    }));
+
+    // `main` refers to `even` through the recenv
+    let main_input = Input {
+        node: main.node,
+        id: id::Input::from_u32(0),
+    };
+    assert!(ctx.connection_exists(Origin::Output(recenv.id, even_out), main_input.into()));
+
+    assert_eq!(ctx.verify(), []);
+}
+
+// fn loop n = loop n
+#[test]
+fn self_recursion_forms_recenv() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    ctx.in_region(f_region, |ctx| {
+        let n = ctx.add_argument();
+        let apply = ctx.add_apply_node();
+        let apply_n = ctx.add_input(apply.node);
+        ctx.connect(f, apply);
+        ctx.connect(n, apply_n);
+    });
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    let [recenv] = root[..] else {
+        panic!("expected only a recenv in omega, got {root:?}");
+    };
+    let recenv = id::Node::<RecEnv>::new(recenv);
+    assert!(ctx.get(recenv).lambdas.contains_key(&f.node.id));
+    assert_eq!(ctx.arguments(ctx.region(recenv.id)).count(), 1);
+}
+
+#[test]
+#[should_panic(expected = "forms a cycle through non-lambda")]
+fn cycle_through_non_lambda() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let apply = ctx.add_apply_node();
+    let output = ctx.add_output(apply.node);
+    ctx.connect(f, apply);
+
+    let f_region = ctx.region(f.node.id);
+    ctx.in_region(f_region, |ctx| {
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+    });
+}
+
+#[test]
+fn errors() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let g = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    let g_region = ctx.region(g.node.id);
+
+    let x = ctx.in_region(f_region, |ctx| ctx.add_argument());
+
+    // `x` is only reachable from within `f`
+    let err = ctx.in_region(g_region, |ctx| {
+        let result = ctx.add_result();
+        ctx.try_connect(x, result)
+    });
+    assert!(matches!(err, Err(Error::NoPath { .. })));
+
+    let (_, branches) = ctx.add_gamma_node(2);
+    let gamma = ctx.nodes(ctx.region).last().unwrap();
+    assert_eq!(
+        ctx.try_region(gamma),
+        Err(Error::RegionCountMismatch {
+            node: gamma,
+            expected: 1,
+            found: branches.len(),
+        })
+    );
+    assert!(matches!(
+        ctx.try_cast::<Lambda>(gamma),
+        Err(Error::WrongNodeKind { node, .. }) if node == gamma
+    ));
+    assert!(ctx.try_cast::<Gamma>(gamma).is_ok());
+    assert_eq!(ctx.try_add_gamma_node(0).err(), Some(Error::NoBranches));
+
+    let region = ctx.region;
+    assert_eq!(
+        ctx.try_move_lambda_to_recenv(f.node).err(),
+        Some(Error::NotInRecEnv { region })
+    );
+
+    let apply = ctx.add_apply_node();
+    let input = Input {
+        node: apply.node,
+        id: id::Input::from_u32(1),
+    };
+    assert_eq!(
+        ctx.try_remove_input(input),
+        Err(Error::NoSuchUser {
+            user: User::Input(apply.node.id, input.id)
+        })
+    );
+    let output = Origin::Output(f.node.id, id::Output::from_u32(1));
+    assert_eq!(
+        ctx.try_connect(output, apply),
+        Err(Error::NoSuchOrigin { origin: output })
+    );
+    assert!(matches!(
+        ctx.try_add_loop_var(id::Node::new(gamma)),
+        Err(Error::WrongNodeKind { node, .. }) if node == gamma
+    ));
+
+    let missing = id::AnyNode::from_u32(100);
+    assert_eq!(
+        ctx.try_add_symbol(missing, "missing"),
+        Err(Error::NoSuchNode { node: missing })
+    );
+    assert_eq!(
+        ctx.try_region(missing),
+        Err(Error::NoSuchNode { node: missing })
+    );
+    assert_eq!(
+        ctx.try_move_node(missing, f_region),
+        Err(Error::NoSuchNode { node: missing })
+    );
+    let nowhere = id::Region::from_u32(100);
+    assert_eq!(
+        ctx.try_move_node(apply.node.id, nowhere),
+        Err(Error::NoSuchRegion { region: nowhere })
+    );
+    assert_eq!(
+        ctx.try_input_as_argument(input),
+        Err(Error::NoSuchUser {
+            user: User::Input(apply.node.id, input.id)
+        })
+    );
+    assert!(ctx.origin_type(output).is_none());
+    assert!(ctx.user_type(input).is_none());
+}
+
+#[test]
+fn failed_connect_leaves_graph_unmodified() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let apply = ctx.add_apply_node();
+    let output = ctx.add_output(apply.node);
+    ctx.connect(f, apply);
+
+    let f_region = ctx.region(f.node.id);
+    let err = ctx.in_region(f_region, |ctx| {
+        let result = ctx.add_result();
+        ctx.try_connect(output, result)
+    });
+
+    assert!(matches!(err, Err(Error::Cycle { through, .. }) if through == apply.node.id));
+    assert_eq!(ctx.inputs(f.node.id).count(), 0);
+    assert_eq!(ctx.arguments(f_region).count(), 0);
+}
+
+#[test]
+fn verify() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    let (result, x, ten) = ctx.in_region(f_region, |ctx| {
+        let x = ctx.add_argument();
+        let ten = ctx.add_number_node(10);
+
+        let plus = ctx.add_placeholder_node("+");
+        let plus_x = ctx.add_input(plus.node);
+        let plus_y = ctx.add_input(plus.node);
+        ctx.connect(x, plus_x);
+        ctx.raw_connect_asserted(ten, plus_x);
+
+        // Using its own output
+        let loops = ctx.add_placeholder_node("loop");
+        let loops_x = ctx.add_input(loops.node);
+        ctx.raw_connect_asserted(loops, loops_x);
+
+        // Two separate cycles, with a node in between them which isn't part of either
+        let [a, b, between, c, d] = ["a", "b", "between", "c", "d"].map(|name| {
+            let node = ctx.add_placeholder_node(name);
+            ctx.add_input(node.node);
+            ctx.add_output(node.node);
+            node.node.id
+        });
+        for (from, to) in [(a, b), (b, a), (b, between), (between, c), (c, d), (d, c)] {
+            let input = Input {
+                node: id::Node::<Placeholder>::new(to),
+                id: id::Input::from_u32(0),
+            };
+            ctx.raw_connect_asserted(Origin::Output(from, id::Output::from_u32(0)), input);
+        }
+
+        let (_, branches) = ctx.add_gamma_node(2);
+        ctx.in_region(branches[1], |ctx| ctx.add_result());
+
+        (ctx.add_result(), plus_y, ten)
+    });
+
+    // An edge in the wrong region
+    unsafe { ctx.raw_connect(ten, result) };
+
+    let diagnostics = ctx.verify();
+    let x = User::from(x);
+    let gamma = ctx.nodes(f_region).last().unwrap();
+
+    assert!(diagnostics.contains(&Diagnostic::NoOrigin { user: x }));
+    assert!(diagnostics.contains(&Diagnostic::UnconnectedResult { result }));
+    assert!(
+        diagnostics.iter().any(
+            |d| matches!(d, Diagnostic::MultipleOrigins { origins, .. } if origins.len() == 2)
+        )
+    );
+    let cycles: Vec<_> = diagnostics
+        .iter()
+        .filter_map(|d| match d {
+            Diagnostic::Cycle { nodes, .. } => Some(nodes.len()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(cycles, [1, 2, 2]);
+    assert!(
+        diagnostics.iter().any(
+            |d| matches!(d, Diagnostic::CrossRegionEdge { region, .. } if *region == ctx.region)
+        )
+    );
+    assert!(
+        diagnostics
+            .iter()
+            .any(|d| matches!(d, Diagnostic::ResultCountMismatch { node, .. } if *node == gamma))
+    );
+}
+
+#[test]
+fn remove_ports() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let values = [1, 2, 3].map(|n| ctx.add_number_node(n));
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    let [a, b, c] = ctx.in_region(f_region, |ctx| {
+        let param = ctx.add_argument();
+        let sum = ctx.add_placeholder_node("sum");
+        let inputs = [0, 1, 2, 3].map(|_| ctx.add_input(sum.node));
+        ctx.connect(param, inputs[0]);
+        for (value, input) in values.into_iter().zip(&inputs[1..]) {
+            ctx.connect(value, *input);
+        }
+        let result = ctx.add_result();
+        ctx.connect(sum, result);
+
+        [inputs[1], inputs[2], inputs[3]]
+    });
+
+    // Forwarded as the arguments after the parameter
+    assert_eq!(ctx.inputs(f.node.id).count(), 3);
+    assert_eq!(ctx.arguments(f_region).count(), 4);
+
+    let b_forwarded = Input {
+        node: f.node,
+        id: id::Input::from_u32(1),
+    };
+    ctx.remove_input(b_forwarded);
+
+    assert_eq!(ctx.inputs(f.node.id).count(), 2);
+    assert_eq!(ctx.arguments(f_region).count(), 3);
+
+    ctx.in_region(f_region, |ctx| {
+        assert!(ctx.connection_exists(values[0].into(), a.into()));
+        assert!(ctx.connection_exists(values[2].into(), c.into()));
+        assert!(!ctx.connection_exists(values[1].into(), b.into()));
+
+        ctx.remove_input(b);
+    });
+
+    assert_eq!(ctx.verify(), []);
+
+    // Removing the node leaves its user without an origin
+    let sum = b.node.id;
+    ctx.remove_node(sum);
+    let result = Result {
+        region: f_region,
+        id: id::Result::from_u32(0),
+    };
+    assert_eq!(ctx.verify(), [Diagnostic::UnconnectedResult { result }]);
+
+    ctx.remove_result(result);
+    assert_eq!(ctx.verify(), []);
+}
+
+#[test]
+fn remove_structured_ports() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    ctx.in_region(f_region, |ctx| {
+        let x = ctx.add_argument();
+
+        let (predicate, theta) = ctx.add_dowhile_node();
+        let vars = [0, 1].map(|_| ctx.add_loop_var(theta));
+        for var in vars {
+            ctx.connect(x, var.input);
+        }
+        ctx.in_region(predicate.region, |ctx| {
+            for var in vars {
+                ctx.connect(var.argument, var.result);
+            }
+            ctx.connect(vars[1].argument, predicate)
+        });
+
+        ctx.remove_output(vars[0].output);
+        assert_eq!(ctx.inputs(theta.id).count(), 1);
+        assert_eq!(ctx.arguments(predicate.region).count(), 1);
+        assert_eq!(ctx.results(predicate.region).count(), 2);
+        assert_eq!(
+            ctx.try_remove_result(predicate),
+            Err(Error::RequiredPort { node: theta.id })
+        );
+
+        let (gamma_predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(x, gamma_predicate);
+        let (results, output) = ctx.add_gamma_output(gamma_predicate.node);
+        for result in results {
+            ctx.in_region(result.region, |ctx| ctx.connect(x, result));
+        }
+
+        // Removing the result of one branch removes it from all of them
+        ctx.remove_result(Result {
+            region: branches[1],
+            id: id::Result::from_u32(0),
+        });
+        assert_eq!(ctx.outputs(output.node.id).count(), 0);
+        for branch in branches {
+            assert_eq!(ctx.results(branch).count(), 0);
+        }
+        assert_eq!(
+            ctx.try_remove_input(gamma_predicate),
+            Err(Error::RequiredPort {
+                node: gamma_predicate.node.id
+            })
+        );
+    });
+
+    assert_eq!(ctx.verify(), []);
+}
+
+// fn even n = odd n
+// fn odd n = even n
+// fn other n = even n
+// fn even n = other n
+#[test]
+fn recursion_joins_existing_recenv() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let even = ctx.add_lambda_node();
+    let odd = ctx.add_lambda_node();
+    let other = ctx.add_lambda_node();
+
+    for (f, calls) in [(even, odd), (odd, even), (other, even), (even, other)] {
+        let region = ctx.region(f.node.id);
+        ctx.in_region(region, |ctx| {
+            let apply = ctx.add_apply_node();
+            ctx.connect(calls, apply);
+        });
+    }
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    let [recenv] = root[..] else {
+        panic!("expected only a recenv in omega, got {root:?}");
+    };
+    let recenv = ctx.cast::<RecEnv>(recenv);
+
+    assert_eq!(ctx.get(recenv).lambdas.len(), 3);
+    assert_eq!(ctx.inputs(recenv.id).count(), 0);
+    assert_eq!(ctx.verify(), []);
+
+    // Removing a lambda removes its recursion argument and output
+    ctx.remove_node(other.node.id);
+    assert_eq!(ctx.get(recenv).lambdas.len(), 2);
+    assert_eq!(ctx.outputs(recenv.id).count(), 2);
+    assert_eq!(ctx.arguments(ctx.region(recenv.id)).count(), 2);
+}
+
+// fn f = f (g)
+// fn g = g (f)
+#[test]
+fn recursion_merges_recenvs() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let g = ctx.add_lambda_node();
+
+    let call = |ctx: &mut TranslationUnitContext, h: Output<Lambda>, calls: Output<Lambda>| {
+        let region = ctx.region(h.node.id);
+        ctx.in_region(region, |ctx| {
+            let apply = ctx.add_apply_node();
+            ctx.connect(calls, apply);
+        });
+    };
+
+    call(&mut ctx, f, f);
+    call(&mut ctx, g, g);
+    assert_eq!(ctx.nodes(ctx.region).count(), 2);
+
+    call(&mut ctx, f, g);
+    call(&mut ctx, g, f);
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    let [recenv] = root[..] else {
+        panic!("expected only a recenv in omega, got {root:?}");
+    };
+    let recenv = ctx.cast::<RecEnv>(recenv);
+    let recenv_region = ctx.region(recenv.id);
+
+    assert_eq!(ctx.get(recenv).lambdas.len(), 2);
+    assert_eq!(ctx.inputs(recenv.id).count(), 0);
+    assert_eq!(ctx.arguments(recenv_region).count(), 2);
+    assert_eq!(ctx.nodes(recenv_region).count(), 2);
+    assert_eq!(ctx.verify(), []);
+
+    // Each lambda calls itself and the other through the recursion arguments
+    for h in [f, g] {
+        let region = ctx.region(h.node.id);
+        let forwarded: Vec<_> = ctx
+            .inputs(h.node.id)
+            .map(|input| ctx.origin_of(User::Input(h.node.id, input)).unwrap())
+            .collect();
+        assert_eq!(forwarded.len(), 2);
+        assert!(
+            forwarded
+                .iter()
+                .all(|origin| matches!(origin, Origin::Argument(r, _) if *r == recenv_region))
+        );
+        assert_eq!(ctx.nodes(region).count(), 2);
+    }
+}
+
+// fn main x =
+//   let unused = x + outer
+//   let (a, _) = if x then (x, 1) else (x, 2)
+//   do { a = a } while a
+//   a
+// fn helper = 0
+#[test]
+fn dead_node_elimination() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let outer = ctx.add_number_node(1);
+    let helper = ctx.add_lambda_node();
+    ctx.in_region(ctx.region(helper.node.id), |ctx| {
+        let zero = ctx.add_number_node(0);
+        let result = ctx.add_result();
+        ctx.connect(zero, result);
+    });
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let main_region = ctx.region(main.node.id);
+    let (predicate, theta) = ctx.in_region(main_region, |ctx| {
+        let x = ctx.add_argument();
+
+        let unused = ctx.add_placeholder_node("+");
+        let unused_x = ctx.add_input(unused.node);
+        let unused_y = ctx.add_input(unused.node);
+        ctx.connect(x, unused_x);
+        ctx.connect(outer, unused_y);
+
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(x, predicate);
+        let (a_results, a) = ctx.add_gamma_output(predicate.node);
+        let (b_results, _) = ctx.add_gamma_output(predicate.node);
+        for (i, branch) in branches.into_iter().enumerate() {
+            ctx.in_region(branch, |ctx| {
+                ctx.connect(x, a_results[i]);
+                let n = ctx.add_number_node(i as i128 + 1);
+                ctx.connect(n, b_results[i]);
+            });
+        }
+
+        let (loop_predicate, theta) = ctx.add_dowhile_node();
+        let var = ctx.add_loop_var(theta);
+        ctx.connect(a, var.input);
+        ctx.in_region(loop_predicate.region, |ctx| {
+            ctx.connect(var.argument, var.result);
+            ctx.connect(var.argument, loop_predicate);
+            // Forwarded as an invariant loop variable which nothing uses
+            let step = ctx.add_placeholder_node("step");
+            let step_input = ctx.add_input(step.node);
+            ctx.connect(x, step_input);
+        });
+
+        let result = ctx.add_result();
+        ctx.connect(var.output, result);
+
+        (predicate, theta)
+    });
+
+    assert_eq!(ctx.inputs(main.node.id).count(), 1);
+    assert_eq!(ctx.loop_vars(theta).count(), 2);
+
+    assert!(ctx.eliminate_dead_nodes());
+    assert!(!ctx.eliminate_dead_nodes());
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    assert_eq!(root, [main.node.id]);
+
+    assert_eq!(ctx.inputs(main.node.id).count(), 0);
+    assert_eq!(ctx.arguments(main_region).count(), 1);
+    assert_eq!(ctx.nodes(main_region).count(), 2);
+
+    assert_eq!(ctx.outputs(predicate.node.id).count(), 1);
+    for &branch in ctx.regions(predicate.node.id) {
+        assert_eq!(ctx.nodes(branch).count(), 0);
+        assert_eq!(ctx.arguments(branch).count(), 1);
+    }
+
+    assert_eq!(ctx.loop_vars(theta).count(), 1);
+    assert_eq!(ctx.nodes(ctx.region(theta.id)).count(), 0);
+
+    assert_eq!(ctx.verify(), []);
+}
+
+#[test]
+fn forwarding_reuses_inputs() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let one = ctx.add_number_node(1);
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    let theta = ctx.in_region(f_region, |ctx| {
+        let plus = ctx.add_placeholder_node("+");
+        let plus_x = ctx.add_input(plus.node);
+        let plus_y = ctx.add_input(plus.node);
+        ctx.connect(one, plus_x);
+        ctx.connect(one, plus_y);
+
+        let (predicate, theta) = ctx.add_dowhile_node();
+        let var = ctx.add_loop_var(theta);
+        ctx.connect(one, var.input);
+        ctx.in_region(predicate.region, |ctx| {
+            let step = ctx.add_placeholder_node("step");
+            let step_input = ctx.add_input(step.node);
+            ctx.connect(var.argument, step_input);
+            ctx.connect(step, var.result);
+
+            // `var` is changed by the loop, so this needs an invariant loop variable of its own
+            let minus = ctx.add_placeholder_node("-");
+            let minus_x = ctx.add_input(minus.node);
+            let minus_y = ctx.add_input(minus.node);
+            ctx.connect(one, minus_x);
+            ctx.connect(one, minus_y);
+            ctx.connect(minus, predicate);
+        });
+
+        theta
     });
 
-    ctx.open_rvsdg_viewer();
+    assert_eq!(ctx.inputs(f.node.id).count(), 1);
+    assert_eq!(ctx.loop_vars(theta).count(), 2);
+    assert_eq!(ctx.verify(), []);
+}
+
+// fn even n = odd n
+// fn odd n = even n
+// fn unused n = unused n
+#[test]
+fn dead_recursive_lambdas() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let even = ctx.add_lambda_node();
+    let odd = ctx.add_lambda_node();
+    let unused = ctx.add_lambda_node();
+    ctx.add_symbol(even.node.id, "even");
+
+    for (f, calls) in [(even, odd), (odd, even), (unused, unused)] {
+        let region = ctx.region(f.node.id);
+        ctx.in_region(region, |ctx| {
+            let apply = ctx.add_apply_node();
+            let output = ctx.add_output(apply.node);
+            ctx.connect(calls, apply);
+            let result = ctx.add_result();
+            ctx.connect(output, result);
+        });
+    }
+
+    assert_eq!(ctx.nodes(ctx.region).count(), 2);
+
+    assert!(ctx.eliminate_dead_nodes());
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    let [recenv] = root[..] else {
+        panic!("expected only the recenv of even and odd, got {root:?}");
+    };
+    let recenv = ctx.cast::<RecEnv>(recenv);
+    assert_eq!(ctx.get(recenv).lambdas.len(), 2);
+
+    // Unexported, so only kept alive by the recursion of even
+    ctx.add_symbol(even.node.id, "");
+    ctx.add_symbol(odd.node.id, "odd");
+    assert!(!ctx.eliminate_dead_nodes());
+
+    assert_eq!(ctx.verify(), []);
+}
+
+// fn main x =
+//   let a = x + 1
+//   let b = x + 1
+//   a * b * 2
+#[test]
+fn common_node_elimination() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let ones = [1, 1].map(|n| ctx.add_number_node(n));
+    let two = ctx.add_number_node(2);
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let main_region = ctx.region(main.node.id);
+    ctx.in_region(main_region, |ctx| {
+        let x = ctx.add_argument();
+
+        let [a, b] = ones.map(|one| {
+            let plus = ctx.add_placeholder_node("+");
+            let plus_x = ctx.add_input(plus.node);
+            let plus_y = ctx.add_input(plus.node);
+            ctx.connect(x, plus_x);
+            ctx.connect(one, plus_y);
+            plus
+        });
+
+        let times = ctx.add_placeholder_node("*");
+        let inputs = [0, 1, 2].map(|_| ctx.add_input(times.node));
+        ctx.connect(a, inputs[0]);
+        ctx.connect(b, inputs[1]);
+        ctx.connect(two, inputs[2]);
+
+        let result = ctx.add_result();
+        ctx.connect(times, result);
+    });
+
+    assert_eq!(ctx.inputs(main.node.id).count(), 3);
+
+    assert!(ctx.eliminate_common_nodes());
+    assert!(!ctx.eliminate_common_nodes());
+
+    let root: Vec<_> = ctx.nodes(ctx.region).collect();
+    assert_eq!(root, [ones[0].node.id, two.node.id, main.node.id]);
+
+    // The forwarded ones are now the same argument, making both additions the same
+    assert_eq!(ctx.inputs(main.node.id).count(), 2);
+    assert_eq!(ctx.nodes(main_region).count(), 2);
+
+    assert_eq!(ctx.verify(), []);
+}
+
+// fn main x = x + x
+#[test]
+fn common_node_elimination_keeps_operands() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let main = ctx.add_lambda_node();
+    let main_region = ctx.region(main.node.id);
+    let plus = ctx.in_region(main_region, |ctx| {
+        let x = ctx.add_argument();
+        let plus = add(ctx, x, x);
+        let result = ctx.add_result();
+        ctx.connect(plus, result);
+        plus
+    });
+
+    assert!(!ctx.eliminate_common_nodes());
+    assert_eq!(ctx.inputs(plus.node.id).count(), 2);
+    assert_eq!(ctx.verify(), []);
+}
+
+// Check the def-use index against the edges stored in each region
+fn assert_uses_consistent(ctx: &TranslationUnitContext) {
+    let edges: Vec<Edge> = ctx
+        .regions
+        .values()
+        .flat_map(|region| region.edges.iter().copied())
+        .collect();
+
+    for edge in &edges {
+        assert!(ctx.users(edge.origin).any(|user| user == edge.user));
+        assert_eq!(ctx.origin_of(edge.user), Some(edge.origin));
+    }
+
+    let indexed: usize = edges
+        .iter()
+        .map(|edge| edge.origin)
+        .collect::<std::collections::HashSet<_>>()
+        .into_iter()
+        .map(|origin| ctx.users(origin).count())
+        .sum();
+    assert_eq!(indexed, edges.len());
+}
+
+// fn even n = odd (n - 1)
+// fn odd n = even (n - 1)
+// fn main = even 10
+#[test]
+fn def_use_index() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let one = ctx.add_number_node(1);
+    let even = ctx.add_lambda_node();
+    let odd = ctx.add_lambda_node();
+
+    for (f, calls) in [(even, odd), (odd, even)] {
+        let region = ctx.region(f.node.id);
+        ctx.in_region(region, |ctx| {
+            let n = ctx.add_argument();
+            let minus = ctx.add_placeholder_node("-");
+            let minus_x = ctx.add_input(minus.node);
+            let minus_y = ctx.add_input(minus.node);
+            ctx.connect(n, minus_x);
+            ctx.connect(one, minus_y);
+
+            let apply = ctx.add_apply_node();
+            let apply_n = ctx.add_input(apply.node);
+            let output = ctx.add_output(apply.node);
+            ctx.connect(calls, apply);
+            ctx.connect(minus, apply_n);
+            let result = ctx.add_result();
+            ctx.connect(output, result);
+        });
+    }
+    assert_uses_consistent(&ctx);
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let main_region = ctx.region(main.node.id);
+    let apply = ctx.in_region(main_region, |ctx| {
+        let ten = ctx.add_number_node(10);
+        let apply = ctx.add_apply_node();
+        let apply_n = ctx.add_input(apply.node);
+        let output = ctx.add_output(apply.node);
+        ctx.connect(even, apply);
+        ctx.connect(ten, apply_n);
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+        apply
+    });
+    assert_uses_consistent(&ctx);
+
+    let callee = ctx.origin_of(apply).unwrap();
+    let Origin::Argument(region, _) = callee else {
+        panic!("expected even to be forwarded into main, got {callee:?}");
+    };
+    assert_eq!(region, main_region);
+    assert_eq!(ctx.users(callee).collect::<Vec<_>>(), [User::from(apply)]);
+    // Forwarded once into the recenv shared by both lambdas
+    assert_eq!(ctx.users(one).count(), 1);
+
+    ctx.remove_edge(callee, apply);
+    assert_eq!(ctx.origin_of(apply), None);
+    assert_uses_consistent(&ctx);
+
+    ctx.eliminate_dead_nodes();
+    ctx.eliminate_common_nodes();
+    assert_uses_consistent(&ctx);
+
+    // Without the call, even and odd are gone along with their use of one
+    assert_eq!(ctx.users(one).count(), 0);
+}
+
+#[derive(Debug, PartialEq)]
+enum Ty {
+    Int,
+    Bool,
+}
+value_type_impl!(Ty);
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Ty::Int => write!(f, "int"),
+            Ty::Bool => write!(f, "bool"),
+        }
+    }
+}
+
+// fn f x = if x then 1 else 2
+#[test]
+fn types() {
+    let mut ctx = TranslationUnitContext::new();
+    let int: Type = std::sync::Arc::new(Ty::Int);
+    let bool: Type = std::sync::Arc::new(Ty::Bool);
+
+    let one = ctx.add_number_node(1);
+    ctx.set_origin_type(one, int.clone());
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    ctx.in_region(f_region, |ctx| {
+        let x = ctx.add_argument();
+        ctx.set_origin_type(x, bool.clone());
+
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.set_user_type(predicate, bool.clone());
+        ctx.connect(x, predicate);
+
+        let (results, output) = ctx.add_gamma_output(predicate.node);
+        ctx.set_origin_type(output, int.clone());
+        for result in &results {
+            ctx.set_user_type(*result, int.clone());
+        }
+
+        ctx.in_region(branches[0], |ctx| {
+            let x = ctx.input_as_argument_in(predicate, branches[0]);
+            assert_eq!(
+                ctx.try_connect(x, results[0]),
+                Err(Error::TypeMismatch {
+                    origin: x.into(),
+                    user: results[0].into(),
+                    expected: "int".into(),
+                    found: "bool".into(),
+                })
+            );
+
+            // Forwarded into the lambda and then the gamma, keeping its type all the way
+            ctx.connect(one, results[0]);
+            let forwarded = ctx.origin_of(results[0]).unwrap();
+            assert!(matches!(forwarded, Origin::Argument(..)));
+            assert_eq!(ctx.origin_type(forwarded).unwrap().to_string(), "int");
+        });
+
+        ctx.in_region(branches[1], |ctx| {
+            let two = ctx.add_number_node(2);
+            ctx.connect(two, results[1]);
+        });
+
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+    });
+
+    assert_eq!(ctx.verify(), []);
+    let xml = ctx.to_xml();
+    assert!(xml.contains(r#"type="int""#), "{xml}");
+
+    // Changing the type of a port after connecting it is caught by the verifier
+    let f_result = Result {
+        region: f_region,
+        id: id::Result::from_u32(0),
+    };
+    ctx.set_user_type(f_result, bool.clone());
+    let [Diagnostic::TypeMismatch { region, .. }] = ctx.verify()[..] else {
+        panic!("expected a type mismatch, got {:?}", ctx.verify());
+    };
+    assert_eq!(region, f_region);
+}
+
+// fn main io =
+//   let io = print io 1
+//   let io = if 1 then print io 1 else io
+//   do { io = print io 1 } while 1
+//   io
+#[test]
+fn state_edges() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let main_region = ctx.region(main.node.id);
+
+    // Threads the state through a print of one, returning the new state
+    fn print(ctx: &mut TranslationUnitContext, io: impl Into<Origin>) -> Output<Placeholder> {
+        let one = ctx.add_number_node(1);
+        let print = ctx.add_placeholder_node("print");
+        let print_io = ctx.add_state_input(print.node);
+        let print_x = ctx.add_input(print.node);
+        ctx.connect(io.into(), print_io);
+        ctx.connect(one, print_x);
+        ctx.add_state_output(print.node)
+    }
+
+    let (discarded, gamma) = ctx.in_region(main_region, |ctx| {
+        let io = ctx.add_state_argument();
+        let io = print(ctx, io);
+
+        // Never threaded into the result, so never observed
+        let discarded = [print(ctx, io), print(ctx, io)];
+
+        let one = ctx.add_number_node(1);
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(one, predicate);
+        let gamma = ctx.add_gamma_state(predicate.node);
+        ctx.connect(io, gamma.input);
+        ctx.in_region(branches[0], |ctx| {
+            let io = print(ctx, gamma.arguments[0]);
+            ctx.connect(io, gamma.results[0]);
+        });
+        ctx.in_region(branches[1], |ctx| {
+            ctx.connect(gamma.arguments[1], gamma.results[1])
+        });
+
+        let (loop_predicate, theta) = ctx.add_dowhile_node();
+        let var = ctx.add_state_loop_var(theta);
+        ctx.connect(gamma.output, var.input);
+        ctx.in_region(loop_predicate.region, |ctx| {
+            let io = print(ctx, var.argument);
+            ctx.connect(io, var.result);
+            let one = ctx.add_number_node(1);
+            ctx.connect(one, loop_predicate);
+        });
+
+        let result = ctx.add_state_result();
+        ctx.connect(var.output, result);
+
+        assert!(ctx.is_state_origin(var.output));
+        assert!(ctx.is_state_user(var.result));
+        assert!(!ctx.is_state_user(predicate));
+
+        (discarded, gamma)
+    });
+
+    let state_edges = ctx.regions[main_region]
+        .edges
+        .iter()
+        .filter(|edge| ctx.is_state_edge(edge))
+        .count();
+    assert_eq!(state_edges, 6);
+
+    // State can't be connected to a value
+    ctx.in_region(main_region, |ctx| {
+        let add = ctx.add_placeholder_node("+");
+        let add_x = ctx.add_input(add.node);
+        ctx.set_user_type(add_x, std::sync::Arc::new(Ty::Int));
+        assert!(matches!(
+            ctx.try_connect(gamma.output, add_x),
+            Err(Error::TypeMismatch { .. })
+        ));
+    });
+
+    let prints = |ctx: &TranslationUnitContext| {
+        ctx.nodes(main_region)
+            .filter(|&node| {
+                let kind = ctx.nodes[node].kind.as_any();
+                kind.downcast_ref::<Placeholder>()
+                    .is_some_and(|p| p.0 == "print")
+            })
+            .count()
+    };
+
+    // The discarded prints have the same origins once their ones are merged, but both have to
+    // happen
+    ctx.eliminate_common_nodes();
+    assert_eq!(prints(&ctx), 3);
+
+    ctx.eliminate_dead_nodes();
+    assert_eq!(prints(&ctx), 1);
+    assert!(
+        !ctx.nodes(main_region)
+            .any(|node| node == discarded[0].node.id)
+    );
+    assert_eq!(ctx.verify(), []);
+}
+
+#[test]
+fn topological_order() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    let ([a, b, c], c_input) = ctx.in_region(f_region, |ctx| {
+        let [a, b, c] = ["a", "b", "c"].map(|name| ctx.add_placeholder_node(name));
+        let b_input = ctx.add_input(b.node);
+        let c_input = ctx.add_input(c.node);
+
+        // b uses c although it was added first
+        ctx.connect(c, b_input);
+        // Rejected by `connect`
+        ctx.raw_connect_asserted(b, c_input);
+
+        ([a, b, c].map(|output| output.node.id), c_input)
+    });
+
+    assert_eq!(
+        ctx.try_topological_nodes(f_region).err(),
+        Some(Error::CyclicRegion {
+            region: f_region,
+            nodes: vec![b, c],
+        })
+    );
+
+    ctx.remove_input(c_input);
+
+    let order: Vec<_> = ctx.topological_nodes(f_region).collect();
+    assert_eq!(order, [a, c, b]);
+    let reverse: Vec<_> = ctx.reverse_topological_nodes(f_region).collect();
+    assert_eq!(reverse, [b, c, a]);
+}
+
+#[derive(Default)]
+struct Trace {
+    events: Vec<String>,
+    stacks: Vec<(id::AnyNode, Vec<Scope>)>,
+}
+
+impl Visitor for Trace {
+    fn visit_node(&mut self, walker: &Walker, node: id::AnyNode) {
+        let kind = walker.ctx.nodes[node].kind.node_type();
+        self.events.push(kind.to_string());
+        self.stacks.push((node, walker.stack().to_vec()));
+    }
+
+    fn enter_region(&mut self, _: &Walker, _: id::Region) {
+        self.events.push("(".into());
+    }
+
+    fn leave_region(&mut self, _: &Walker, _: id::Region) {
+        self.events.push(")".into());
+    }
+}
+
+// fn main x = if x then 1 else 2
+#[test]
+fn walk() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let main_region = ctx.region(main.node.id);
+    let (gamma, branches) = ctx.in_region(main_region, |ctx| {
+        let x = ctx.add_argument();
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(x, predicate);
+        let (results, output) = ctx.add_gamma_output(predicate.node);
+        for (i, branch) in branches.iter().enumerate() {
+            ctx.in_region(*branch, |ctx| {
+                let n = ctx.add_number_node(i as i128 + 1);
+                ctx.connect(n, results[i]);
+            });
+        }
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+        (predicate.node.id, branches)
+    });
+
+    let mut pre = Trace::default();
+    ctx.walk(Order::PreOrder, &mut pre);
+    assert_eq!(
+        pre.events.join(" "),
+        "omega ( lambda ( gamma ( number ) ( number ) ) )"
+    );
+
+    let mut post = Trace::default();
+    ctx.walk(Order::PostOrder, &mut post);
+    assert_eq!(
+        post.events.join(" "),
+        "( ( ( number ) ( number ) gamma ) lambda ) omega"
+    );
+
+    let omega = ctx.omega().id;
+    let root = ctx.get(ctx.omega()).region;
+    let (number, stack) = &pre.stacks[4];
+    assert_eq!(
+        stack[..],
+        [
+            Scope::Node(omega),
+            Scope::Region(root),
+            Scope::Node(main.node.id),
+            Scope::Region(main_region),
+            Scope::Node(gamma),
+            Scope::Region(branches[1]),
+        ]
+    );
+
+    // Walking from within the tree sees the same enclosing scopes
+    let mut from = Trace::default();
+    ctx.walk_from(*number, Order::PreOrder, &mut from);
+    assert_eq!(from.stacks, [(*number, stack.clone())]);
+
+    let xml = ctx.to_xml();
+    assert!(xml.contains(r#"<node id="n0.r0.n1:main.r1.n2." type="gamma">"#), "{xml}");
+}
+
+// Disconnects every result of every lambda, which is never correct
+struct Disconnect;
+
+impl Pass for Disconnect {
+    fn name(&self) -> &str {
+        "disconnect"
+    }
+
+    fn run(&mut self, ctx: &mut TranslationUnitContext) -> bool {
+        let root = ctx.get(ctx.omega()).region;
+        let lambdas: Vec<_> = ctx.nodes(root).collect();
+        let mut changed = false;
+        for lambda in lambdas {
+            let region = ctx.region(lambda);
+            for result in ctx.results(region) {
+                if let Some(origin) = ctx.origin_of(User::Result(region, result)) {
+                    ctx.remove_edge(origin, User::Result(region, result));
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+// fn main x = (x + 1) + (x + 1)
+#[test]
+fn pass_manager() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let main_region = ctx.region(main.node.id);
+    ctx.in_region(main_region, |ctx| {
+        let x = ctx.add_argument();
+        let [a, b] = [0, 1].map(|_| {
+            let one = ctx.add_number_node(1);
+            let plus = ctx.add_placeholder_node("+");
+            let plus_x = ctx.add_input(plus.node);
+            let plus_y = ctx.add_input(plus.node);
+            ctx.connect(x, plus_x);
+            ctx.connect(one, plus_y);
+            plus
+        });
+        let plus = ctx.add_placeholder_node("+");
+        let plus_x = ctx.add_input(plus.node);
+        let plus_y = ctx.add_input(plus.node);
+        ctx.connect(a, plus_x);
+        ctx.connect(b, plus_y);
+
+        // Never used, so removed by dead node elimination
+        let _unused = ctx.add_number_node(2);
+
+        let result = ctx.add_result();
+        ctx.connect(plus, result);
+    });
+
+    let mut pipeline = PassManager::new();
+    pipeline
+        .add_pass(DeadNodeElimination)
+        .add_pass(CommonNodeElimination)
+        .run_to_fixpoint(true)
+        .verify_between(true);
+
+    assert!(pipeline.run(&mut ctx));
+    assert_eq!(ctx.nodes(main_region).count(), 3);
+    assert!(!pipeline.run(&mut ctx));
+
+    let mut broken = PassManager::new();
+    broken.add_pass(Disconnect).verify_between(true);
+    let Err(Error::VerifyFailed { pass, diagnostics }) = broken.try_run(&mut ctx) else {
+        panic!("expected the verifier to fail");
+    };
+    assert_eq!(pass, "disconnect");
+    assert!(matches!(
+        diagnostics[..],
+        [Diagnostic::UnconnectedResult { .. }]
+    ));
+}
+
+// let k = 10
+// fn add x = x + k
+// fn main a = if a { add a } else { a }
+#[test]
+fn inline() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let k = ctx.add_number_node(10);
+    let add = ctx.add_lambda_node();
+    let add_region = ctx.region(add.node.id);
+    ctx.in_region(add_region, |ctx| {
+        let x = ctx.add_argument();
+        let plus = ctx.add_placeholder_node("+");
+        let plus_x = ctx.add_input(plus.node);
+        let plus_y = ctx.add_input(plus.node);
+        ctx.connect(x, plus_x);
+        ctx.connect(k, plus_y);
+        let result = ctx.add_result();
+        ctx.connect(plus, result);
+    });
+    assert_eq!(ctx.inputs(add.node.id).count(), 1);
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let (apply, branch, unknown) = ctx.in_region(ctx.region(main.node.id), |ctx| {
+        let a = ctx.add_argument();
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(a, predicate);
+        let (results, output) = ctx.add_gamma_output(predicate.node);
+
+        let apply = ctx.in_region(branches[0], |ctx| {
+            let apply = ctx.add_apply_node();
+            let apply_x = ctx.add_input(apply.node);
+            let apply_output = ctx.add_output(apply.node);
+            ctx.connect(add, apply);
+            ctx.connect(a, apply_x);
+            ctx.connect(apply_output, results[0]);
+            apply
+        });
+        ctx.in_region(branches[1], |ctx| ctx.connect(a, results[1]));
+
+        let callee = ctx.add_placeholder_node("callee");
+        let unknown = ctx.add_apply_node();
+        ctx.connect(callee, unknown);
+
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+
+        (apply.node, branches[0], unknown.node)
+    });
+
+    assert_eq!(
+        ctx.try_inline(unknown),
+        Err(Error::UnknownCallee { apply: unknown.id })
+    );
+
+    ctx.inline(apply);
+
+    let nodes: Vec<_> = ctx.nodes(branch).collect();
+    assert_eq!(nodes.len(), 1);
+    let kind = ctx.nodes[nodes[0]].kind.as_any();
+    assert_eq!(kind.downcast_ref(), Some(&Placeholder("+")));
+
+    // Both the parameter and the context variable are forwarded into the branch
+    let origins: Vec<_> = ctx
+        .inputs(nodes[0])
+        .map(|input| ctx.origin_of(User::Input(nodes[0], input)).unwrap())
+        .collect();
+    assert!(
+        origins
+            .iter()
+            .all(|origin| matches!(origin, Origin::Argument(region, _) if *region == branch))
+    );
+    assert_ne!(origins[0], origins[1]);
+    assert_eq!(
+        ctx.users(Origin::Output(nodes[0], id::Output::from_u32(0)))
+            .count(),
+        1
+    );
+
+    ctx.remove_node(unknown.id);
+    assert_eq!(ctx.verify(), []);
+
+    // The helper is no longer called
+    assert!(ctx.eliminate_dead_nodes());
+    assert!(!ctx.nodes(ctx.region).any(|node| node == add.node.id));
+    assert_eq!(ctx.verify(), []);
+
+    // fn even n = odd n
+    // fn odd n = even n
+    // fn start n = even n
+    let even = ctx.add_lambda_node();
+    let odd = ctx.add_lambda_node();
+    for (f, calls) in [(even, odd), (odd, even)] {
+        ctx.in_region(ctx.region(f.node.id), |ctx| {
+            let n = ctx.add_argument();
+            let apply = ctx.add_apply_node();
+            let apply_n = ctx.add_input(apply.node);
+            let output = ctx.add_output(apply.node);
+            ctx.connect(calls, apply);
+            ctx.connect(n, apply_n);
+            let result = ctx.add_result();
+            ctx.connect(output, result);
+        });
+    }
+    let recenv = ctx.nodes[even.node.id].region;
+    let recenv = ctx.regions[recenv].container_node.unwrap();
+
+    let start = ctx.add_lambda_node();
+    let start_region = ctx.region(start.node.id);
+    let apply = ctx.in_region(start_region, |ctx| {
+        let n = ctx.add_argument();
+        let apply = ctx.add_apply_node();
+        let apply_n = ctx.add_input(apply.node);
+        let output = ctx.add_output(apply.node);
+        ctx.connect(even, apply);
+        ctx.connect(n, apply_n);
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+        apply
+    });
+
+    // The recursion argument `even` uses for `odd` becomes the recenv output of `odd`
+    ctx.inline(apply.node);
+    let [call] = ctx.nodes(start_region).collect::<Vec<_>>()[..] else {
+        panic!("expected the call to `odd` to replace the call to `even`");
+    };
+    let callee = ctx
+        .origin_of(User::Input(call, id::Input::from_u32(0)))
+        .unwrap();
+    let Origin::Argument(forwarded, argument) = callee else {
+        panic!("expected `odd` to be forwarded into `start`, got {callee:?}");
+    };
+    let input = ctx.argument_as_input(forwarded, argument).unwrap();
+    assert!(matches!(
+        ctx.origin_of(User::Input(start.node.id, input.id)),
+        Some(Origin::Output(node, _)) if node == recenv
+    ));
+    assert_eq!(ctx.verify(), []);
+}
+
+// fn even n = odd n
+// fn odd n = even n
+// fn f x = if x { 1 } else { x }
+#[test]
+fn deep_copy() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let even = ctx.add_lambda_node();
+    let odd = ctx.add_lambda_node();
+    ctx.add_symbol(even.node.id, "even");
+    for (f, calls) in [(even, odd), (odd, even)] {
+        ctx.in_region(ctx.region(f.node.id), |ctx| {
+            let n = ctx.add_argument();
+            let apply = ctx.add_apply_node();
+            let apply_n = ctx.add_input(apply.node);
+            let output = ctx.add_output(apply.node);
+            ctx.connect(calls, apply);
+            ctx.connect(n, apply_n);
+            let result = ctx.add_result();
+            ctx.connect(output, result);
+        });
+    }
+    let recenv = ctx.nodes[even.node.id].region;
+    let recenv = ctx.regions[recenv].container_node.unwrap();
+
+    let copy = ctx.clone_node(recenv, ctx.region);
+    let copy = ctx.cast::<RecEnv>(copy);
+    let copy_region = ctx.region(copy.id);
+    assert_ne!(copy_region, ctx.region(recenv));
+    assert_eq!(ctx.get(copy).lambdas.len(), 2);
+    for lambda in ctx.get(copy).lambdas.keys() {
+        assert_eq!(ctx.nodes[*lambda].region, copy_region);
+        assert_eq!(ctx.regions[ctx.region(*lambda)].edges.len(), 3);
+    }
+    assert_eq!(
+        ctx.nodes(copy_region)
+            .filter(|&node| ctx.symbols[node] == "even")
+            .count(),
+        1
+    );
+    assert_eq!(ctx.verify(), []);
+
+    let f = ctx.add_lambda_node();
+    let f_region = ctx.region(f.node.id);
+    let gamma = ctx.in_region(f_region, |ctx| {
+        let x = ctx.add_argument();
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(x, predicate);
+        let (results, output) = ctx.add_gamma_output(predicate.node);
+        ctx.in_region(branches[0], |ctx| {
+            let one = ctx.add_number_node(1);
+            ctx.connect(one, results[0]);
+        });
+        ctx.in_region(branches[1], |ctx| ctx.connect(x, results[1]));
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+        predicate.node
+    });
+
+    let g = ctx.add_lambda_node();
+    let g_region = ctx.region(g.node.id);
+    let y = ctx.in_region(g_region, |ctx| ctx.add_argument());
+
+    assert_eq!(
+        ctx.try_copy_region_into(f_region, g_region, &[]),
+        Err(Error::ArgumentCountMismatch {
+            region: f_region,
+            expected: 1,
+            found: 0
+        })
+    );
+
+    let mapping = ctx.copy_region_into(f_region, g_region, &[y.into()]);
+    assert_eq!(mapping.nodes.len(), 2);
+    assert_eq!(mapping.regions.len(), 2);
+
+    let gamma_copy = mapping.nodes[&gamma.id];
+    assert_eq!(ctx.nodes[gamma_copy].region, g_region);
+    assert_eq!(
+        mapping.results,
+        [Some(Origin::Output(gamma_copy, id::Output::from_u32(0)))]
+    );
+    assert_eq!(
+        ctx.origin_of(User::Input(gamma_copy, id::Input::from_u32(0))),
+        Some(y.into())
+    );
+
+    ctx.in_region(g_region, |ctx| {
+        let result = ctx.add_result();
+        ctx.connect(mapping.results[0].unwrap(), result);
+    });
+
+    assert_eq!(ctx.verify(), []);
+}
+
+#[derive(Debug, Clone)]
+struct Add;
+node_kind_impl!(Add, "add", fold);
+
+impl nodes::Fold for Add {
+    fn fold(&self, inputs: &[i128]) -> Option<Vec<i128>> {
+        Some(vec![inputs.iter().sum()])
+    }
+}
+
+fn add(
+    ctx: &mut TranslationUnitContext,
+    x: impl Into<Origin>,
+    y: impl Into<Origin>,
+) -> Output<Add> {
+    let node = ctx.add_node(|_, _| (Add, []));
+    let x_input = ctx.add_input(node);
+    let y_input = ctx.add_input(node);
+    ctx.connect(x, x_input);
+    ctx.connect(y, y_input);
+    ctx.add_output(node)
+}
+
+// fn main x =
+//   let a = 1 + 2
+//   let b = if 1 { x } else { a + 4 }
+//   do { x = x + a } while ..
+#[test]
+fn constant_folding() {
+    let mut ctx = TranslationUnitContext::new();
+
+    let main = ctx.add_lambda_node();
+    ctx.add_symbol(main.node.id, "main");
+    let main_region = ctx.region(main.node.id);
+    let (b, theta) = ctx.in_region(main_region, |ctx| {
+        let x = ctx.add_argument();
+        let one = ctx.add_number_node(1);
+        let two = ctx.add_number_node(2);
+        let a = add(ctx, one, two);
+
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(one, predicate);
+        let (results, output) = ctx.add_gamma_output(predicate.node);
+        ctx.in_region(branches[0], |ctx| ctx.connect(x, results[0]));
+        ctx.in_region(branches[1], |ctx| {
+            let four = ctx.add_number_node(4);
+            let sum = add(ctx, a, four);
+            ctx.connect(sum, results[1]);
+        });
+        let b = ctx.add_result();
+        ctx.connect(output, b);
+
+        let (loop_predicate, theta) = ctx.add_dowhile_node();
+        let var = ctx.add_loop_var(theta);
+        ctx.connect(x, var.input);
+        ctx.in_region(loop_predicate.region, |ctx| {
+            let sum = add(ctx, var.argument, a);
+            ctx.connect(sum, var.result);
+            let done = ctx.add_placeholder_node("done");
+            let done_input = ctx.add_input(done.node);
+            ctx.connect(sum, done_input);
+            ctx.connect(done, loop_predicate);
+        });
+        let result = ctx.add_result();
+        ctx.connect(var.output, result);
+
+        (b, theta)
+    });
+
+    assert!(ctx.fold_constants());
+    assert!(!ctx.fold_constants());
+    assert_eq!(ctx.verify(), []);
+
+    let number = |ctx: &TranslationUnitContext, user: User| match ctx.origin_of(user) {
+        Some(Origin::Output(node, _)) => ctx.nodes[node]
+            .kind
+            .as_any()
+            .downcast_ref::<Number>()
+            .map(|n| n.0),
+        _ => None,
+    };
+
+    assert_eq!(number(&ctx, b.into()), Some(7));
+    assert!(
+        !ctx.nodes(main_region)
+            .any(|node| ctx.try_cast::<Gamma>(node).is_ok())
+    );
+
+    // The invariant sum is recreated within the loop, the loop variable isn't
+    let body = ctx.region(theta.id);
+    let sum = ctx
+        .nodes(body)
+        .find(|&node| ctx.nodes[node].kind.as_any().is::<Add>())
+        .unwrap();
+    assert_eq!(number(&ctx, User::Input(sum, id::Input::from_u32(0))), None);
+    assert_eq!(
+        number(&ctx, User::Input(sum, id::Input::from_u32(1))),
+        Some(3)
+    );
+
+    let mut passes = PassManager::new();
+    passes
+        .add_pass(ConstantFolding)
+        .add_pass(DeadNodeElimination)
+        .run_to_fixpoint(true)
+        .verify_between(true);
+    passes.run(&mut ctx);
+    assert_eq!(ctx.nodes(main_region).count(), 2);
+
+    // Folding gives a single value, so nodes with any other amount of outputs are left alone
+    let wrong = ctx.in_region(main_region, |ctx| {
+        let one = ctx.add_number_node(1);
+        let wrong = add(ctx, one, one);
+        ctx.add_output(wrong.node);
+        wrong.node
+    });
+    assert!(!ctx.fold_constants());
+    assert!(ctx.nodes(main_region).any(|node| node == wrong.id));
+}
+
+#[derive(Debug, Clone)]
+struct Sub;
+node_kind_impl!(Sub, "sub", evaluate);
+
+impl interp::Evaluate for Sub {
+    fn evaluate(
+        &self,
+        inputs: &[interp::Value],
+    ) -> std::result::Result<Vec<interp::Value>, String> {
+        match inputs {
+            [interp::Value::Number(x), interp::Value::Number(y)] => {
+                Ok(vec![interp::Value::Number(x - y)])
+            }
+            _ => Err(format!("can't subtract {inputs:?}")),
+        }
+    }
+}
+
+fn sub(
+    ctx: &mut TranslationUnitContext,
+    x: impl Into<Origin>,
+    y: impl Into<Origin>,
+) -> Output<Sub> {
+    let node = ctx.add_node(|_, _| (Sub, []));
+    let x_input = ctx.add_input(node);
+    let y_input = ctx.add_input(node);
+    ctx.connect(x, x_input);
+    ctx.connect(y, y_input);
+    ctx.add_output(node)
+}
+
+#[derive(Debug, Clone)]
+struct NonZero;
+node_kind_impl!(NonZero, "nonzero", evaluate);
+
+impl interp::Evaluate for NonZero {
+    fn evaluate(
+        &self,
+        inputs: &[interp::Value],
+    ) -> std::result::Result<Vec<interp::Value>, String> {
+        match inputs {
+            [interp::Value::Number(x)] => Ok(vec![interp::Value::Number((*x != 0) as i128)]),
+            _ => Err(format!("can't compare {inputs:?}")),
+        }
+    }
+}
+
+// let g = 10
+// fn even n = if n { odd (n - 1) } else { 1 }
+// fn odd n = if n { even (n - 1) } else { 0 }
+// fn sum n = do { i = i + 1; acc = acc + i + g } while i - n
+#[test]
+fn interpret() {
+    use interp::{EvalError, Interpreter, Value};
+
+    let mut ctx = TranslationUnitContext::new();
+
+    let (g_result, g) = ctx.add_globalv_node();
+    ctx.in_region(ctx.region(g.node.id), |ctx| {
+        let ten = ctx.add_number_node(10);
+        ctx.connect(
+            ten,
+            Result {
+                region: ctx.region,
+                id: g_result,
+            },
+        );
+    });
+
+    let even = ctx.add_lambda_node();
+    let odd = ctx.add_lambda_node();
+    ctx.add_symbol(even.node.id, "even");
+    ctx.add_symbol(odd.node.id, "odd");
+    for (f, calls, base) in [(even, odd, 1), (odd, even, 0)] {
+        ctx.in_region(ctx.region(f.node.id), |ctx| {
+            let n = ctx.add_argument();
+            let nonzero = ctx.add_node(|_, _| (NonZero, []));
+            let nonzero_n = ctx.add_input(nonzero);
+            let nonzero = ctx.add_output(nonzero);
+            ctx.connect(n, nonzero_n);
+            let (predicate, branches) = ctx.add_gamma_node(2);
+            ctx.connect(nonzero, predicate);
+            let (results, output) = ctx.add_gamma_output(predicate.node);
+            ctx.in_region(branches[0], |ctx| {
+                let base = ctx.add_number_node(base);
+                ctx.connect(base, results[0]);
+            });
+            ctx.in_region(branches[1], |ctx| {
+                let one = ctx.add_number_node(1);
+                let m = sub(ctx, n, one);
+                let apply = ctx.add_apply_node();
+                let apply_m = ctx.add_input(apply.node);
+                let apply_output = ctx.add_output(apply.node);
+                ctx.connect(calls, apply);
+                ctx.connect(m, apply_m);
+                ctx.connect(apply_output, results[1]);
+            });
+            let result = ctx.add_result();
+            ctx.connect(output, result);
+        });
+    }
+
+    let sum = ctx.add_lambda_node();
+    ctx.add_symbol(sum.node.id, "sum");
+    ctx.in_region(ctx.region(sum.node.id), |ctx| {
+        let n = ctx.add_argument();
+        let zero = ctx.add_number_node(0);
+        let (predicate, theta) = ctx.add_dowhile_node();
+        let i = ctx.add_loop_var(theta);
+        let acc = ctx.add_loop_var(theta);
+        ctx.connect(zero, i.input);
+        ctx.connect(zero, acc.input);
+        ctx.in_region(predicate.region, |ctx| {
+            let one = ctx.add_number_node(1);
+            let next = add(ctx, i.argument, one);
+            let partial = add(ctx, acc.argument, next);
+            let total = add(ctx, partial, g);
+            ctx.connect(next, i.result);
+            ctx.connect(total, acc.result);
+            let remaining = sub(ctx, next, n);
+            ctx.connect(remaining, predicate);
+        });
+        let result = ctx.add_result();
+        ctx.connect(acc.output, result);
+    });
+
+    assert_eq!(ctx.verify(), []);
+
+    let mut interpreter = Interpreter::new(&ctx);
+    for n in 0..6 {
+        let even = interpreter.call("even", vec![Value::Number(n)]);
+        let odd = interpreter.call("odd", vec![Value::Number(n)]);
+        assert_eq!(even, Ok(vec![Value::Number((n % 2 == 0) as i128)]));
+        assert_eq!(odd, Ok(vec![Value::Number((n % 2 == 1) as i128)]));
+    }
+    assert_eq!(
+        interpreter.call("sum", vec![Value::Number(3)]),
+        Ok(vec![Value::Number(36)])
+    );
+
+    assert_eq!(
+        ctx.call("sum", vec![]),
+        Err(EvalError::ArityMismatch {
+            lambda: sum.node.id,
+            expected: 1,
+            found: 0
+        })
+    );
+    assert_eq!(
+        ctx.call("main", vec![]),
+        Err(EvalError::UnknownSymbol("main".to_string()))
+    );
+    assert!(matches!(
+        ctx.call("even", vec![Value::State]),
+        Err(EvalError::Failed { .. })
+    ));
+
+    // Folding and removing dead nodes doesn't change the results
+    let mut passes = PassManager::new();
+    passes
+        .add_pass(ConstantFolding)
+        .add_pass(CommonNodeElimination)
+        .add_pass(DeadNodeElimination)
+        .run_to_fixpoint(true)
+        .verify_between(true);
+    passes.run(&mut ctx);
+    assert_eq!(
+        ctx.call("sum", vec![Value::Number(3)]),
+        Ok(vec![Value::Number(36)])
+    );
+    assert_eq!(
+        ctx.call("odd", vec![Value::Number(5)]),
+        Ok(vec![Value::Number(1)])
+    );
+}
+
+// The graph the format tests write and read back
+//
+// fn f = g
+// fn g = f
+// fn f io = print io
+//
+// Each test names `g` and `print` after what its format has to quote or escape, and finishes
+// the second `f` by using the output of `print`. The second `f` also keeps the id of a removed
+// node.
+struct FormatGraph {
+    ctx: TranslationUnitContext,
+    f: Output<Lambda>,
+    recenv: id::Node<RecEnv>,
+    other: Output<Lambda>,
+    removed: id::AnyNode,
+    print: Output<Placeholder>,
+}
+
+fn format_graph(g_symbol: &str, print: &'static str) -> FormatGraph {
+    let mut ctx = TranslationUnitContext::new();
+
+    let f = ctx.add_lambda_node();
+    let g = ctx.add_lambda_node();
+    ctx.add_symbol(f.node.id, "f");
+    ctx.add_symbol(g.node.id, g_symbol);
+    for (lambda, uses) in [(f, g), (g, f)] {
+        ctx.in_region(ctx.region(lambda.node.id), |ctx| {
+            let result = ctx.add_result();
+            ctx.connect(uses, result);
+        });
+    }
+    let recenv = ctx.nodes[f.node.id].region;
+    let recenv = ctx.cast::<RecEnv>(ctx.regions[recenv].container_node.unwrap());
+
+    let other = ctx.add_lambda_node();
+    ctx.add_symbol(other.node.id, "f");
+    let (removed, print) = ctx.in_region(ctx.region(other.node.id), |ctx| {
+        let removed = ctx.add_placeholder_node("removed");
+        ctx.remove_node(removed.node.id);
+
+        let io = ctx.add_state_argument();
+        let print = ctx.add_placeholder_node(print);
+        let print_io = ctx.add_state_input(print.node);
+        ctx.connect(io, print_io);
+
+        (removed.node.id, print)
+    });
+
+    FormatGraph {
+        ctx,
+        f,
+        recenv,
+        other,
+        removed,
+        print,
+    }
+}
+
+// fn f = g.o0
+// fn g.o0 = f
+// fn n5 io = print io + print io
+#[test]
+fn xml_roundtrip() {
+    let FormatGraph {
+        mut ctx,
+        recenv,
+        other,
+        print,
+        ..
+    } = format_graph("g.o0", "<print \"&\">");
+
+    // A symbol which looks like the id of a node
+    ctx.add_symbol(other.node.id, "n5");
+    ctx.in_region(ctx.region(other.node.id), |ctx| {
+        let print_io_out = ctx.add_state_output(print.node);
+        let result = ctx.add_state_result();
+        ctx.connect(print_io_out, result);
+
+        let sum = add(ctx, print, print);
+        let result = ctx.add_result();
+        ctx.connect(sum, result);
+    });
+
+    // The copy has the same symbols as the original
+    ctx.clone_node(recenv.id, ctx.region);
+    assert_eq!(ctx.verify(), []);
+
+    let xml = ctx.to_xml();
+    assert_eq!(
+        TranslationUnitContext::from_xml(&xml).err(),
+        Some(XmlError::UnknownKind("add".to_string()))
+    );
+
+    let mut kinds = KindRegistry::new();
+    kinds.register("add", |_| Some(Box::new(Add)));
+    let read = TranslationUnitContext::from_xml_with(&xml, &kinds).unwrap();
+    assert_eq!(read.verify(), []);
+
+    // Nodes are numbered in the order they're read, which from then on is the order they're written
+    let written = read.to_xml();
+    let reread = TranslationUnitContext::from_xml_with(&written, &kinds).unwrap();
+    assert_eq!(reread.to_xml(), written);
+
+    let symbols: Vec<_> = read
+        .nodes
+        .keys()
+        .map(|node| read.symbols[node].as_str())
+        .filter(|sym| !sym.is_empty())
+        .collect();
+    assert_eq!(symbols, ["f", "g.o0", "n5", "f", "g.o0"]);
+
+    let recenvs: Vec<_> = read
+        .nodes(read.region)
+        .filter_map(|node| read.try_cast::<RecEnv>(node).ok())
+        .collect();
+    assert_eq!(recenvs.len(), 2);
+    for env in recenvs {
+        assert_eq!(read.get(env).lambdas.len(), 2);
+    }
+
+    let n5 = read
+        .nodes(read.region)
+        .find(|&node| read.symbols[node] == "n5")
+        .unwrap();
+    let n5_region = read.region(n5);
+    let print = read
+        .nodes(n5_region)
+        .find(|&node| {
+            read.nodes[node].kind.as_any().downcast_ref() == Some(&Placeholder("<print \"&\">"))
+        })
+        .unwrap();
+    let io = read
+        .origin_of(User::Input(print, id::Input::from_u32(0)))
+        .unwrap();
+    assert!(read.is_state_origin(io));
+    assert_eq!(read.nodes(n5_region).count(), 2);
+
+    assert!(matches!(
+        TranslationUnitContext::from_xml_with(
+            "<rvsdg><edge source=\"a\" target=\"b\"/></rvsdg>",
+            &kinds
+        ),
+        Err(XmlError::UnknownPort(_))
+    ));
+    assert_eq!(
+        TranslationUnitContext::from_xml(
+            "<rvsdg><node id=\"f\" type=\"lambda\"><input id=\"i\"/><region/></node></rvsdg>"
+        )
+        .err(),
+        Some(XmlError::MissingArguments("f".to_string()))
+    );
+    assert_eq!(
+        TranslationUnitContext::from_xml(
+            "<rvsdg>\
+                <node type=\"lambda\"><output id=\"o\"/><region><result id=\"r\"/></region></node>\
+                <edge source=\"o\" target=\"r\"/>\
+            </rvsdg>"
+        )
+        .err(),
+        Some(XmlError::CrossRegionEdge {
+            source: "o".to_string(),
+            target: "r".to_string(),
+        })
+    );
+}
+
+// fn f = say "hi" {x|y}
+// fn say "hi" {x|y} = f
+// fn f io = print io
+#[test]
+fn dot() {
+    let FormatGraph {
+        mut ctx,
+        f,
+        recenv,
+        other,
+        removed,
+        print,
+    } = format_graph("say \"hi\" {x|y}", "print");
+
+    ctx.in_region(ctx.region(other.node.id), |ctx| {
+        let result = ctx.add_result();
+        ctx.connect(print, result);
+    });
+
+    let dot = ctx.to_dot();
+    assert!(dot.starts_with("digraph rvsdg {"));
+    assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+
+    // Lambdas with the same symbol are still separate clusters
+    for node in [recenv.id, f.node.id, other.node.id] {
+        assert!(dot.contains(&format!("subgraph cluster_n{} {{", node.as_u32())));
+    }
+    assert_eq!(dot.matches("label=\"lambda f\";").count(), 2);
+
+    // Symbols are quoted in cluster labels and escaped in record labels
+    assert!(dot.contains(r#"label="lambda say \"hi\" {x|y}";"#), "{dot}");
+    assert!(dot.contains(r#"|lambda\nsay \"hi\" \{x\|y\}|"#), "{dot}");
+
+    assert!(!dot.contains(&format!("n{} [", removed.as_u32())));
+    assert!(dot.contains(&format!(
+        "n{} [shape=record, label=\"{{{{<i0> i0: state}}|placeholder print|{{<o0> o0}}}}\"];",
+        print.node.id.as_u32()
+    )));
+
+    let edges: usize = ctx.regions.values().map(|region| region.edges.len()).sum();
+    assert_eq!(dot.matches(" -> ").count(), edges);
+}
+
+#[derive(Debug, Clone)]
+struct Undef;
+node_kind_impl!(Undef, "undef");
+
+// fn f = g
+// fn g = f
+// fn f io = add (print "a b" io) undef
+#[test]
+fn text_roundtrip() {
+    let FormatGraph {
+        mut ctx,
+        other,
+        print,
+        ..
+    } = format_graph("g", "a b");
+
+    ctx.in_region(ctx.region(other.node.id), |ctx| {
+        let undef = ctx.add_node(|_, _| (Undef, []));
+        let undef = ctx.add_output(undef);
+        let sum = add(ctx, print, undef);
+        let result = ctx.add_result();
+        ctx.connect(sum, result);
+    });
+    assert_eq!(ctx.verify(), []);
+
+    let mut kinds = KindRegistry::new();
+    kinds
+        .register("add", |_| Some(Box::new(Add)))
+        .register("undef", |_| Some(Box::new(Undef)));
+
+    let text = ctx.to_text();
+    let read = TranslationUnitContext::from_text_with(&text, &kinds).unwrap();
+    assert_eq!(read.verify(), []);
+    assert_eq!(read.to_text(), text);
+
+    let lambdas: Vec<_> = read
+        .nodes
+        .keys()
+        .filter(|&node| read.is_kind::<Lambda>(node))
+        .collect();
+    let symbols: Vec<_> = lambdas
+        .iter()
+        .map(|&node| read.symbols[node].as_str())
+        .collect();
+    assert_eq!(symbols, ["f", "g", "f"]);
+    let recenv = read.nodes[lambdas[0]].region;
+    let recenv = read.regions[recenv].container_node.unwrap();
+    assert_eq!(read.get(read.cast::<RecEnv>(recenv)).lambdas.len(), 2);
+    assert_eq!(read.nodes(read.region(lambdas[2])).count(), 3);
+
+    // Kinds without a payload may be followed by a line starting with a word
+    let text = "\
+%0 = lambda @f {
+  %1 = undef
+  return %1
+}
+";
+    let read = TranslationUnitContext::from_text_with(text, &kinds).unwrap();
+    assert_eq!(read.to_text(), text);
+
+    // Values may be used before they're defined, and payloads and symbols which aren't plain
+    // words are quoted
+    let text = "\
+%0 = lambda @\"two words\"(%3) {
+  ^(%1: state):
+  %2 = placeholder \"a b\"(%1: state)
+  return %2
+}
+%3 = number -5
+";
+    let read = TranslationUnitContext::from_text(text).unwrap();
+    assert_eq!(read.to_text(), text);
+    let lambda = read.nodes(read.region).next().unwrap();
+    assert_eq!(read.symbols[lambda], "two words");
+
+    assert_eq!(
+        TranslationUnitContext::from_text("%0 = number 1\n%1 = apply(%0, %2)\n").err(),
+        Some(TextError {
+            line: 2,
+            message: "%2 is never defined".to_string()
+        })
+    );
+    assert_eq!(
+        TranslationUnitContext::from_text("%0 = lambda(%1) {\n}\n%1 = number 1\n").err(),
+        Some(TextError {
+            line: 1,
+            message: "lambda has more inputs than its regions have arguments".to_string()
+        })
+    );
+    assert!(TranslationUnitContext::from_text(&ctx.to_text()).is_err());
+}
+
+#[derive(Debug, Clone)]
+struct Scale(u32);
+node_kind_impl!(Scale, "scale", encode);
+
+impl nodes::Encode for Scale {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        binary::write_u32(bytes, self.0);
+    }
+}
+
+// fn f = g
+// fn g = f
+// fn f io = scale (print "ü\0" io)
+#[test]
+fn binary_roundtrip() {
+    use binary::{BinaryError, Bytes};
+
+    let FormatGraph {
+        mut ctx,
+        f,
+        recenv,
+        other,
+        print,
+        ..
+    } = format_graph("g", "ü\0");
+
+    let scale = ctx.in_region(ctx.region(other.node.id), |ctx| {
+        let scale = ctx.add_node(|_, _| (Scale(300), []));
+        let scale_x = ctx.add_input(scale);
+        let output = ctx.add_output(scale);
+        ctx.connect(print, scale_x);
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+
+        scale
+    });
+    assert_eq!(ctx.verify(), []);
+
+    let mut kinds = KindRegistry::new();
+    kinds.register_decoder("scale", |bytes| {
+        Some(Box::new(Scale(Bytes::new(bytes).u32()?)))
+    });
+
+    let mut bytes = vec![];
+    ctx.save(&mut bytes).unwrap();
+
+    let loaded = TranslationUnitContext::load_with(&mut bytes.as_slice(), &kinds).unwrap();
+    assert_eq!(loaded.verify(), []);
+
+    // Ids are kept, including those of removed nodes
+    assert_eq!(loaded.nodes.len(), ctx.nodes.len());
+    assert_eq!(loaded.regions.len(), ctx.regions.len());
+    assert_eq!(loaded.to_text(), ctx.to_text());
+    assert_eq!(loaded.get(scale).0, 300);
+    assert_eq!(loaded.get(recenv).lambdas, ctx.get(recenv).lambdas);
+    assert_eq!(loaded.symbols[other.node.id], "f");
+    let kind = loaded.nodes[print.node.id].kind.as_any();
+    assert_eq!(kind.downcast_ref(), Some(&Placeholder("ü\0")));
+
+    let mut saved = vec![];
+    loaded.save(&mut saved).unwrap();
+    assert_eq!(saved, bytes);
+
+    assert!(matches!(
+        TranslationUnitContext::load(&mut bytes.as_slice()),
+        Err(BinaryError::UnknownKind(_))
+    ));
+    assert!(matches!(
+        TranslationUnitContext::load_with(&mut &bytes[..bytes.len() - 1], &kinds),
+        Err(BinaryError::Corrupt)
+    ));
+    assert!(matches!(
+        TranslationUnitContext::load_with(&mut &b"<rvsdg/>"[..], &kinds),
+        Err(BinaryError::NotRvsdg)
+    ));
+
+    // Ids within edges and kinds which don't fit the rest of the graph
+    let corrupted = |corrupt: &dyn Fn(&mut TranslationUnitContext)| {
+        let mut ctx = TranslationUnitContext::load_with(&mut bytes.as_slice(), &kinds).unwrap();
+        corrupt(&mut ctx);
+        let mut saved = vec![];
+        ctx.save(&mut saved).unwrap();
+        TranslationUnitContext::load_with(&mut saved.as_slice(), &kinds)
+    };
+    let without_input = corrupted(&|ctx| {
+        ctx.nodes[scale.id].inputs = 0;
+        ctx.nodes[scale.id].input_types.clear();
+    });
+    assert!(matches!(without_input, Err(BinaryError::Corrupt)));
+    let without_omega = corrupted(&|ctx| {
+        let omega = ctx.omega().id;
+        ctx.nodes[omega].kind = Box::new(Placeholder("omega"));
+    });
+    assert!(matches!(without_omega, Err(BinaryError::Corrupt)));
+    let not_a_lambda = corrupted(&|ctx| {
+        let lambdas = &mut ctx.get_mut(recenv).lambdas;
+        let ports = lambdas.remove(&f.node.id).unwrap();
+        lambdas.insert(print.node.id, ports);
+    });
+    assert!(matches!(not_a_lambda, Err(BinaryError::Corrupt)));
+
+    // Structure which doesn't fit together
+    let other_region = ctx.region(other.node.id);
+    let missing_argument = corrupted(&|ctx| {
+        ctx.nodes[other.node.id].inputs = 2;
+        ctx.nodes[other.node.id].input_types = vec![None; 2];
+    });
+    assert!(matches!(missing_argument, Err(BinaryError::Corrupt)));
+    let wrong_region = corrupted(&|ctx| ctx.nodes[print.node.id].region = ctx.region);
+    assert!(matches!(wrong_region, Err(BinaryError::Corrupt)));
+    let wrong_container = corrupted(&|ctx| {
+        ctx.regions[other_region].container_node = Some(f.node.id);
+    });
+    assert!(matches!(wrong_container, Err(BinaryError::Corrupt)));
+    let cross_region_edge = corrupted(&|ctx| {
+        let edge = ctx.regions[other_region].edges.pop().unwrap();
+        let omega = ctx.region;
+        ctx.regions[omega].edges.push(edge);
+    });
+    assert!(matches!(cross_region_edge, Err(BinaryError::Corrupt)));
+}
+
+// fn f = g
+// fn g = f
+// fn f io = scale (print "\"a\"\n" io)
+#[cfg(feature = "serde")]
+#[test]
+fn serde_roundtrip() {
+    use binary::Bytes;
+    use serde_json::Value;
+
+    let FormatGraph {
+        mut ctx,
+        f,
+        recenv,
+        other,
+        print,
+        ..
+    } = format_graph("g", "\"a\"\n");
+
+    ctx.in_region(ctx.region(other.node.id), |ctx| {
+        let scale = ctx.add_node(|_, _| (Scale(300), []));
+        let scale_x = ctx.add_input(scale);
+        let output = ctx.add_output(scale);
+        ctx.connect(print, scale_x);
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+    });
+    assert_eq!(ctx.verify(), []);
+
+    let mut kinds = KindRegistry::new();
+    kinds.register_decoder("scale", |bytes| {
+        Some(Box::new(Scale(Bytes::new(bytes).u32()?)))
+    });
+
+    let json = serde_json::to_string(&ctx).unwrap();
+    let read = TranslationUnitContext::deserialize_with(
+        &mut serde_json::Deserializer::from_str(&json),
+        &kinds,
+    )
+    .unwrap();
+    assert_eq!(read.verify(), []);
+    assert_eq!(read.nodes.len(), ctx.nodes.len());
+    assert_eq!(read.to_text(), ctx.to_text());
+    assert_eq!(read.get(recenv).lambdas, ctx.get(recenv).lambdas);
+    assert_eq!(serde_json::to_string(&read).unwrap(), json);
+
+    let edge = Edge {
+        origin: Origin::Argument(id::Region::from_u32(1), id::Argument::from_u32(2)),
+        user: User::Input(id::AnyNode::from_u32(3), id::Input::from_u32(0)),
+    };
+    let edge_json = serde_json::to_string(&edge).unwrap();
+    assert_eq!(
+        edge_json,
+        r#"{"origin":{"Argument":[1,2]},"user":{"Input":[3,0]}}"#
+    );
+    assert_eq!(serde_json::from_str::<Edge>(&edge_json).unwrap(), edge);
+    let node = serde_json::from_str::<id::Node<id::AnyNode>>("3").unwrap();
+    assert_eq!(node.id, id::AnyNode::from_u32(3));
+
+    assert!(serde_json::from_str::<TranslationUnitContext>(&json).is_err());
+
+    // Ids within edges and kinds which don't fit the rest of the graph
+    fn first_user(unit: &mut Value) -> &mut Value {
+        let regions = unit["regions"].as_array_mut().unwrap();
+        let edge = regions
+            .iter_mut()
+            .find_map(|region| region["edges"].get_mut(0))
+            .unwrap();
+        edge["user"]
+            .as_object_mut()
+            .unwrap()
+            .values_mut()
+            .next()
+            .unwrap()
+    }
+    let corrupted = |corrupt: &dyn Fn(&mut Value)| {
+        let mut unit: Value = serde_json::from_str(&json).unwrap();
+        corrupt(&mut unit);
+        TranslationUnitContext::deserialize_with(unit, &kinds).err()
+    };
+    let no_port = corrupted(&|unit| first_user(unit)[1] = 99.into()).unwrap();
+    assert!(
+        no_port.to_string().starts_with("there's no user"),
+        "{no_port}"
+    );
+    let no_node = corrupted(&|unit| first_user(unit)[0] = 999.into()).unwrap();
+    assert!(
+        no_node.to_string().starts_with("there's no user"),
+        "{no_node}"
+    );
+    let no_omega =
+        corrupted(&|unit| unit["nodes"][0]["kind"] = serde_json::json!({ "type": "lambda" }));
+    assert_eq!(no_omega.unwrap().to_string(), "the omega node is missing");
+
+    // Structure which doesn't fit together
+    let other_region = ctx.region(other.node.id).as_u32() as usize;
+    let omega_region = ctx.region.as_u32() as usize;
+    let (other, print) = (
+        other.node.id.as_u32() as usize,
+        print.node.id.as_u32() as usize,
+    );
+    let structure = "the nodes and regions don't fit together";
+    let missing_argument = corrupted(&|unit| {
+        unit["nodes"][other]["input_types"] = serde_json::json!([null, null]);
+    });
+    assert_eq!(missing_argument.unwrap().to_string(), structure);
+    let wrong_region = corrupted(&|unit| unit["nodes"][print]["region"] = omega_region.into());
+    assert_eq!(wrong_region.unwrap().to_string(), structure);
+    let wrong_container = corrupted(&|unit| {
+        unit["regions"][other_region]["container_node"] = f.node.id.as_u32().into();
+    });
+    assert_eq!(wrong_container.unwrap().to_string(), structure);
+    let cross_region_edge = corrupted(&|unit| {
+        let edges = unit["regions"][other_region]["edges"]
+            .as_array_mut()
+            .unwrap();
+        let edge = edges.pop().unwrap();
+        unit["regions"][omega_region]["edges"]
+            .as_array_mut()
+            .unwrap()
+            .push(edge);
+    });
+    assert_eq!(
+        cross_region_edge.unwrap().to_string(),
+        format!("an edge of region{omega_region} connects ports outside of it")
+    );
+}
+
+// Run a control-flow graph of nodes which can be folded or evaluated
+fn run_cfg(ctx: &TranslationUnitContext, cfg: &cfg::Cfg, params: &[i128]) -> Vec<i128> {
+    use cfg::Terminator;
+
+    let mut values: HashMap<cfg::Value, i128> = cfg
+        .params
+        .iter()
+        .copied()
+        .zip(params.iter().copied())
+        .collect();
+    let (mut from, mut block) = (None, cfg.entry());
+    loop {
+        let b = &cfg.blocks[block];
+
+        let phis: Vec<_> = b
+            .phis
+            .iter()
+            .map(|phi| {
+                let &(_, value) = phi
+                    .incoming
+                    .iter()
+                    .find(|(pred, _)| Some(*pred) == from)
+                    .unwrap();
+                (phi.value, values[&value])
+            })
+            .collect();
+        values.extend(phis);
+
+        for inst in &b.insts {
+            let inputs: Vec<_> = inst.inputs.iter().map(|v| values[v]).collect();
+            let kind = &ctx.nodes[inst.node].kind;
+            let outputs = match kind.as_any().downcast_ref::<Number>() {
+                Some(number) => vec![number.0],
+                None => match kind.as_fold() {
+                    Some(fold) => fold.fold(&inputs).unwrap(),
+                    None => {
+                        let inputs: Vec<_> =
+                            inputs.into_iter().map(interp::Value::Number).collect();
+                        kind.as_evaluate()
+                            .unwrap()
+                            .evaluate(&inputs)
+                            .unwrap()
+                            .into_iter()
+                            .map(|value| match value {
+                                interp::Value::Number(n) => n,
+                                value => panic!("{value:?} is not a number"),
+                            })
+                            .collect()
+                    }
+                },
+            };
+            values.extend(inst.outputs.iter().copied().zip(outputs));
+        }
+
+        from = Some(block);
+        block = match &b.terminator {
+            Terminator::Jump(target) => *target,
+            Terminator::Branch {
+                condition,
+                nonzero,
+                zero,
+            } => {
+                if values[condition] != 0 {
+                    *nonzero
+                } else {
+                    *zero
+                }
+            }
+            Terminator::Switch { predicate, targets } => targets[values[predicate] as usize],
+            Terminator::Return(results) => return results.iter().map(|v| values[v]).collect(),
+        };
+    }
+}
+
+// let g = 10
+// fn sum n = do { i = i + 1; acc = acc + i + g } while i - n
+// fn pick n = if nonzero n { n - 1 } else { 1 }
+#[test]
+fn lower_to_cfg() {
+    use cfg::Terminator;
+    use interp::Value;
+
+    let mut ctx = TranslationUnitContext::new();
+
+    let (g_result, g) = ctx.add_globalv_node();
+    ctx.in_region(ctx.region(g.node.id), |ctx| {
+        let ten = ctx.add_number_node(10);
+        ctx.connect(
+            ten,
+            Result {
+                region: ctx.region,
+                id: g_result,
+            },
+        );
+    });
+
+    let sum = ctx.add_lambda_node();
+    ctx.add_symbol(sum.node.id, "sum");
+    ctx.in_region(ctx.region(sum.node.id), |ctx| {
+        let n = ctx.add_argument();
+        let zero = ctx.add_number_node(0);
+        let (predicate, theta) = ctx.add_dowhile_node();
+        let i = ctx.add_loop_var(theta);
+        let acc = ctx.add_loop_var(theta);
+        ctx.connect(zero, i.input);
+        ctx.connect(zero, acc.input);
+        ctx.in_region(predicate.region, |ctx| {
+            let removed = ctx.add_number_node(2);
+            ctx.remove_node(removed.node.id);
+
+            let one = ctx.add_number_node(1);
+            let next = add(ctx, i.argument, one);
+            let partial = add(ctx, acc.argument, next);
+            let total = add(ctx, partial, g);
+            ctx.connect(next, i.result);
+            ctx.connect(total, acc.result);
+            let remaining = sub(ctx, next, n);
+            ctx.connect(remaining, predicate);
+        });
+        let result = ctx.add_result();
+        ctx.connect(acc.output, result);
+    });
+
+    let pick = ctx.add_lambda_node();
+    ctx.add_symbol(pick.node.id, "pick");
+    ctx.in_region(ctx.region(pick.node.id), |ctx| {
+        let n = ctx.add_argument();
+        let nonzero = ctx.add_node(|_, _| (NonZero, []));
+        let nonzero_n = ctx.add_input(nonzero);
+        let nonzero = ctx.add_output(nonzero);
+        ctx.connect(n, nonzero_n);
+        let (predicate, branches) = ctx.add_gamma_node(2);
+        ctx.connect(nonzero, predicate);
+        let (results, output) = ctx.add_gamma_output(predicate.node);
+        ctx.in_region(branches[0], |ctx| {
+            let one = ctx.add_number_node(1);
+            ctx.connect(one, results[0]);
+        });
+        ctx.in_region(branches[1], |ctx| {
+            let one = ctx.add_number_node(1);
+            let m = sub(ctx, n, one);
+            ctx.connect(m, results[1]);
+        });
+        let result = ctx.add_result();
+        ctx.connect(output, result);
+    });
+    assert_eq!(ctx.verify(), []);
+
+    // The loop becomes a header with a phi for each loop variable, branched back to from its end.
+    // The global is a context variable, which becomes a parameter after `n`.
+    let cfg = ctx.lower_to_cfg(sum.node);
+    assert_eq!(cfg.params.len(), 2);
+    let header = match cfg.blocks[cfg.entry()].terminator {
+        Terminator::Jump(header) => header,
+        ref terminator => panic!("expected a jump to the loop, found {terminator:?}"),
+    };
+    assert_eq!(cfg.blocks[header].phis.len(), 4);
+    assert!(cfg.blocks.values().any(|block| matches!(
+        block.terminator,
+        Terminator::Branch { nonzero, .. } if nonzero == header
+    )));
+
+    for n in 1..5 {
+        let expected = ctx.call("sum", vec![Value::Number(n)]);
+        let found = run_cfg(&ctx, &cfg, &[n, 10]);
+        assert_eq!(expected, Ok(found.into_iter().map(Value::Number).collect()));
+    }
+
+    // The gamma becomes a switch to each branch, joined by a phi for its output
+    let cfg = ctx.lower_to_cfg(pick.node);
+    let text = cfg.display(&ctx).to_string();
+    assert!(text.contains("switch v1, [block1, block2]"), "{text}");
+    let join = cfg.blocks.values().last().unwrap();
+    assert_eq!(join.phis.len(), 1);
+    assert_eq!(join.phis[0].incoming.len(), 2);
+    assert!(
+        matches!(&join.terminator, Terminator::Return(values) if values == &[join.phis[0].value])
+    );
+
+    for n in 0..3 {
+        let expected = ctx.call("pick", vec![Value::Number(n)]);
+        let found = run_cfg(&ctx, &cfg, &[n]);
+        assert_eq!(expected, Ok(found.into_iter().map(Value::Number).collect()));
+    }
+
+    let mut ctx = TranslationUnitContext::new();
+    let outer = ctx.add_lambda_node();
+    let inner = ctx.in_region(ctx.region(outer.node.id), |ctx| ctx.add_lambda_node());
+    assert!(matches!(
+        ctx.try_lower_to_cfg(outer.node),
+        Err(Error::NotLowerable { node }) if node == inner.node.id
+    ));
+
+    // A dowhile region without the predicate
+    let (_, theta) = ctx.in_region(ctx.region(inner.node.id), |ctx| ctx.add_dowhile_node());
+    let body = ctx.region(theta.id);
+    ctx.regions[body].results = 0;
+    ctx.regions[body].result_types.clear();
+    assert!(matches!(
+        ctx.try_lower_to_cfg(inner.node),
+        Err(Error::NotLowerable { node }) if node == theta.id
+    ));
 }
@@ -0,0 +1,738 @@
+use super::*;
+use std::fmt::{self, Write};
+
+// Textual IR
+//
+//     %1 = lambda @main(%0: state) {
+//       ^(%2, %3: state):
+//       %4 = number 1
+//       %5:2 = apply(%2, %4, %3: state) -> (_, state)
+//       return %5#0, %5#1
+//     }
+//
+// Every argument and every node with outputs is named by a number, given in the order they're
+// printed so that printing a parsed graph gives back the same text. A node with several outputs
+// is named by `%n:outputs` and its outputs are referred to as `%n#output`. The arguments and
+// results of a region are listed with `^(..):` and `return`, both left out when there are none.
+// Unconnected inputs and results are written as `_`, and nodes without inputs leave out the
+// parentheses. The payload of a node is on the same line as its kind.
+
+struct Printer<'a> {
+    ctx: &'a TranslationUnitContext,
+    nodes: HashMap<id::AnyNode, u32>,
+    arguments: HashMap<(id::Region, id::Argument), u32>,
+    text: String,
+}
+
+impl<'a> Printer<'a> {
+    // Values may be used before they're printed, so everything is named up front
+    fn name_region(&mut self, region: id::Region) {
+        for a in self.ctx.arguments(region) {
+            let name = self.next_name();
+            self.arguments.insert((region, a), name);
+        }
+
+        for node in self.ctx.nodes(region) {
+            if self.ctx.nodes[node].outputs != 0 {
+                let name = self.next_name();
+                self.nodes.insert(node, name);
+            }
+
+            for &inner in self.ctx.regions(node) {
+                self.name_region(inner);
+            }
+        }
+    }
+
+    fn next_name(&self) -> u32 {
+        (self.nodes.len() + self.arguments.len()) as u32
+    }
+
+    fn origin(&self, origin: Option<Origin>) -> String {
+        match origin {
+            None => String::from("_"),
+            Some(Origin::Argument(region, a)) => format!("%{}", self.arguments[&(region, a)]),
+            Some(Origin::Output(node, _)) if self.ctx.nodes[node].outputs == 1 => {
+                format!("%{}", self.nodes[&node])
+            }
+            Some(Origin::Output(node, o)) => format!("%{}#{}", self.nodes[&node], o.as_u32()),
+        }
+    }
+
+    fn user(&self, user: User) -> String {
+        let origin = self.origin(self.ctx.origin_of(user));
+        match self.ctx.user_type(user) {
+            Some(ty) => format!("{origin}: {}", word(&ty.to_string())),
+            None => origin,
+        }
+    }
+
+    fn line(&mut self, depth: usize, line: impl fmt::Display) {
+        writeln!(self.text, "{:indent$}{line}", "", indent = depth * 2).unwrap();
+    }
+
+    fn print_region(&mut self, region: id::Region, depth: usize) {
+        let ctx = self.ctx;
+
+        if ctx.regions[region].arguments != 0 {
+            let arguments: Vec<_> = ctx
+                .arguments(region)
+                .map(|a| {
+                    let name = self.arguments[&(region, a)];
+                    match ctx.origin_type(Origin::Argument(region, a)) {
+                        Some(ty) => format!("%{name}: {}", word(&ty.to_string())),
+                        None => format!("%{name}"),
+                    }
+                })
+                .collect();
+            self.line(depth, format_args!("^({}):", arguments.join(", ")));
+        }
+
+        for node in ctx.nodes(region) {
+            self.print_node(node, depth);
+        }
+
+        if ctx.regions[region].results != 0 {
+            let results: Vec<_> = ctx
+                .results(region)
+                .map(|r| self.user(User::Result(region, r)))
+                .collect();
+            self.line(depth, format_args!("return {}", results.join(", ")));
+        }
+    }
+
+    fn print_node(&mut self, id: id::AnyNode, depth: usize) {
+        let ctx = self.ctx;
+        let node = &ctx.nodes[id];
+
+        let mut line = match node.outputs {
+            0 => String::new(),
+            1 => format!("%{} = ", self.nodes[&id]),
+            n => format!("%{}:{n} = ", self.nodes[&id]),
+        };
+
+        line.push_str(&word(node.kind.node_type()));
+        if let Some(payload) = node.kind.payload() {
+            write!(line, " {}", word(&payload)).unwrap();
+        }
+        if let Some(name) = ctx.symbols.get(id).filter(|name| !name.is_empty()) {
+            write!(line, " @{}", word(name)).unwrap();
+        }
+
+        let inputs: Vec<_> = ctx
+            .inputs(id)
+            .map(|i| self.user(User::Input(id, i)))
+            .collect();
+        if !inputs.is_empty() {
+            write!(line, "({})", inputs.join(", ")).unwrap();
+        }
+
+        if node.output_types.iter().any(Option::is_some) {
+            let types: Vec<_> = node
+                .output_types
+                .iter()
+                .map(|ty| match ty {
+                    Some(ty) => word(&ty.to_string()),
+                    None => String::from("_"),
+                })
+                .collect();
+            write!(line, " -> ({})", types.join(", ")).unwrap();
+        }
+
+        let regions = ctx.regions(id);
+        if regions.is_empty() {
+            self.line(depth, line);
+            return;
+        }
+
+        line.push_str(" {");
+        self.line(depth, line);
+        for (i, &region) in regions.iter().enumerate() {
+            self.print_region(region, depth + 1);
+            let close = if i + 1 == regions.len() { "}" } else { "} {" };
+            self.line(depth, close);
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// Names, kinds, payloads and types are quoted unless they're plain words
+fn word(word: &str) -> String {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    let plain = !digits.is_empty()
+        && digits.chars().all(is_word_char)
+        && (digits.len() == word.len() || digits.chars().all(|c| c.is_ascii_digit()))
+        && word != "_"
+        && word != "return";
+
+    if plain {
+        word.to_string()
+    } else {
+        format!("{word:?}")
+    }
+}
+
+/// Errors from reading the textual IR, see [`TranslationUnitContext::from_text`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TextError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // `%n` and `%n#output`
+    Value(u32, Option<u32>),
+    // Either a plain or a quoted word
+    Word(String, bool),
+    Symbol(String),
+    Arrow,
+    Punct(char),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Value(n, None) => write!(f, "%{n}"),
+            Token::Value(n, Some(o)) => write!(f, "%{n}#{o}"),
+            Token::Word(word, false) => write!(f, "{word}"),
+            Token::Word(word, true) => write!(f, "{word:?}"),
+            Token::Symbol(name) => write!(f, "@{name}"),
+            Token::Arrow => write!(f, "->"),
+            Token::Punct(c) => write!(f, "{c}"),
+            Token::End => write!(f, "end of input"),
+        }
+    }
+}
+
+fn error(line: usize, message: impl Into<String>) -> TextError {
+    TextError {
+        line,
+        message: message.into(),
+    }
+}
+
+fn tokenize(text: &str) -> std::result::Result<Vec<(Token, usize)>, TextError> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    let number = |chars: &mut std::iter::Peekable<std::str::Chars>, line| {
+        let mut digits = String::new();
+        while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(c);
+            chars.next();
+        }
+        digits
+            .parse::<u32>()
+            .map_err(|_| error(line, "expected a number"))
+    };
+
+    let quoted = |chars: &mut std::iter::Peekable<std::str::Chars>, line| {
+        let mut word = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => return Ok(word),
+                Some('\\') => match chars.next() {
+                    Some('n') => word.push('\n'),
+                    Some('t') => word.push('\t'),
+                    Some('r') => word.push('\r'),
+                    Some('0') => word.push('\0'),
+                    Some(c @ ('"' | '\\' | '\'')) => word.push(c),
+                    Some('u') => {
+                        let code: String = chars
+                            .by_ref()
+                            .skip_while(|&c| c == '{')
+                            .take_while(|&c| c != '}')
+                            .collect();
+                        let c = u32::from_str_radix(&code, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| error(line, "invalid unicode escape"))?;
+                        word.push(c);
+                    }
+                    _ => return Err(error(line, "invalid escape")),
+                },
+                Some(c) => word.push(c),
+                None => return Err(error(line, "unterminated string")),
+            }
+        }
+    };
+
+    let plain = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        let mut word = String::new();
+        while let Some(&c) = chars.peek().filter(|&&c| is_word_char(c)) {
+            word.push(c);
+            chars.next();
+        }
+        word
+    };
+
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+                continue;
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '/' => {
+                // Comments run to the end of the line
+                chars.next();
+                if chars.next() != Some('/') {
+                    return Err(error(line, "expected a comment"));
+                }
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                continue;
+            }
+            '%' => {
+                chars.next();
+                let n = number(&mut chars, line)?;
+                let output = match chars.next_if_eq(&'#') {
+                    Some(_) => Some(number(&mut chars, line)?),
+                    None => None,
+                };
+                Token::Value(n, output)
+            }
+            '@' => {
+                chars.next();
+                match chars.next_if_eq(&'"') {
+                    Some(_) => Token::Symbol(quoted(&mut chars, line)?),
+                    None => Token::Symbol(plain(&mut chars)),
+                }
+            }
+            '"' => {
+                chars.next();
+                Token::Word(quoted(&mut chars, line)?, true)
+            }
+            '-' => {
+                chars.next();
+                if chars.next_if_eq(&'>').is_some() {
+                    Token::Arrow
+                } else {
+                    Token::Word(format!("-{}", plain(&mut chars)), false)
+                }
+            }
+            c if is_word_char(c) => Token::Word(plain(&mut chars), false),
+            '(' | ')' | '{' | '}' | ',' | ':' | '=' | '^' => {
+                chars.next();
+                Token::Punct(c)
+            }
+            c => return Err(error(line, format!("unexpected character {c:?}"))),
+        };
+        tokens.push((token, line));
+    }
+
+    tokens.push((Token::End, line));
+    Ok(tokens)
+}
+
+// `%n` or `%n#output`, resolved once everything is defined
+type Reference = (u32, Option<u32>);
+
+enum Definition {
+    Node(id::AnyNode),
+    Argument(id::Region, id::Argument),
+}
+
+struct TextReader<'a> {
+    kinds: &'a KindRegistry,
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    definitions: HashMap<u32, Definition>,
+    // Values may be used before they're defined, so edges are connected once everything is read
+    edges: Vec<(id::Region, Reference, User, usize)>,
+    recenvs: Vec<id::Node<RecEnv>>,
+}
+
+impl TextReader<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.position].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        let found = *self.peek() == token;
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: Token) -> std::result::Result<(), TextError> {
+        match self.peek() {
+            found if *found == token => {
+                self.next();
+                Ok(())
+            }
+            found => Err(self.error(format!("expected {token} but found {found}"))),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> TextError {
+        error(self.line(), message)
+    }
+
+    fn word(&mut self, what: &str) -> std::result::Result<String, TextError> {
+        match self.next() {
+            Token::Word(word, _) => Ok(word),
+            found => {
+                self.position -= 1;
+                Err(self.error(format!("expected {what} but found {found}")))
+            }
+        }
+    }
+
+    fn define(
+        &mut self,
+        name: u32,
+        definition: Definition,
+        line: usize,
+    ) -> std::result::Result<(), TextError> {
+        match self.definitions.insert(name, definition) {
+            Some(_) => Err(error(line, format!("%{name} is defined several times"))),
+            None => Ok(()),
+        }
+    }
+
+    // Either a type or `_` for no type
+    fn port_type(&mut self) -> std::result::Result<Option<Type>, TextError> {
+        let line = self.line();
+        match self.next() {
+            Token::Word(word, false) if word == "_" => Ok(None),
+            Token::Word(name, _) => match self.kinds.types.get(&name) {
+                Some(ty) => Ok(Some(ty.clone())),
+                None => Err(error(line, format!("no type is registered as {name}"))),
+            },
+            found => Err(error(line, format!("expected a type but found {found}"))),
+        }
+    }
+
+    // A comma separated list within the delimiters
+    fn list(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> std::result::Result<(), TextError>,
+    ) -> std::result::Result<(), TextError> {
+        if self.eat(Token::Punct(close)) {
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            if self.eat(Token::Punct(close)) {
+                return Ok(());
+            }
+            self.expect(Token::Punct(','))?;
+        }
+    }
+
+    // The origin of a use, followed by the type of the user
+    fn read_use(&mut self) -> std::result::Result<(Option<Reference>, Option<Type>), TextError> {
+        let origin = match self.next() {
+            Token::Value(n, output) => Some((n, output)),
+            Token::Word(word, false) if word == "_" => None,
+            found => {
+                self.position -= 1;
+                return Err(self.error(format!("expected a value but found {found}")));
+            }
+        };
+        let ty = match self.eat(Token::Punct(':')) {
+            true => self.port_type()?,
+            false => None,
+        };
+        Ok((origin, ty))
+    }
+
+    fn read_region(
+        &mut self,
+        ctx: &mut TranslationUnitContext,
+        region: id::Region,
+        end: Token,
+    ) -> std::result::Result<(), TextError> {
+        if self.eat(Token::Punct('^')) {
+            self.expect(Token::Punct('('))?;
+            self.list(')', |reader| {
+                let line = reader.line();
+                let Token::Value(name, None) = reader.next() else {
+                    return Err(error(line, "expected an argument"));
+                };
+                let ty = match reader.eat(Token::Punct(':')) {
+                    true => reader.port_type()?,
+                    false => None,
+                };
+
+                let r = &mut ctx.regions[region];
+                let argument = id::Argument::from_u32(r.arguments);
+                r.arguments += 1;
+                r.argument_types.push(ty);
+                reader.define(name, Definition::Argument(region, argument), line)
+            })?;
+            self.expect(Token::Punct(':'))?;
+        }
+
+        loop {
+            match self.peek() {
+                token if *token == end => break,
+                Token::Word(word, false) if word == "return" => {
+                    self.next();
+                    loop {
+                        let line = self.line();
+                        let (origin, ty) = self.read_use()?;
+
+                        let r = &mut ctx.regions[region];
+                        let result = id::Result::from_u32(r.results);
+                        r.results += 1;
+                        r.result_types.push(ty);
+                        if let Some(origin) = origin {
+                            let user = User::Result(region, result);
+                            self.edges.push((region, origin, user, line));
+                        }
+
+                        if !self.eat(Token::Punct(',')) {
+                            break;
+                        }
+                    }
+                    break;
+                }
+                _ => self.read_node(ctx, region)?,
+            }
+        }
+
+        self.expect(end)
+    }
+
+    fn read_node(
+        &mut self,
+        ctx: &mut TranslationUnitContext,
+        region: id::Region,
+    ) -> std::result::Result<(), TextError> {
+        let line = self.line();
+
+        let (name, outputs) = match self.peek().clone() {
+            Token::Value(name, None) => {
+                self.next();
+                let outputs = match self.eat(Token::Punct(':')) {
+                    true => self
+                        .word("an amount of outputs")?
+                        .parse()
+                        .map_err(|_| error(line, "expected an amount of outputs"))?,
+                    false => 1,
+                };
+                self.expect(Token::Punct('='))?;
+                (Some(name), outputs)
+            }
+            _ => (None, 0),
+        };
+
+        let kind_line = self.line();
+        let kind = self.word("a node kind")?;
+
+        // A word on the next line is the kind of the next node or a `return`
+        let payload = match self.peek() {
+            Token::Word(..) if self.line() == kind_line => Some(self.word("a payload")?),
+            _ => None,
+        };
+        let symbol = match self.peek().clone() {
+            Token::Symbol(name) => {
+                self.next();
+                Some(name)
+            }
+            _ => None,
+        };
+
+        let factory = self
+            .kinds
+            .kinds
+            .get(&kind)
+            .ok_or_else(|| error(line, format!("no node kind is registered as {kind}")))?;
+        let created = factory(payload.as_deref()).ok_or_else(|| match &payload {
+            Some(payload) => error(
+                line,
+                format!("{payload:?} is not a valid payload for {kind}"),
+            ),
+            None => error(line, format!("{kind} nodes need a payload")),
+        })?;
+
+        let mut inputs = vec![];
+        if self.eat(Token::Punct('(')) {
+            self.list(')', |reader| {
+                let line = reader.line();
+                let (origin, ty) = reader.read_use()?;
+                inputs.push((origin, ty, line));
+                Ok(())
+            })?;
+        }
+
+        let mut output_types = vec![None; outputs as usize];
+        if self.eat(Token::Arrow) {
+            let mut types = vec![];
+            self.expect(Token::Punct('('))?;
+            self.list(')', |reader| {
+                types.push(reader.port_type()?);
+                Ok(())
+            })?;
+            if types.len() != output_types.len() {
+                return Err(error(
+                    line,
+                    format!("expected {outputs} output types but found {}", types.len()),
+                ));
+            }
+            output_types = types;
+        }
+
+        let mut regions = vec![];
+        while self.eat(Token::Punct('{')) {
+            let inner = ctx.add_region(0, 0);
+            self.read_region(ctx, inner, Token::Punct('}'))?;
+            regions.push(inner);
+        }
+
+        let node = ctx.in_region(region, |ctx| ctx.push_node(created, regions));
+
+        if let Some(symbol) = symbol {
+            ctx.add_symbol(node, symbol);
+        }
+
+        let n = &mut ctx.nodes[node];
+        n.outputs = outputs;
+        n.output_types = output_types;
+        for (origin, ty, line) in inputs {
+            let input = id::Input::from_u32(n.inputs);
+            n.inputs += 1;
+            n.input_types.push(ty);
+            if let Some(origin) = origin {
+                self.edges
+                    .push((region, origin, User::Input(node, input), line));
+            }
+        }
+
+        // Inputs are forwarded as the last arguments of each region
+        if !ctx.has_forwarded_arguments(node) {
+            return Err(error(
+                line,
+                format!("{kind} has more inputs than its regions have arguments"),
+            ));
+        }
+
+        if let Some(name) = name {
+            self.define(name, Definition::Node(node), line)?;
+        }
+
+        if let Ok(env) = ctx.try_cast::<RecEnv>(node) {
+            self.recenvs.push(env);
+        }
+
+        Ok(())
+    }
+
+    fn connect_edges(self, ctx: &mut TranslationUnitContext) -> std::result::Result<(), TextError> {
+        for (region, (name, output), user, line) in self.edges {
+            let origin = match (self.definitions.get(&name), output) {
+                (Some(&Definition::Argument(region, a)), None) => Origin::Argument(region, a),
+                (Some(&Definition::Node(node)), output) => {
+                    let output = output.unwrap_or(0);
+                    if output >= ctx.nodes[node].outputs {
+                        return Err(error(line, format!("%{name} has no output {output}")));
+                    }
+                    Origin::Output(node, id::Output::from_u32(output))
+                }
+                (Some(Definition::Argument(..)), Some(output)) => {
+                    return Err(error(line, format!("%{name} has no output {output}")));
+                }
+                (None, _) => return Err(error(line, format!("%{name} is never defined"))),
+            };
+
+            let defined_in = match origin {
+                Origin::Output(node, _) => ctx.nodes[node].region,
+                Origin::Argument(region, _) => region,
+            };
+            if defined_in != region {
+                return Err(error(line, format!("%{name} is not in scope")));
+            }
+
+            ctx.push_edge(region, Edge { origin, user });
+        }
+
+        for env in self.recenvs {
+            ctx.restore_recenv_lambdas(env);
+        }
+
+        Ok(())
+    }
+}
+
+impl TranslationUnitContext {
+    /// Print the graph in the textual IR.
+    ///
+    /// Values are named in the order they're printed, so the text of a graph read by
+    /// [`Self::from_text`] is the same as the text it was read from.
+    pub fn to_text(&self) -> String {
+        let mut printer = Printer {
+            ctx: self,
+            nodes: HashMap::new(),
+            arguments: HashMap::new(),
+            text: String::new(),
+        };
+
+        let omega = self.get(self.omega()).region;
+        printer.name_region(omega);
+        printer.print_region(omega, 0);
+        printer.text
+    }
+
+    /// Read a graph printed by [`Self::to_text`].
+    ///
+    /// Only the built-in node kinds can be read, see [`Self::from_text_with`] for custom kinds.
+    pub fn from_text(text: &str) -> std::result::Result<Self, TextError> {
+        Self::from_text_with(text, &KindRegistry::new())
+    }
+
+    /// Read a graph, creating the kinds of nodes and the types of ports through the registry.
+    pub fn from_text_with(
+        text: &str,
+        kinds: &KindRegistry,
+    ) -> std::result::Result<Self, TextError> {
+        let mut ctx = TranslationUnitContext::new();
+        let omega = ctx.get(ctx.omega()).region;
+
+        let mut reader = TextReader {
+            kinds,
+            tokens: tokenize(text)?,
+            position: 0,
+            definitions: HashMap::new(),
+            edges: vec![],
+            recenvs: vec![],
+        };
+
+        reader.read_region(&mut ctx, omega, Token::End)?;
+        reader.connect_edges(&mut ctx)?;
+
+        Ok(ctx)
+    }
+}
@@ -0,0 +1,166 @@
+use super::*;
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+/// The type of the values flowing through ports.
+///
+/// Types are opaque to the graph, which only compares them when connecting. Implement using
+/// `value_type_impl!` for types with [`PartialEq`].
+pub trait ValueType: Any + fmt::Debug + fmt::Display + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn type_eq(&self, other: &dyn ValueType) -> bool;
+}
+
+pub type Type = Arc<dyn ValueType>;
+
+#[macro_export]
+macro_rules! value_type_impl {
+    ($ty:ty) => {
+        impl ValueType for $ty {
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
+
+            fn type_eq(&self, other: &dyn ValueType) -> bool {
+                other.as_any().downcast_ref::<$ty>() == Some(self)
+            }
+        }
+    };
+}
+
+impl TranslationUnitContext {
+    /// Set the type of an output or argument.
+    ///
+    /// Setting the type of an argument forwarded from an input sets the type of the input.
+    pub fn set_origin_type(&mut self, origin: impl Into<Origin>, ty: Type) {
+        self.try_set_origin_type(origin, ty)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_set_origin_type(
+        &mut self,
+        origin: impl Into<Origin>,
+        ty: Type,
+    ) -> std::result::Result<(), Error> {
+        let origin = origin.into();
+        self.check_origin(origin)?;
+
+        match origin {
+            Origin::Output(node, output) => {
+                self.nodes[node].output_types[output.as_u32() as usize] = Some(ty);
+            }
+            Origin::Argument(region, argument) => match self.forwarded_from(region, argument) {
+                Some(input) => self.set_user_type(input, ty),
+                None => self.regions[region].argument_types[argument.as_u32() as usize] = Some(ty),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Set the type of an input or result.
+    ///
+    /// The arguments an input is forwarded as get the same type, as do all parts of a loop
+    /// variable.
+    pub fn set_user_type(&mut self, user: impl Into<User>, ty: Type) {
+        self.try_set_user_type(user, ty)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    pub fn try_set_user_type(
+        &mut self,
+        user: impl Into<User>,
+        ty: Type,
+    ) -> std::result::Result<(), Error> {
+        let user = user.into();
+        self.check_user(user)?;
+
+        match user {
+            User::Input(node, input) => {
+                self.nodes[node].input_types[input.as_u32() as usize] = Some(ty.clone());
+
+                let any = id::Node::<id::AnyNode>::new(node);
+                for region in self.regions(node).to_vec() {
+                    let argument = self.input_as_argument_in(
+                        Input {
+                            node: any,
+                            id: input,
+                        },
+                        region,
+                    );
+                    self.regions[region].argument_types[argument.id.as_u32() as usize] =
+                        Some(ty.clone());
+                }
+
+                if self.is_kind::<DoWhile>(node) {
+                    let region = self.region(node);
+                    self.regions[region].result_types[input.as_u32() as usize + 1] =
+                        Some(ty.clone());
+                    self.nodes[node].output_types[input.as_u32() as usize] = Some(ty);
+                }
+            }
+            User::Result(region, result) => {
+                self.regions[region].result_types[result.as_u32() as usize] = Some(ty);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The type of an output or argument, if it has one.
+    ///
+    /// Ports which don't exist have no type.
+    pub fn origin_type(&self, origin: impl Into<Origin>) -> Option<&Type> {
+        let origin = origin.into();
+        self.check_origin(origin).ok()?;
+        match origin {
+            Origin::Output(node, output) => {
+                self.nodes[node].output_types[output.as_u32() as usize].as_ref()
+            }
+            Origin::Argument(region, argument) => {
+                self.regions[region].argument_types[argument.as_u32() as usize].as_ref()
+            }
+        }
+    }
+
+    /// The type of an input or result, if it has one.
+    ///
+    /// Ports which don't exist have no type.
+    pub fn user_type(&self, user: impl Into<User>) -> Option<&Type> {
+        let user = user.into();
+        self.check_user(user).ok()?;
+        match user {
+            User::Input(node, input) => {
+                self.nodes[node].input_types[input.as_u32() as usize].as_ref()
+            }
+            User::Result(region, result) => {
+                self.regions[region].result_types[result.as_u32() as usize].as_ref()
+            }
+        }
+    }
+
+    // Ports without a type can be connected to anything
+    pub(crate) fn check_types(&self, origin: Origin, user: User) -> std::result::Result<(), Error> {
+        match (self.origin_type(origin), self.user_type(user)) {
+            (Some(found), Some(expected)) if !found.type_eq(expected.as_ref()) => {
+                Err(Error::TypeMismatch {
+                    origin,
+                    user,
+                    expected: expected.to_string(),
+                    found: found.to_string(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn forwarded_from(
+        &self,
+        region: id::Region,
+        argument: id::Argument,
+    ) -> Option<Input<id::AnyNode>> {
+        self.regions[region].container_node?;
+        self.argument_as_input(region, argument)
+    }
+}
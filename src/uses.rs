@@ -0,0 +1,84 @@
+use super::*;
+
+// The def-use index of the edges of all regions.
+//
+// Kept consistent by making every change to `Region::edges` through the methods here. Ports are
+// unique across regions, so the index doesn't need to know which region an edge is in.
+#[derive(Debug, Default)]
+pub(crate) struct Uses {
+    users: HashMap<Origin, Vec<User>>,
+    origins: HashMap<User, Vec<Origin>>,
+}
+
+impl Uses {
+    fn insert(&mut self, edge: Edge) {
+        self.users.entry(edge.origin).or_default().push(edge.user);
+        self.origins.entry(edge.user).or_default().push(edge.origin);
+    }
+
+    fn remove(&mut self, edge: Edge) {
+        remove_one(&mut self.users, edge.origin, edge.user);
+        remove_one(&mut self.origins, edge.user, edge.origin);
+    }
+}
+
+fn remove_one<K: std::hash::Hash + Eq, V: PartialEq>(
+    map: &mut HashMap<K, Vec<V>>,
+    key: K,
+    value: V,
+) {
+    let Some(values) = map.get_mut(&key) else {
+        return;
+    };
+    if let Some(i) = values.iter().position(|v| *v == value) {
+        values.remove(i);
+    }
+    if values.is_empty() {
+        map.remove(&key);
+    }
+}
+
+impl TranslationUnitContext {
+    /// All users of an origin, in no particular order.
+    pub fn users(&self, origin: impl Into<Origin>) -> impl Iterator<Item = User> + '_ {
+        let origin = origin.into();
+        self.uses.users.get(&origin).into_iter().flatten().copied()
+    }
+
+    /// The origin a user is connected to.
+    pub fn origin_of(&self, user: impl Into<User>) -> Option<Origin> {
+        self.origins_of(user.into()).next()
+    }
+
+    // Well-formed graphs only have one origin for each user, but that's for the verifier to decide
+    pub(crate) fn origins_of(&self, user: User) -> impl Iterator<Item = Origin> + '_ {
+        self.uses.origins.get(&user).into_iter().flatten().copied()
+    }
+
+    pub(crate) fn push_edge(&mut self, region: id::Region, edge: Edge) {
+        self.uses.insert(edge);
+        self.regions[region].edges.push(edge);
+    }
+
+    pub(crate) fn retain_edges(&mut self, region: id::Region, mut keep: impl FnMut(&Edge) -> bool) {
+        let uses = &mut self.uses;
+        self.regions[region].edges.retain(|edge| {
+            let kept = keep(edge);
+            if !kept {
+                uses.remove(*edge);
+            }
+            kept
+        });
+    }
+
+    pub(crate) fn rewrite_edges(&mut self, region: id::Region, mut f: impl FnMut(&mut Edge)) {
+        for edge in &mut self.regions[region].edges {
+            let before = *edge;
+            f(edge);
+            if *edge != before {
+                self.uses.remove(before);
+                self.uses.insert(*edge);
+            }
+        }
+    }
+}
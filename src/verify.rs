@@ -0,0 +1,321 @@
+use super::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A violation of the rules of what defines an RVSDG
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// An input of a node isn't connected to anything
+    NoOrigin { user: User },
+    /// A user is connected to more than one origin
+    MultipleOrigins { user: User, origins: Vec<Origin> },
+    /// A result of a region isn't connected to anything
+    UnconnectedResult { result: Result },
+    /// An edge is stored in a region other than the one its origin or user belongs to
+    CrossRegionEdge { region: id::Region, edge: Edge },
+    /// An edge refers to a port which does not exist
+    InvalidPort { region: id::Region, edge: Edge },
+    /// An edge connects ports of different types
+    TypeMismatch { region: id::Region, edge: Edge },
+    /// A region has fewer arguments than the inputs its node forwards to it, or in the case of
+    /// gamma and dowhile nodes, a different amount.
+    ArgumentCountMismatch {
+        node: id::AnyNode,
+        region: id::Region,
+        arguments: u32,
+        inputs: u32,
+    },
+    /// A region has a different amount of results than its node has outputs
+    ResultCountMismatch {
+        node: id::AnyNode,
+        region: id::Region,
+        results: u32,
+        outputs: u32,
+    },
+    /// The nodes of a region depend on each other
+    Cycle {
+        region: id::Region,
+        nodes: Vec<id::AnyNode>,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::NoOrigin { user } => write!(f, "{user:?} has no origin"),
+            Diagnostic::MultipleOrigins { user, origins } => {
+                write!(f, "{user:?} has several origins: {origins:?}")
+            }
+            Diagnostic::UnconnectedResult { result } => {
+                write!(f, "{} of {} is not connected", result.id, result.region)
+            }
+            Diagnostic::CrossRegionEdge { region, edge } => write!(
+                f,
+                "{:?} → {:?} in {region} crosses a region boundary",
+                edge.origin, edge.user
+            ),
+            Diagnostic::InvalidPort { region, edge } => write!(
+                f,
+                "{:?} → {:?} in {region} refers to a port which does not exist",
+                edge.origin, edge.user
+            ),
+            Diagnostic::TypeMismatch { region, edge } => write!(
+                f,
+                "{:?} → {:?} in {region} connects ports of different types",
+                edge.origin, edge.user
+            ),
+            Diagnostic::ArgumentCountMismatch {
+                node,
+                region,
+                arguments,
+                inputs,
+            } => write!(
+                f,
+                "{region} of {node} has {arguments} arguments for {inputs} inputs"
+            ),
+            Diagnostic::ResultCountMismatch {
+                node,
+                region,
+                results,
+                outputs,
+            } => write!(
+                f,
+                "{region} of {node} has {results} results for {outputs} outputs"
+            ),
+            Diagnostic::Cycle { region, nodes } => {
+                write!(f, "{nodes:?} form a cycle in {region}")
+            }
+        }
+    }
+}
+
+struct Verifier<'ctx> {
+    ctx: &'ctx TranslationUnitContext,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl TranslationUnitContext {
+    /// Check the whole translation unit for violations of the rules of what defines an RVSDG.
+    ///
+    /// Returns every violation found, an empty list means the graph is well-formed.
+    pub fn verify(&self) -> Vec<Diagnostic> {
+        let mut verifier = Verifier {
+            ctx: self,
+            diagnostics: vec![],
+        };
+
+        self.walk(Order::PreOrder, &mut verifier);
+
+        verifier.diagnostics
+    }
+}
+
+impl Visitor for Verifier<'_> {
+    fn enter_region(&mut self, _: &Walker, region: id::Region) {
+        let nodes = self.ctx.regions[region]
+            .nodes
+            .as_slice(&self.ctx.node_id_pool);
+
+        self.edges(region, nodes);
+        self.origins(region, nodes);
+        self.cycles(region, nodes);
+    }
+
+    fn visit_node(&mut self, _: &Walker, node: id::AnyNode) {
+        self.node(node);
+    }
+}
+
+impl Verifier<'_> {
+    fn edges(&mut self, region: id::Region, nodes: &[id::AnyNode]) {
+        let ctx = self.ctx;
+
+        for &edge in &ctx.regions[region].edges {
+            let (in_region, exists) = match edge.origin {
+                Origin::Output(node, output) => (
+                    nodes.contains(&node),
+                    output.as_u32() < ctx.nodes[node].outputs,
+                ),
+                Origin::Argument(r, argument) => {
+                    (r == region, argument.as_u32() < ctx.regions[r].arguments)
+                }
+            };
+
+            let (user_in_region, user_exists) = match edge.user {
+                User::Input(node, input) => (
+                    nodes.contains(&node),
+                    input.as_u32() < ctx.nodes[node].inputs,
+                ),
+                User::Result(r, result) => (r == region, result.as_u32() < ctx.regions[r].results),
+            };
+
+            if !(in_region && user_in_region) {
+                self.diagnostics
+                    .push(Diagnostic::CrossRegionEdge { region, edge });
+            }
+
+            if !(exists && user_exists) {
+                self.diagnostics
+                    .push(Diagnostic::InvalidPort { region, edge });
+            } else if ctx.check_types(edge.origin, edge.user).is_err() {
+                self.diagnostics
+                    .push(Diagnostic::TypeMismatch { region, edge });
+            }
+        }
+    }
+
+    fn origins(&mut self, region: id::Region, nodes: &[id::AnyNode]) {
+        let ctx = self.ctx;
+
+        let users = nodes
+            .iter()
+            .flat_map(|&node| ctx.inputs(node).map(move |i| User::Input(node, i)))
+            .chain(ctx.results(region).map(|r| User::Result(region, r)));
+
+        for user in users {
+            let origins: Vec<_> = ctx.regions[region]
+                .edges
+                .iter()
+                .filter(|edge| edge.user == user)
+                .map(|edge| edge.origin)
+                .collect();
+
+            match (origins.len(), user) {
+                (0, User::Result(region, id)) => {
+                    let result = Result { region, id };
+                    self.diagnostics
+                        .push(Diagnostic::UnconnectedResult { result });
+                }
+                (0, _) => self.diagnostics.push(Diagnostic::NoOrigin { user }),
+                (1, _) => {}
+                _ => self
+                    .diagnostics
+                    .push(Diagnostic::MultipleOrigins { user, origins }),
+            }
+        }
+    }
+
+    // Every strongly connected component of more than one node, or of a single node using its
+    // own output, is a separate cycle.
+    fn cycles(&mut self, region: id::Region, nodes: &[id::AnyNode]) {
+        let mut dependencies: HashMap<id::AnyNode, Vec<id::AnyNode>> = HashMap::new();
+        for edge in &self.ctx.regions[region].edges {
+            if let (Origin::Output(from, _), User::Input(to, _)) = (edge.origin, edge.user)
+                && nodes.contains(&to)
+            {
+                dependencies.entry(from).or_default().push(to);
+            }
+        }
+
+        let mut components = Components {
+            dependencies: &dependencies,
+            index: HashMap::new(),
+            stack: vec![],
+            found: vec![],
+        };
+        for &node in nodes {
+            if !components.index.contains_key(&node) {
+                components.visit(node);
+            }
+        }
+
+        for mut nodes in components.found {
+            let uses_itself = dependencies
+                .get(&nodes[0])
+                .is_some_and(|to| to.contains(&nodes[0]));
+            if nodes.len() > 1 || uses_itself {
+                nodes.sort_by_key(|node| node.as_u32());
+                self.diagnostics.push(Diagnostic::Cycle { region, nodes });
+            }
+        }
+    }
+
+    fn node(&mut self, node: id::AnyNode) {
+        let ctx = self.ctx;
+        let inputs = ctx.nodes[node].inputs;
+        let outputs = ctx.nodes[node].outputs;
+
+        let is_gamma = ctx.is_kind::<Gamma>(node);
+        let is_dowhile = ctx.is_kind::<DoWhile>(node);
+
+        for &region in ctx.regions(node) {
+            let arguments = ctx.regions[region].arguments;
+            let results = ctx.regions[region].results;
+
+            // Gamma and dowhile regions only have arguments forwarded from inputs
+            let arguments_ok = if is_gamma || is_dowhile {
+                arguments == inputs
+            } else {
+                arguments >= inputs
+            };
+
+            if !arguments_ok {
+                self.diagnostics.push(Diagnostic::ArgumentCountMismatch {
+                    node,
+                    region,
+                    arguments,
+                    inputs,
+                });
+            }
+
+            // Every branch of a gamma has a result for each output, while dowhile regions also
+            // have the predicate.
+            let results_ok = if is_gamma {
+                results == outputs
+            } else if is_dowhile {
+                results == outputs + 1 && outputs == inputs
+            } else {
+                true
+            };
+
+            if !results_ok {
+                self.diagnostics.push(Diagnostic::ResultCountMismatch {
+                    node,
+                    region,
+                    results,
+                    outputs,
+                });
+            }
+        }
+    }
+}
+
+// Tarjan's algorithm for finding the strongly connected components of the dependency graph
+struct Components<'a> {
+    dependencies: &'a HashMap<id::AnyNode, Vec<id::AnyNode>>,
+    // The visitation index and lowest reachable index of each visited node. The lowest index is
+    // set to `usize::MAX` once the node has been assigned a component.
+    index: HashMap<id::AnyNode, (usize, usize)>,
+    stack: Vec<id::AnyNode>,
+    found: Vec<Vec<id::AnyNode>>,
+}
+
+impl Components<'_> {
+    fn visit(&mut self, node: id::AnyNode) -> usize {
+        let index = self.index.len();
+        self.index.insert(node, (index, index));
+        self.stack.push(node);
+
+        let mut low = index;
+        for &to in self.dependencies.get(&node).into_iter().flatten() {
+            let reachable = match self.index.get(&to) {
+                None => self.visit(to),
+                Some(&(_, low)) => low,
+            };
+            low = low.min(reachable);
+        }
+
+        if low == index {
+            let at = self.stack.iter().rposition(|&n| n == node).unwrap();
+            let component = self.stack.split_off(at);
+            for n in &component {
+                self.index.get_mut(n).unwrap().1 = usize::MAX;
+            }
+            self.found.push(component);
+            usize::MAX
+        } else {
+            self.index.get_mut(&node).unwrap().1 = low;
+            low
+        }
+    }
+}
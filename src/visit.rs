@@ -0,0 +1,135 @@
+use super::*;
+
+/// Hooks called while walking the region tree, see [`TranslationUnitContext::walk`].
+///
+/// The [`Walker`] gives access to the context and the nodes and regions enclosing what's being
+/// visited.
+pub trait Visitor {
+    /// Called for each node, before or after its regions depending on the [`Order`]
+    fn visit_node(&mut self, walker: &Walker, node: id::AnyNode) {
+        let _ = (walker, node);
+    }
+
+    /// Called for each node once it and all its regions have been visited
+    fn leave_node(&mut self, walker: &Walker, node: id::AnyNode) {
+        let _ = (walker, node);
+    }
+
+    /// Called for each region, before any of its nodes
+    fn enter_region(&mut self, walker: &Walker, region: id::Region) {
+        let _ = (walker, region);
+    }
+
+    /// Called for each region, after all of its nodes
+    fn leave_region(&mut self, walker: &Walker, region: id::Region) {
+        let _ = (walker, region);
+    }
+}
+
+/// Whether nodes are visited before or after their regions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    PreOrder,
+    PostOrder,
+}
+
+/// A node or region enclosing what's being visited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Node(id::AnyNode),
+    Region(id::Region),
+}
+
+pub struct Walker<'ctx> {
+    pub ctx: &'ctx TranslationUnitContext,
+    order: Order,
+    stack: Vec<Scope>,
+}
+
+impl Walker<'_> {
+    /// The nodes and regions enclosing what's being visited, starting with omega.
+    ///
+    /// Doesn't include the node or region being visited.
+    pub fn stack(&self) -> &[Scope] {
+        &self.stack
+    }
+
+    /// The innermost region enclosing what's being visited
+    pub fn current_region(&self) -> Option<id::Region> {
+        self.stack.iter().rev().find_map(|scope| match scope {
+            Scope::Region(region) => Some(*region),
+            Scope::Node(_) => None,
+        })
+    }
+
+    fn node(&mut self, visitor: &mut impl Visitor, node: id::AnyNode) {
+        if self.order == Order::PreOrder {
+            visitor.visit_node(self, node);
+        }
+
+        self.stack.push(Scope::Node(node));
+        for &region in self.ctx.regions(node) {
+            self.region(visitor, region);
+        }
+        self.stack.pop();
+
+        if self.order == Order::PostOrder {
+            visitor.visit_node(self, node);
+        }
+
+        visitor.leave_node(self, node);
+    }
+
+    fn region(&mut self, visitor: &mut impl Visitor, region: id::Region) {
+        visitor.enter_region(self, region);
+
+        self.stack.push(Scope::Region(region));
+        for node in self.ctx.nodes(region) {
+            self.node(visitor, node);
+        }
+        self.stack.pop();
+
+        visitor.leave_region(self, region);
+    }
+}
+
+impl TranslationUnitContext {
+    /// Walk the whole region tree, starting from the omega node.
+    pub fn walk(&self, order: Order, visitor: &mut impl Visitor) {
+        self.walk_from(self.omega().id, order, visitor)
+    }
+
+    /// Walk a node and the region tree within it.
+    pub fn walk_from(&self, node: id::AnyNode, order: Order, visitor: &mut impl Visitor) {
+        let mut walker = Walker {
+            ctx: self,
+            order,
+            stack: self.enclosing(node),
+        };
+        walker.node(visitor, node);
+    }
+
+    fn enclosing(&self, node: id::AnyNode) -> Vec<Scope> {
+        let omega = self.omega().id;
+        if node == omega {
+            return vec![];
+        }
+
+        let mut stack = vec![];
+        let mut region = self.nodes[node].region;
+        loop {
+            stack.push(Scope::Region(region));
+            match self.regions[region].container_node {
+                Some(node) => {
+                    stack.push(Scope::Node(node));
+                    region = self.nodes[node].region;
+                }
+                None => break,
+            }
+        }
+        stack.push(Scope::Node(omega));
+
+        stack.reverse();
+        stack
+    }
+}
@@ -2,117 +2,130 @@ use super::*;
 use std::fmt;
 use xmlwriter::{Options, XmlWriter};
 
-pub struct XmlCtx<'ctx> {
-    stack: Vec<StackEntry>,
-    ctx: &'ctx TranslationUnitContext,
+pub struct XmlCtx {
+    unit: Option<String>,
     xml: XmlWriter,
 }
 
-enum StackEntry {
-    Unit(String),
-    Node(id::AnyNode),
-    Region(id::Region),
-}
-
-impl<'ctx> XmlCtx<'ctx> {
-    pub fn write_node(&mut self, id: id::AnyNode) {
-        let node = &self.ctx.nodes[id];
+impl Visitor for XmlCtx {
+    fn visit_node(&mut self, walker: &Walker, id: id::AnyNode) {
+        let ctx = walker.ctx;
+        let node = &ctx.nodes[id];
+        let scope = Scope::Node(id);
 
         self.xml.start_element("node");
-        self.stack.push(StackEntry::Node(id));
 
-        self.xml.write_attribute("id", &self.prefixed(""));
-        if let Some(name) = self.ctx.symbols.get(id) {
-            self.xml.write_attribute("name", &name);
+        self.attribute("id", self.prefixed(walker, &[scope], ""));
+        if let Some(name) = ctx.symbols.get(id).filter(|name| !name.is_empty()) {
+            self.attribute("name", name);
+        }
+        self.attribute("type", node.kind.node_type());
+        if let Some(payload) = node.kind.payload() {
+            self.attribute("payload", payload);
         }
-        self.xml.write_attribute("type", node.kind.node_type());
 
-        for i in self.ctx.inputs(id) {
+        for i in ctx.inputs(id) {
             self.xml.start_element("input");
-            self.xml.write_attribute("id", &self.prefixed(i));
+            self.attribute("id", self.prefixed(walker, &[scope], i));
+            if let Some(ty) = ctx.user_type(User::Input(id, i)) {
+                self.attribute("type", ty);
+            }
             self.xml.end_element();
         }
 
-        for o in self.ctx.outputs(id) {
+        for o in ctx.outputs(id) {
             self.xml.start_element("output");
-            self.xml.write_attribute("id", &self.prefixed(o));
-            // self.xml.write_attribute("id", &format_args!("{id}.{o}"));
+            self.attribute("id", self.prefixed(walker, &[scope], o));
+            if let Some(ty) = ctx.origin_type(Origin::Output(id, o)) {
+                self.attribute("type", ty);
+            }
             self.xml.end_element();
         }
+    }
 
-        for region in node.regions.as_slice(&self.ctx.region_id_pool) {
-            self.write_region(*region);
-        }
-
-        self.stack.pop();
+    fn leave_node(&mut self, _: &Walker, _: id::AnyNode) {
         self.xml.end_element();
     }
 
-    pub fn write_region(&mut self, region: id::Region) {
-        self.xml.start_element("region");
-        self.stack.push(StackEntry::Region(region));
+    fn enter_region(&mut self, walker: &Walker, region: id::Region) {
+        let ctx = walker.ctx;
+        let scope = Scope::Region(region);
 
-        // self.xml.write_attribute("id", &self.prefixed(""));
+        self.xml.start_element("region");
 
-        for a in self.ctx.arguments(region) {
+        for a in ctx.arguments(region) {
             self.xml.start_element("argument");
-            self.xml.write_attribute("id", &self.prefixed(a));
+            self.attribute("id", self.prefixed(walker, &[scope], a));
+            if let Some(ty) = ctx.origin_type(Origin::Argument(region, a)) {
+                self.attribute("type", ty);
+            }
             self.xml.end_element();
         }
 
-        for r in self.ctx.results(region) {
+        for r in ctx.results(region) {
             self.xml.start_element("result");
-            self.xml.write_attribute("id", &self.prefixed(r));
+            self.attribute("id", self.prefixed(walker, &[scope], r));
+            if let Some(ty) = ctx.user_type(User::Result(region, r)) {
+                self.attribute("type", ty);
+            }
             self.xml.end_element();
         }
+    }
 
-        for node_id in self.ctx.nodes(region) {
-            self.write_node(node_id);
-        }
+    fn leave_region(&mut self, walker: &Walker, region: id::Region) {
+        let scope = Scope::Region(region);
 
-        for edge in &self.ctx.regions[region].edges {
+        for edge in &walker.ctx.regions[region].edges {
             self.xml.start_element("edge");
-            self.xml.write_attribute(
+            self.attribute(
                 "source",
-                &match edge.origin {
+                match edge.origin {
                     Origin::Output(node, output) => {
-                        self.stack.push(StackEntry::Node(node));
-                        let str = self.prefixed(&output);
-                        self.stack.pop().unwrap();
-                        str
+                        self.prefixed(walker, &[scope, Scope::Node(node)], output)
                     }
-                    Origin::Argument(_, argument) => self.prefixed(&argument),
+                    Origin::Argument(_, argument) => self.prefixed(walker, &[scope], argument),
                 },
             );
-            self.xml.write_attribute(
+            self.attribute(
                 "target",
-                &match edge.user {
+                match edge.user {
                     User::Input(node, input) => {
-                        self.stack.push(StackEntry::Node(node));
-                        let str = self.prefixed(&input);
-                        self.stack.pop().unwrap();
-                        str
+                        self.prefixed(walker, &[scope, Scope::Node(node)], input)
                     }
-                    User::Result(_, result) => self.prefixed(&result),
+                    User::Result(_, result) => self.prefixed(walker, &[scope], result),
                 },
             );
             self.xml.end_element();
         }
 
-        self.stack.pop();
         self.xml.end_element();
     }
+}
+
+impl XmlCtx {
+    // The writer only escapes quotes, so escape the rest of the markup within attribute values
+    fn attribute(&mut self, name: &str, value: impl fmt::Display) {
+        let value = value.to_string().replace('&', "&amp;").replace('<', "&lt;");
+        self.xml.write_attribute(name, &value);
+    }
 
-    fn prefixed(&self, v: impl fmt::Display) -> String {
+    fn prefixed(&self, walker: &Walker, inner: &[Scope], v: impl fmt::Display) -> String {
         let mut buf = String::new();
-        for entry in &self.stack {
-            match entry {
-                StackEntry::Node(id) => match self.ctx.symbols.get(*id) {
-                    Some(sym) => buf.push_str(sym),
-                    None => buf.push_str(&format!("n{}", id.as_u32())),
-                },
-                StackEntry::Region(id) => buf.push_str(&format!("r{}", id.as_u32())),
-                StackEntry::Unit(name) => buf.push_str(&format!("{name}")),
+        if let Some(name) = &self.unit {
+            buf.push_str(name);
+            buf.push('.');
+        }
+        for scope in walker.stack().iter().chain(inner) {
+            match scope {
+                // The node number keeps the ids unique, the symbol only makes them readable
+                Scope::Node(id) => {
+                    buf.push_str(&format!("n{}", id.as_u32()));
+                    if let Some(sym) = walker.ctx.symbols.get(*id).filter(|sym| !sym.is_empty()) {
+                        buf.push(':');
+                        buf.push_str(&sym.replace('.', "_"));
+                    }
+                }
+                Scope::Region(id) => buf.push_str(&format!("r{}", id.as_u32())),
             }
             buf.push('.');
         }
@@ -147,25 +160,331 @@ impl TranslationUnitContext {
     pub fn add_to_xml(&self, name: String, xml: XmlWriter) -> XmlWriter {
         let mut ctx = XmlCtx {
             xml,
-            stack: vec![StackEntry::Unit(name)],
-            ctx: self,
+            unit: Some(name),
         };
-        ctx.write_node(id::AnyNode::from_u32(0));
+        self.walk(Order::PreOrder, &mut ctx);
         ctx.xml
     }
 
     pub fn to_xml(&self) -> String {
         let xml = new_xml();
 
-        let mut ctx = XmlCtx {
-            xml,
-            stack: vec![],
-            ctx: self,
-        };
-        ctx.write_node(id::AnyNode::from_u32(0));
+        let mut ctx = XmlCtx { xml, unit: None };
+        self.walk(Order::PreOrder, &mut ctx);
 
         ctx.xml.end_element();
 
         ctx.xml.end_document()
     }
 }
+
+/// Errors from reading XML, see [`TranslationUnitContext::from_xml`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XmlError {
+    /// The document isn't valid XML
+    Parse(String),
+    /// The element isn't part of the format where it appears
+    UnexpectedElement(String),
+    /// The element lacks an attribute the format requires
+    MissingAttribute {
+        element: String,
+        attribute: &'static str,
+    },
+    /// There's no factory registered for the `type` of a node
+    UnknownKind(String),
+    /// The factory of the kind didn't accept the payload
+    InvalidPayload {
+        kind: String,
+        payload: Option<String>,
+    },
+    /// An edge refers to a port which doesn't exist
+    UnknownPort(String),
+    /// Several ports have the same id
+    DuplicateId(String),
+    /// A region of the node has fewer arguments than the node has inputs
+    MissingArguments(String),
+    /// An edge connects ports outside of the region it's in
+    CrossRegionEdge { source: String, target: String },
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            XmlError::Parse(err) => write!(f, "invalid xml: {err}"),
+            XmlError::UnexpectedElement(name) => write!(f, "unexpected <{name}> element"),
+            XmlError::MissingAttribute { element, attribute } => {
+                write!(
+                    f,
+                    "<{element}> element is missing the {attribute} attribute"
+                )
+            }
+            XmlError::UnknownKind(kind) => write!(f, "no node kind is registered as {kind}"),
+            XmlError::InvalidPayload { kind, payload } => match payload {
+                Some(payload) => write!(f, "{payload:?} is not a valid payload for {kind}"),
+                None => write!(f, "{kind} nodes need a payload"),
+            },
+            XmlError::UnknownPort(id) => write!(f, "there's no port with the id {id}"),
+            XmlError::DuplicateId(id) => write!(f, "several ports have the id {id}"),
+            XmlError::MissingArguments(node) => {
+                write!(f, "{node} has more inputs than its regions have arguments")
+            }
+            XmlError::CrossRegionEdge { source, target } => {
+                write!(f, "the edge {source} → {target} leaves its region")
+            }
+        }
+    }
+}
+
+impl std::error::Error for XmlError {}
+
+struct XmlReader<'a> {
+    kinds: &'a KindRegistry,
+    origins: HashMap<&'a str, Origin>,
+    users: HashMap<&'a str, User>,
+    // Edges may refer to ports of nodes further down, so they're connected once everything is read
+    edges: Vec<(id::Region, &'a str, &'a str)>,
+    recenvs: Vec<id::Node<RecEnv>>,
+}
+
+type Element<'a> = roxmltree::Node<'a, 'a>;
+
+impl<'a> XmlReader<'a> {
+    fn attribute(
+        element: Element<'a>,
+        attribute: &'static str,
+    ) -> std::result::Result<&'a str, XmlError> {
+        element
+            .attribute(attribute)
+            .ok_or_else(|| XmlError::MissingAttribute {
+                element: element.tag_name().name().to_string(),
+                attribute,
+            })
+    }
+
+    fn port_type(&self, element: Element<'a>) -> Option<Type> {
+        let ty = element.attribute("type")?;
+        self.kinds.types.get(ty).cloned()
+    }
+
+    fn add_origin(
+        &mut self,
+        element: Element<'a>,
+        origin: Origin,
+    ) -> std::result::Result<(), XmlError> {
+        let id = Self::attribute(element, "id")?;
+        match self.origins.insert(id, origin) {
+            Some(_) => Err(XmlError::DuplicateId(id.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn add_user(&mut self, element: Element<'a>, user: User) -> std::result::Result<(), XmlError> {
+        let id = Self::attribute(element, "id")?;
+        match self.users.insert(id, user) {
+            Some(_) => Err(XmlError::DuplicateId(id.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn read_region(
+        &mut self,
+        ctx: &mut TranslationUnitContext,
+        element: Element<'a>,
+        region: id::Region,
+    ) -> std::result::Result<(), XmlError> {
+        for child in element.children().filter(Element::is_element) {
+            match child.tag_name().name() {
+                "argument" => {
+                    let r = &mut ctx.regions[region];
+                    let argument = id::Argument::from_u32(r.arguments);
+                    r.arguments += 1;
+                    r.argument_types.push(self.port_type(child));
+                    self.add_origin(child, Origin::Argument(region, argument))?;
+                }
+                "result" => {
+                    let r = &mut ctx.regions[region];
+                    let result = id::Result::from_u32(r.results);
+                    r.results += 1;
+                    r.result_types.push(self.port_type(child));
+                    self.add_user(child, User::Result(region, result))?;
+                }
+                "node" => self.read_node(ctx, child, region)?,
+                "edge" => {
+                    let source = Self::attribute(child, "source")?;
+                    let target = Self::attribute(child, "target")?;
+                    self.edges.push((region, source, target));
+                }
+                name => return Err(XmlError::UnexpectedElement(name.to_string())),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_node(
+        &mut self,
+        ctx: &mut TranslationUnitContext,
+        element: Element<'a>,
+        region: id::Region,
+    ) -> std::result::Result<(), XmlError> {
+        let kind = Self::attribute(element, "type")?;
+        let payload = element.attribute("payload");
+        let factory = self
+            .kinds
+            .kinds
+            .get(kind)
+            .ok_or_else(|| XmlError::UnknownKind(kind.to_string()))?;
+        let created = factory(payload).ok_or_else(|| XmlError::InvalidPayload {
+            kind: kind.to_string(),
+            payload: payload.map(str::to_string),
+        })?;
+
+        let regions: Vec<_> = element
+            .children()
+            .filter(|child| child.has_tag_name("region"))
+            .map(|child| (child, ctx.add_region(0, 0)))
+            .collect();
+
+        let node = ctx.in_region(region, |ctx| {
+            ctx.push_node(created, regions.iter().map(|(_, region)| *region))
+        });
+
+        if let Some(name) = element.attribute("name") {
+            ctx.add_symbol(node, name);
+        }
+
+        for child in element.children().filter(Element::is_element) {
+            match child.tag_name().name() {
+                "input" => {
+                    let n = &mut ctx.nodes[node];
+                    let input = id::Input::from_u32(n.inputs);
+                    n.inputs += 1;
+                    n.input_types.push(self.port_type(child));
+                    self.add_user(child, User::Input(node, input))?;
+                }
+                "output" => {
+                    let n = &mut ctx.nodes[node];
+                    let output = id::Output::from_u32(n.outputs);
+                    n.outputs += 1;
+                    n.output_types.push(self.port_type(child));
+                    self.add_origin(child, Origin::Output(node, output))?;
+                }
+                "region" => {}
+                name => return Err(XmlError::UnexpectedElement(name.to_string())),
+            }
+        }
+
+        for (child, region) in regions {
+            self.read_region(ctx, child, region)?;
+        }
+
+        // Inputs are forwarded as the last arguments of each region
+        if !ctx.has_forwarded_arguments(node) {
+            let id = element.attribute("id").unwrap_or(kind);
+            return Err(XmlError::MissingArguments(id.to_string()));
+        }
+
+        if let Ok(env) = ctx.try_cast::<RecEnv>(node) {
+            self.recenvs.push(env);
+        }
+
+        Ok(())
+    }
+
+    fn connect_edges(self, ctx: &mut TranslationUnitContext) -> std::result::Result<(), XmlError> {
+        for (region, source, target) in self.edges {
+            let origin = *self
+                .origins
+                .get(source)
+                .ok_or_else(|| XmlError::UnknownPort(source.to_string()))?;
+            let user = *self
+                .users
+                .get(target)
+                .ok_or_else(|| XmlError::UnknownPort(target.to_string()))?;
+            let edge = Edge { origin, user };
+            if !ctx.edge_is_in(region, edge) {
+                return Err(XmlError::CrossRegionEdge {
+                    source: source.to_string(),
+                    target: target.to_string(),
+                });
+            }
+            ctx.push_edge(region, edge);
+        }
+
+        for env in self.recenvs {
+            ctx.restore_recenv_lambdas(env);
+        }
+
+        Ok(())
+    }
+}
+
+impl TranslationUnitContext {
+    /// Read a graph written by [`Self::to_xml`] or another tool using the same format.
+    ///
+    /// Only the built-in node kinds can be read, see [`Self::from_xml_with`] for custom kinds.
+    pub fn from_xml(xml: &str) -> std::result::Result<Self, XmlError> {
+        Self::from_xml_with(xml, &KindRegistry::new())
+    }
+
+    /// Read a graph, creating the kinds of nodes through the registry.
+    ///
+    /// The `<rvsdg>` element either contains the omega node or the nodes and edges of the region
+    /// of omega directly.
+    pub fn from_xml_with(xml: &str, kinds: &KindRegistry) -> std::result::Result<Self, XmlError> {
+        let document =
+            roxmltree::Document::parse(xml).map_err(|err| XmlError::Parse(err.to_string()))?;
+
+        let root = document.root_element();
+        if !root.has_tag_name("rvsdg") {
+            return Err(XmlError::UnexpectedElement(
+                root.tag_name().name().to_string(),
+            ));
+        }
+
+        let mut ctx = TranslationUnitContext::new();
+        let omega = ctx.get(ctx.omega()).region;
+
+        let mut reader = XmlReader {
+            kinds,
+            origins: HashMap::new(),
+            users: HashMap::new(),
+            edges: vec![],
+            recenvs: vec![],
+        };
+
+        let is_omega = |child: &Element| {
+            child.has_tag_name("node") && child.attribute("type") == Some("omega")
+        };
+
+        let elements = || root.children().filter(Element::is_element);
+        if elements().any(|child| is_omega(&child) || child.has_tag_name("region")) {
+            let mut regions = vec![];
+            for child in elements() {
+                if is_omega(&child) {
+                    regions.extend(child.children().filter(Element::is_element));
+                } else {
+                    regions.push(child);
+                }
+            }
+
+            // Several regions can't be merged into the region of omega
+            match regions[..] {
+                [] => {}
+                [region] if region.has_tag_name("region") => {
+                    reader.read_region(&mut ctx, region, omega)?
+                }
+                [_, region, ..] | [region] => {
+                    let name = region.tag_name().name().to_string();
+                    return Err(XmlError::UnexpectedElement(name));
+                }
+            }
+        } else {
+            reader.read_region(&mut ctx, root, omega)?;
+        }
+
+        reader.connect_edges(&mut ctx)?;
+
+        Ok(ctx)
+    }
+}